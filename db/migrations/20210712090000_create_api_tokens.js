@@ -0,0 +1,21 @@
+exports.up = async function(knex) {
+  await knex.schema.withSchema('krumnet').createTable('api_tokens', function(table) {
+    table.string('id', 36).defaultTo(knex.raw('uuid_generate_v4()')).notNullable().primary();
+    table.string('user_id', 36).references('id').inTable('krumnet.users').notNullable();
+    table.string('name').notNullable();
+    table.string('token_hash').notNullable();
+    table.specificType('scopes', 'text[]').notNullable();
+    table.timestamp('created_at').defaultTo(knex.fn.now());
+    table.timestamp('expires_at');
+    table.timestamp('last_used_at');
+    table.timestamp('revoked_at');
+    table.unique('id');
+    table.unique('token_hash');
+    table.index('user_id');
+  });
+  await knex.raw('create unique index api_tokens_active_name on krumnet.api_tokens (user_id, name) where revoked_at is null');
+};
+
+exports.down = function(knex) {
+  return knex.schema.withSchema('krumnet').dropTable('api_tokens');
+};
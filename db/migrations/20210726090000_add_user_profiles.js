@@ -0,0 +1,19 @@
+exports.up = async function(knex) {
+  await knex.schema.withSchema('krumnet').alterTable('users', function(table) {
+    table.string('display_name');
+    table.string('avatar_url', 512);
+    table.string('pronouns');
+    table.string('timezone');
+  });
+  await knex.raw('create unique index users_display_name on krumnet.users (lower(display_name)) where display_name is not null');
+};
+
+exports.down = async function(knex) {
+  await knex.raw('drop index krumnet.users_display_name');
+  await knex.schema.withSchema('krumnet').alterTable('users', function(table) {
+    table.dropColumn('display_name');
+    table.dropColumn('avatar_url');
+    table.dropColumn('pronouns');
+    table.dropColumn('timezone');
+  });
+};
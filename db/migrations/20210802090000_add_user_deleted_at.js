@@ -0,0 +1,11 @@
+exports.up = function(knex) {
+  return knex.schema.withSchema('krumnet').alterTable('users', function(table) {
+    table.timestamp('deleted_at');
+  });
+};
+
+exports.down = function(knex) {
+  return knex.schema.withSchema('krumnet').alterTable('users', function(table) {
+    table.dropColumn('deleted_at');
+  });
+};
@@ -0,0 +1,12 @@
+exports.up = async function(knex) {
+  await knex.schema.withSchema('krumnet').alterTable('users', function(table) {
+    table.string('role').notNullable().defaultTo('player');
+  });
+  await knex.raw(`alter table krumnet.users add constraint users_role_check check (role in ('player', 'moderator', 'admin'))`);
+};
+
+exports.down = function(knex) {
+  return knex.schema.withSchema('krumnet').alterTable('users', function(table) {
+    table.dropColumn('role');
+  });
+};
@@ -0,0 +1,33 @@
+exports.up = async function(knex) {
+  await knex.schema.withSchema('krumnet').createTable('jobs', function(table) {
+    table.string('id', 36).notNullable().primary();
+    table.string('queue').notNullable();
+    table.text('payload').notNullable();
+    table.string('state').notNullable().defaultTo('waiting');
+    table.timestamp('run_at').notNullable().defaultTo(knex.fn.now());
+    table.string('worker', 36);
+    table.timestamp('heartbeat_at');
+    table.timestamp('finished_at');
+    table.timestamp('created_at').notNullable().defaultTo(knex.fn.now());
+    table.index(['queue', 'state', 'run_at']);
+    table.index(['queue', 'finished_at']);
+  });
+  await knex.raw(`alter table krumnet.jobs add constraint jobs_state_check check (state in ('waiting', 'scheduled', 'held', 'idle', 'dead'))`);
+  await knex.raw(`
+    create function krumnet.notify_job_waiting() returns trigger as $$
+    begin
+      perform pg_notify('krumnet_jobs', new.queue);
+      return new;
+    end;
+    $$ language plpgsql
+  `);
+  await knex.raw(`
+    create trigger jobs_notify_waiting after insert or update of state on krumnet.jobs
+    for each row when (new.state = 'waiting') execute function krumnet.notify_job_waiting()
+  `);
+};
+
+exports.down = async function(knex) {
+  await knex.schema.withSchema('krumnet').dropTable('jobs');
+  await knex.raw('drop function krumnet.notify_job_waiting');
+};
@@ -0,0 +1,15 @@
+exports.up = function(knex) {
+  return knex.schema.withSchema('krumnet').alterTable('jobs', function(table) {
+    table.string('dedupe_key');
+    table.boolean('rerun').notNullable().defaultTo(false);
+    table.index(['queue', 'dedupe_key']);
+  });
+};
+
+exports.down = function(knex) {
+  return knex.schema.withSchema('krumnet').alterTable('jobs', function(table) {
+    table.dropIndex(['queue', 'dedupe_key']);
+    table.dropColumn('dedupe_key');
+    table.dropColumn('rerun');
+  });
+};
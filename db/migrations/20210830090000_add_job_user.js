@@ -0,0 +1,13 @@
+exports.up = function(knex) {
+  return knex.schema.withSchema('krumnet').alterTable('jobs', function(table) {
+    table.string('user_id');
+    table.index(['queue', 'user_id']);
+  });
+};
+
+exports.down = function(knex) {
+  return knex.schema.withSchema('krumnet').alterTable('jobs', function(table) {
+    table.dropIndex(['queue', 'user_id']);
+    table.dropColumn('user_id');
+  });
+};
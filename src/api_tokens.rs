@@ -0,0 +1,47 @@
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+
+use crate::constants::{API_TOKEN_LENGTH, API_TOKEN_PREFIX};
+
+// Personal api tokens are random strings handed to the user exactly once; only a sha256 digest of
+// the token is ever persisted.
+pub fn generate() -> String {
+  let random = thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(API_TOKEN_LENGTH)
+    .map(char::from)
+    .collect::<String>();
+
+  format!("{}{}", API_TOKEN_PREFIX, random)
+}
+
+pub fn hash(token: &str) -> String {
+  Sha256::digest(token.as_bytes())
+    .iter()
+    .map(|byte| format!("{:02x}", byte))
+    .collect()
+}
+
+pub fn is_api_token(token: &str) -> bool {
+  token.starts_with(API_TOKEN_PREFIX)
+}
+
+#[cfg(test)]
+mod test {
+  use super::{generate, hash, is_api_token};
+
+  #[test]
+  fn generated_tokens_are_recognized() {
+    let token = generate();
+    assert!(is_api_token(&token));
+    assert_ne!(token, generate());
+  }
+
+  #[test]
+  fn hash_is_stable() {
+    assert_eq!(hash("krt_abc"), hash("krt_abc"));
+    assert_ne!(hash("krt_abc"), hash("krt_abd"));
+    assert_eq!(hash("krt_abc").len(), 64);
+  }
+}
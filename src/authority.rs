@@ -1,11 +1,170 @@
-#[derive(Debug, PartialEq)]
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+// Scopes limit what an authenticated request is allowed to do. Sessions created through a login
+// flow carry every scope, while personal api tokens carry only the scopes they were minted with.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+  Read,
+  LobbyPlay,
+  Account,
+}
+
+impl Scope {
+  pub fn parse(value: &str) -> Option<Self> {
+    match value {
+      "read" => Some(Scope::Read),
+      "lobby_play" => Some(Scope::LobbyPlay),
+      "account" => Some(Scope::Account),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Scope::Read => "read",
+      Scope::LobbyPlay => "lobby_play",
+      Scope::Account => "account",
+    }
+  }
+
+  // Whether or not a token holding this scope should be allowed to perform actions requiring the
+  // `required` scope. Playing in lobbies requires reading them, and account access implies both.
+  fn satisfies(&self, required: Scope) -> bool {
+    matches!(
+      (self, required),
+      (Scope::Account, _)
+        | (Scope::LobbyPlay, Scope::LobbyPlay)
+        | (Scope::LobbyPlay, Scope::Read)
+        | (Scope::Read, Scope::Read)
+    )
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Scopes(Vec<Scope>);
+
+impl Scopes {
+  pub fn all() -> Self {
+    Scopes(vec![Scope::Read, Scope::LobbyPlay, Scope::Account])
+  }
+
+  pub fn from_strings(values: &[String]) -> Self {
+    let scopes = values
+      .iter()
+      .filter_map(|value| {
+        let parsed = Scope::parse(value);
+
+        if parsed.is_none() {
+          warn!("ignoring unrecognized scope '{}'", value);
+        }
+
+        parsed
+      })
+      .collect();
+
+    Scopes(scopes)
+  }
+
+  pub fn allows(&self, required: Scope) -> bool {
+    self.0.iter().any(|scope| scope.satisfies(required))
+  }
+
+  pub fn to_strings(&self) -> Vec<String> {
+    self.0.iter().map(|s| String::from(s.as_str())).collect()
+  }
+}
+
+// Roles are stored on the user record and are ordered; any role satisfies the roles beneath it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+  #[default]
+  Player,
+  Moderator,
+  Admin,
+}
+
+impl Role {
+  pub fn parse(value: &str) -> Option<Self> {
+    match value {
+      "player" => Some(Role::Player),
+      "moderator" => Some(Role::Moderator),
+      "admin" => Some(Role::Admin),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Role::Player => "player",
+      Role::Moderator => "moderator",
+      Role::Admin => "admin",
+    }
+  }
+
+  pub fn satisfies(&self, required: Role) -> bool {
+    *self >= required
+  }
+
+  // Unrecognized values are treated as the least privileged role rather than failing the request.
+  pub fn from_stored(value: &str) -> Self {
+    Role::parse(value).unwrap_or_else(|| {
+      warn!("unrecognized role '{}', treating as player", value);
+      Role::Player
+    })
+  }
+}
+
+#[derive(Debug, Default, PartialEq)]
 pub enum Authority {
-  User { id: String, token: String },
+  User {
+    id: String,
+    token: String,
+    scopes: Scopes,
+    role: Role,
+  },
+  #[default]
   None,
 }
 
-impl Default for Authority {
-  fn default() -> Self {
-    Authority::None
+#[cfg(test)]
+mod test {
+  use super::{Role, Scope, Scopes};
+
+  #[test]
+  fn read_only_scopes() {
+    let scopes = Scopes::from_strings(&[String::from("read")]);
+    assert!(scopes.allows(Scope::Read));
+    assert!(!scopes.allows(Scope::LobbyPlay));
+    assert!(!scopes.allows(Scope::Account));
+  }
+
+  #[test]
+  fn lobby_play_implies_read() {
+    let scopes = Scopes::from_strings(&[String::from("lobby_play")]);
+    assert!(scopes.allows(Scope::Read));
+    assert!(scopes.allows(Scope::LobbyPlay));
+    assert!(!scopes.allows(Scope::Account));
+  }
+
+  #[test]
+  fn unknown_scopes_ignored() {
+    let scopes = Scopes::from_strings(&[String::from("admin")]);
+    assert!(!scopes.allows(Scope::Read));
+  }
+
+  #[test]
+  fn all_scopes() {
+    assert!(Scopes::all().allows(Scope::Account));
+  }
+
+  #[test]
+  fn role_ordering() {
+    assert!(Role::Admin.satisfies(Role::Moderator));
+    assert!(Role::Moderator.satisfies(Role::Moderator));
+    assert!(!Role::Player.satisfies(Role::Moderator));
+    assert_eq!(Role::from_stored("superuser"), Role::Player);
   }
 }
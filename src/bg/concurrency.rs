@@ -0,0 +1,135 @@
+use async_std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use crate::bg::registry::Registry;
+use crate::interchange::jobs::Job;
+
+#[derive(Default)]
+struct State {
+  running: HashMap<&'static str, usize>,
+  held: HashSet<String>,
+}
+
+// Decides whether a job may start alongside the jobs already running in this worker, based on the
+// configured per-kind limits and the resources each running job holds, as named by its handler.
+pub struct Limiter {
+  _limits: HashMap<String, usize>,
+  _registry: Arc<Registry>,
+  _state: Mutex<State>,
+}
+
+// Held for as long as a job runs; dropping it makes room for the next job of the same kind and
+// releases the job's resources.
+pub struct Permit {
+  _limiter: Arc<Limiter>,
+  _kind: &'static str,
+  _resources: Vec<String>,
+}
+
+impl Limiter {
+  pub fn new(limits: &HashMap<String, usize>, registry: Arc<Registry>) -> Arc<Self> {
+    Arc::new(Limiter {
+      _limits: limits.clone(),
+      _registry: registry,
+      _state: Mutex::new(State::default()),
+    })
+  }
+
+  // Returns `None` when the job's kind is at its limit or another running job holds one of its
+  // resources.
+  pub fn admit(self: &Arc<Self>, job: &Job) -> Option<Permit> {
+    let kind = job.kind();
+    let resources = self._registry.resources(job);
+    let mut state = self._state.lock().ok()?;
+
+    let running = state.running.get(kind).copied().unwrap_or(0);
+    let limit = self._limits.get(kind).copied().unwrap_or(usize::MAX);
+
+    if running >= limit || resources.iter().any(|r| state.held.contains(r)) {
+      return None;
+    }
+
+    *state.running.entry(kind).or_insert(0) += 1;
+    state.held.extend(resources.iter().cloned());
+
+    Some(Permit {
+      _limiter: self.clone(),
+      _kind: kind,
+      _resources: resources,
+    })
+  }
+}
+
+impl Drop for Permit {
+  fn drop(&mut self) {
+    if let Ok(mut state) = self._limiter._state.lock() {
+      if let Some(running) = state.running.get_mut(self._kind) {
+        *running = running.saturating_sub(1);
+      }
+
+      for resource in &self._resources {
+        state.held.remove(resource);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::Limiter;
+  use crate::bg::registry::Registry;
+  use crate::interchange::jobs::{CheckRoundFulfillment, CreateLobby, Job};
+  use async_std::sync::Arc;
+  use std::collections::HashMap;
+
+  fn fulfillment(round_id: &str) -> Job {
+    Job::CheckRoundFulfillment(CheckRoundFulfillment {
+      round_id: String::from(round_id),
+      result: None,
+    })
+  }
+
+  fn lobby() -> Job {
+    Job::CreateLobby(CreateLobby {
+      creator: String::from("creator"),
+      result: None,
+    })
+  }
+
+  #[test]
+  fn kinds_match_tags() {
+    for job in vec![fulfillment("round"), lobby()] {
+      let serialized = serde_json::to_value(&job).unwrap();
+      assert_eq!(serialized["t"], job.kind());
+    }
+  }
+
+  #[test]
+  fn limits_kinds() {
+    let mut limits = HashMap::new();
+    limits.insert(String::from("create_lobby"), 1);
+    let limiter = Limiter::new(&limits, Arc::new(Registry::default()));
+
+    let first = limiter.admit(&lobby());
+    assert!(first.is_some());
+    assert!(limiter.admit(&lobby()).is_none());
+    assert!(limiter.admit(&fulfillment("round")).is_some());
+
+    drop(first);
+    assert!(limiter.admit(&lobby()).is_some());
+  }
+
+  #[test]
+  fn serializes_shared_resources() {
+    let limiter = Limiter::new(&HashMap::new(), Arc::new(Registry::default()));
+
+    let first = limiter.admit(&fulfillment("round-1"));
+    assert!(first.is_some());
+    assert!(limiter.admit(&fulfillment("round-1")).is_none());
+    assert!(limiter.admit(&fulfillment("round-2")).is_some());
+
+    drop(first);
+    assert!(limiter.admit(&fulfillment("round-1")).is_some());
+  }
+}
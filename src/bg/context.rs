@@ -3,5 +3,5 @@ use async_std::sync::Arc;
 
 pub struct Context {
   pub records: Arc<RecordStore>,
-  pub jobs: Arc<JobStore>,
+  pub jobs: Arc<dyn JobStore>,
 }
@@ -1,19 +1,22 @@
-use log::{debug, info, warn};
+use log::{debug, info};
 use sqlx::query_file;
 
-use crate::interchange::jobs::CleanupGameMembership as CleanupContext;
-use crate::{bg::context::Context, interchange};
-
-fn log_and_serialize<E: std::error::Error>(error: E) -> String {
-  warn!("{}", error);
-  format!("{}", error)
-}
+use crate::bg::outcome::{Failure, Outcome};
+use crate::bg::{registry::JobHandler, retry::RetryPolicy};
+use crate::interchange::http::JobResult;
+use crate::interchange::jobs::{CleanupGameMembership as CleanupContext, Job};
+use crate::{bg::context::Context, interchange, policy};
+use async_trait::async_trait;
 
 async fn round_ids_without_entries(
   context: &Context,
   details: &CleanupContext,
-) -> Result<Vec<String>, String> {
-  let mut conn = context.records.acquire().await.map_err(log_and_serialize)?;
+) -> Result<Vec<String>, Failure> {
+  let mut conn = context
+    .records
+    .acquire()
+    .await
+    .map_err(Failure::retryable)?;
   query_file!(
     "src/bg/handlers/game_memberships/data-store/get-round-ids.sql",
     details.user_id,
@@ -21,11 +24,14 @@ async fn round_ids_without_entries(
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(log_and_serialize)
+  .map_err(Failure::retryable)
   .map(|result| result.into_iter().map(|row| row.round_id).collect())
 }
 
-async fn cleanup_inner(context: &Context, details: &CleanupContext) -> Result<Vec<String>, String> {
+async fn cleanup_inner(
+  context: &Context,
+  details: &CleanupContext,
+) -> Result<Vec<String>, Failure> {
   let round_ids = round_ids_without_entries(context, details).await?;
 
   if round_ids.len() == 0 {
@@ -38,7 +44,11 @@ async fn cleanup_inner(context: &Context, details: &CleanupContext) -> Result<Ve
 
   info!("found rounds w/o entries - {:?}", round_ids);
 
-  let mut conn = context.records.acquire().await.map_err(log_and_serialize)?;
+  let mut conn = context
+    .records
+    .acquire()
+    .await
+    .map_err(Failure::retryable)?;
 
   let round_ids = query_file!(
     "src/bg/handlers/game_memberships/data-store/create-empty-entries-for-game-member.sql",
@@ -48,34 +58,112 @@ async fn cleanup_inner(context: &Context, details: &CleanupContext) -> Result<Ve
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(log_and_serialize)?
+  .map_err(Failure::retryable)?
   .into_iter()
   .map(|row| row.round_id)
   .collect::<Vec<String>>();
 
-  for id in &round_ids {
-    let job_context = interchange::jobs::CheckRoundFulfillment {
-      round_id: id.clone(),
-      result: None,
-    };
-    let job = interchange::jobs::Job::CheckRoundFulfillment(job_context);
+  let jobs = round_ids
+    .iter()
+    .map(|id| {
+      let job_context = interchange::jobs::CheckRoundFulfillment {
+        round_id: id.clone(),
+        result: None,
+      };
+      interchange::jobs::Job::CheckRoundFulfillment(job_context)
+    })
+    .collect::<Vec<_>>();
 
-    info!("queing round completion check job for round {:?}", job);
+  info!(
+    "queing round completion check jobs for rounds {:?}",
+    round_ids
+  );
 
-    context.jobs.queue(&job).await.map_err(log_and_serialize)?;
-  }
+  context
+    .jobs
+    .queue_all(&jobs)
+    .await
+    .map_err(Failure::retryable)?;
 
   Ok(round_ids)
 }
 
-pub async fn cleanup(details: &CleanupContext, context: &Context) -> interchange::jobs::Job {
+pub async fn cleanup(details: &CleanupContext, context: &Context) -> Outcome {
   debug!("cleaning up game member '{}'", details.member_id);
-  interchange::jobs::Job::CleanupGameMembership(interchange::jobs::CleanupGameMembership {
-    result: Some(cleanup_inner(context, details).await),
-    ..details.clone()
+  Outcome::new(cleanup_inner(context, details).await, |result| {
+    interchange::jobs::Job::CleanupGameMembership(interchange::jobs::CleanupGameMembership {
+      result,
+      ..details.clone()
+    })
   })
 }
 
+pub struct CleanupGameMembershipHandler;
+
+#[async_trait]
+impl JobHandler for CleanupGameMembershipHandler {
+  type Payload = CleanupContext;
+  type Output = Vec<String>;
+
+  fn kind(&self) -> &'static str {
+    "cleanup_game_membership"
+  }
+
+  fn payload<'a>(&self, job: &'a Job) -> Option<&'a CleanupContext> {
+    match job {
+      Job::CleanupGameMembership(details) => Some(details),
+      _ => None,
+    }
+  }
+
+  fn output<'a>(&self, details: &'a CleanupContext) -> Option<&'a Result<Vec<String>, String>> {
+    details.result.as_ref()
+  }
+
+  fn reset(&self, details: &CleanupContext) -> Job {
+    Job::CleanupGameMembership(CleanupContext {
+      result: None,
+      ..details.clone()
+    })
+  }
+
+  fn resources(&self, details: &CleanupContext) -> Vec<String> {
+    vec![format!("game:{}", details.game_id)]
+  }
+
+  async fn handle(&self, _id: &str, details: &CleanupContext, context: &Context) -> Outcome {
+    cleanup(details, context).await
+  }
+
+  fn describe(&self, details: &CleanupContext, round_ids: &Vec<String>) -> JobResult {
+    JobResult::GameMembershipCleanup {
+      game_id: details.game_id.clone(),
+      member_id: details.member_id.clone(),
+      round_ids: round_ids.clone(),
+    }
+  }
+
+  fn policy(&self) -> RetryPolicy {
+    RetryPolicy::new(5, 2, 120)
+  }
+
+  fn user(&self, details: &CleanupContext) -> Option<String> {
+    Some(details.user_id.clone())
+  }
+
+  // The rest of the game's players are waiting on the entries filled in for the member.
+  async fn related(
+    &self,
+    details: &CleanupContext,
+    user_id: &str,
+    context: &crate::Context,
+  ) -> std::io::Result<bool> {
+    policy::view_game(context, user_id, &details.game_id)
+      .await
+      .map(|decision| decision.is_ok())
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::{cleanup_inner, round_ids_without_entries};
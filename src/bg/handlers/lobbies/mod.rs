@@ -1,15 +1,16 @@
 use crate::{
+  bg::context::Context,
+  bg::outcome::{Failure, Outcome},
+  bg::registry::JobHandler,
+  bg::retry::RetryPolicy,
+  interchange::http::JobResult,
   interchange::jobs::{CreateGame, CreateLobby, Job},
   names, RecordStore,
 };
-use log::{debug, info, warn};
+use async_trait::async_trait;
+use log::{debug, info};
 use sqlx::query_file;
 
-fn warn_and_stringify<E: std::fmt::Display>(err: E) -> String {
-  warn!("{}", err);
-  format!("{}", err)
-}
-
 #[derive(Debug)]
 struct UserInfo {
   id: String,
@@ -17,15 +18,15 @@ struct UserInfo {
   email: String,
 }
 
-async fn find_user(user_id: &String, records: &RecordStore) -> Result<UserInfo, String> {
-  let mut conn = records.acquire().await.map_err(warn_and_stringify)?;
+async fn find_user(user_id: &String, records: &RecordStore) -> Result<UserInfo, Failure> {
+  let mut conn = records.acquire().await.map_err(Failure::retryable)?;
   query_file!(
     "src/bg/handlers/lobbies/data-store/find-user-by-id.sql",
     user_id
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(warn_and_stringify)?
+  .map_err(Failure::retryable)?
   .into_iter()
   .nth(0)
   .map(|row| {
@@ -35,17 +36,20 @@ async fn find_user(user_id: &String, records: &RecordStore) -> Result<UserInfo,
       email: row.email,
     })
   })
-  .unwrap_or(Err(format!("Unable to find user '{}'", user_id)))
+  .unwrap_or(Err(Failure::permanent(format!(
+    "Unable to find user '{}'",
+    user_id
+  ))))
 }
 
 pub async fn make_lobby(
   records: &RecordStore,
-  job_id: &String,
+  job_id: &str,
   creator: &String,
-) -> std::result::Result<String, String> {
+) -> Result<String, Failure> {
   let name = names::get();
   let user = find_user(creator, records).await?;
-  let mut conn = records.acquire().await.map_err(warn_and_stringify)?;
+  let mut conn = records.acquire().await.map_err(Failure::retryable)?;
 
   query_file!(
     "src/bg/handlers/lobbies/data-store/create-lobby.sql",
@@ -55,28 +59,33 @@ pub async fn make_lobby(
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(warn_and_stringify)?
+  .map_err(Failure::retryable)?
   .into_iter()
   .nth(0)
   .map(|row| Ok(row.lobby_id))
-  .unwrap_or(Err(format!("Lobby creation failed for job '{}'", job_id)))
+  .unwrap_or(Err(Failure::permanent(format!(
+    "Lobby creation failed for job '{}'",
+    job_id
+  ))))
 }
 
-pub async fn create_lobby(job_id: &String, details: &CreateLobby, records: &RecordStore) -> Job {
+pub async fn create_lobby(job_id: &str, details: &CreateLobby, records: &RecordStore) -> Outcome {
   let result = make_lobby(records, job_id, &details.creator).await;
 
-  Job::CreateLobby(CreateLobby {
-    result: Some(result),
-    creator: details.creator.clone(),
+  Outcome::new(result, |result| {
+    Job::CreateLobby(CreateLobby {
+      result,
+      creator: details.creator.clone(),
+    })
   })
 }
 
 pub async fn make_game(
   records: &RecordStore,
-  job_id: &String,
+  job_id: &str,
   creator: &String,
   lobby_id: &String,
-) -> std::result::Result<String, String> {
+) -> Result<String, Failure> {
   let user = find_user(creator, records).await?;
   debug!(
     "creating game for lobby '{}' (user '{}')",
@@ -84,7 +93,7 @@ pub async fn make_game(
   );
   let name = names::get();
 
-  let mut conn = records.acquire().await.map_err(warn_and_stringify)?;
+  let mut conn = records.acquire().await.map_err(Failure::retryable)?;
 
   let gid = query_file!(
     "src/bg/handlers/lobbies/data-store/create-game-for-lobby.sql",
@@ -94,11 +103,11 @@ pub async fn make_game(
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(warn_and_stringify)?
+  .map_err(Failure::retryable)?
   .into_iter()
   .nth(0)
   .map(|row| row.game_id)
-  .ok_or(format!("Unable to create game for lobby '{}'", lobby_id))?;
+  .ok_or_else(|| Failure::permanent(format!("Unable to create game for lobby '{}'", lobby_id)))?;
 
   info!("game '{}' created for lobby '{}'", gid, lobby_id);
 
@@ -109,17 +118,115 @@ pub async fn make_game(
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(warn_and_stringify)?;
+  .map_err(Failure::retryable)?;
 
   Ok(String::from(gid))
 }
 
-pub async fn create_game(job_id: &String, details: &CreateGame, records: &RecordStore) -> Job {
+pub async fn create_game(job_id: &str, details: &CreateGame, records: &RecordStore) -> Outcome {
   let result = make_game(records, job_id, &details.creator, &details.lobby_id).await;
 
-  Job::CreateGame(CreateGame {
-    result: Some(result),
-    lobby_id: details.lobby_id.clone(),
-    creator: details.creator.clone(),
+  Outcome::new(result, |result| {
+    Job::CreateGame(CreateGame {
+      result,
+      lobby_id: details.lobby_id.clone(),
+      creator: details.creator.clone(),
+    })
   })
 }
+
+pub struct CreateLobbyHandler;
+
+#[async_trait]
+impl JobHandler for CreateLobbyHandler {
+  type Payload = CreateLobby;
+  type Output = String;
+
+  fn kind(&self) -> &'static str {
+    "create_lobby"
+  }
+
+  fn payload<'a>(&self, job: &'a Job) -> Option<&'a CreateLobby> {
+    match job {
+      Job::CreateLobby(details) => Some(details),
+      _ => None,
+    }
+  }
+
+  fn output<'a>(&self, details: &'a CreateLobby) -> Option<&'a Result<String, String>> {
+    details.result.as_ref()
+  }
+
+  fn reset(&self, details: &CreateLobby) -> Job {
+    Job::CreateLobby(CreateLobby {
+      result: None,
+      ..details.clone()
+    })
+  }
+
+  async fn handle(&self, id: &str, details: &CreateLobby, context: &Context) -> Outcome {
+    create_lobby(id, details, &context.records).await
+  }
+
+  fn describe(&self, _details: &CreateLobby, id: &String) -> JobResult {
+    JobResult::NewLobby { id: id.clone() }
+  }
+
+  fn policy(&self) -> RetryPolicy {
+    RetryPolicy::new(3, 1, 30)
+  }
+
+  fn user(&self, details: &CreateLobby) -> Option<String> {
+    Some(details.creator.clone())
+  }
+}
+
+pub struct CreateGameHandler;
+
+#[async_trait]
+impl JobHandler for CreateGameHandler {
+  type Payload = CreateGame;
+  type Output = String;
+
+  fn kind(&self) -> &'static str {
+    "create_game"
+  }
+
+  fn payload<'a>(&self, job: &'a Job) -> Option<&'a CreateGame> {
+    match job {
+      Job::CreateGame(details) => Some(details),
+      _ => None,
+    }
+  }
+
+  fn output<'a>(&self, details: &'a CreateGame) -> Option<&'a Result<String, String>> {
+    details.result.as_ref()
+  }
+
+  fn reset(&self, details: &CreateGame) -> Job {
+    Job::CreateGame(CreateGame {
+      result: None,
+      ..details.clone()
+    })
+  }
+
+  fn resources(&self, details: &CreateGame) -> Vec<String> {
+    vec![format!("lobby:{}", details.lobby_id)]
+  }
+
+  async fn handle(&self, id: &str, details: &CreateGame, context: &Context) -> Outcome {
+    create_game(id, details, &context.records).await
+  }
+
+  fn describe(&self, _details: &CreateGame, id: &String) -> JobResult {
+    JobResult::NewGame { id: id.clone() }
+  }
+
+  fn policy(&self) -> RetryPolicy {
+    RetryPolicy::new(3, 1, 30)
+  }
+
+  fn user(&self, details: &CreateGame) -> Option<String> {
+    Some(details.creator.clone())
+  }
+}
@@ -1,21 +1,30 @@
-use crate::{bg::context::Context, interchange};
+use crate::{
+  bg::context::Context,
+  bg::outcome::{Failure, Outcome},
+  bg::registry::JobHandler,
+  bg::retry::RetryPolicy,
+  interchange,
+  interchange::http::JobResult,
+  interchange::jobs::{CleanupLobbyMembership, Job},
+  policy,
+};
+use async_trait::async_trait;
 use log::{debug, info, warn};
 use sqlx::query_file;
 
-fn stringify_error<E: std::fmt::Display>(e: E) -> String {
-  warn!("lobby membership error - {}", e);
-  format!("{}", e)
-}
-
-async fn count_lobby_members(lobby_id: &String, context: &Context) -> Result<i64, String> {
-  let mut conn = context.records.acquire().await.map_err(stringify_error)?;
+async fn count_lobby_members(lobby_id: &String, context: &Context) -> Result<i64, Failure> {
+  let mut conn = context
+    .records
+    .acquire()
+    .await
+    .map_err(Failure::retryable)?;
   query_file!(
     "src/bg/handlers/lobby_memberships/data-store/count-remaining-lobby-members.sql",
     lobby_id
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(stringify_error)?
+  .map_err(Failure::retryable)?
   .into_iter()
   .nth(0)
   .and_then(|row| {
@@ -25,10 +34,10 @@ async fn count_lobby_members(lobby_id: &String, context: &Context) -> Result<i64
     );
     row.count.map(Ok)
   })
-  .unwrap_or(Err(format!(
+  .unwrap_or(Err(Failure::permanent(format!(
     "Unable to find matching lobbies for '{}'",
     lobby_id
-  )))
+  ))))
 }
 
 struct LeftGame {
@@ -38,15 +47,22 @@ struct LeftGame {
   game_member_id: String,
 }
 
-async fn leave_games(lobby_member_id: &String, context: &Context) -> Result<Vec<LeftGame>, String> {
-  let mut conn = context.records.acquire().await.map_err(stringify_error)?;
+async fn leave_games(
+  lobby_member_id: &String,
+  context: &Context,
+) -> Result<Vec<LeftGame>, Failure> {
+  let mut conn = context
+    .records
+    .acquire()
+    .await
+    .map_err(Failure::retryable)?;
   query_file!(
     "src/bg/handlers/lobby_memberships/data-store/leave-game-member-by-lobby-member.sql",
     lobby_member_id
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(stringify_error)?
+  .map_err(Failure::retryable)?
   .into_iter()
   .map(|row| {
     Ok(LeftGame {
@@ -56,50 +72,59 @@ async fn leave_games(lobby_member_id: &String, context: &Context) -> Result<Vec<
       game_member_id: row.game_member_id,
     })
   })
-  .collect::<Result<Vec<LeftGame>, String>>()
+  .collect::<Result<Vec<LeftGame>, Failure>>()
 }
 
-async fn close_lobby(lobby_id: &String, context: &Context) -> Result<String, String> {
-  let mut conn = context.records.acquire().await.map_err(stringify_error)?;
+async fn close_lobby(lobby_id: &String, context: &Context) -> Result<String, Failure> {
+  let mut conn = context
+    .records
+    .acquire()
+    .await
+    .map_err(Failure::retryable)?;
   query_file!(
     "src/bg/handlers/lobby_memberships/data-store/close-lobby.sql",
     lobby_id
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(stringify_error)?
+  .map_err(Failure::retryable)?
   .into_iter()
   .nth(0)
   .map(|row| Ok(row.id))
-  .unwrap_or(Err(format!(
+  .unwrap_or(Err(Failure::permanent(format!(
     "Unable to set closed timestamp for lobby '{}'",
     lobby_id
-  )))
+  ))))
 }
 
 pub async fn cleanup_inner(
   member_id: &String,
   lobby_id: &String,
   context: &Context,
-) -> Result<String, String> {
+) -> Result<String, Failure> {
   let count = count_lobby_members(lobby_id, context).await?;
   let left_games = leave_games(member_id, context).await?;
 
-  let jobs = left_games.iter().map(|g| {
-    let details = interchange::jobs::CleanupGameMembership {
-      user_id: g.user_id.clone(),
-      game_id: g.game_id.clone(),
-      member_id: g.game_member_id.clone(),
-      lobby_id: g.lobby_id.clone(),
-      result: None,
-    };
-    interchange::jobs::Job::CleanupGameMembership(details)
-  });
+  let jobs = left_games
+    .iter()
+    .map(|g| {
+      let details = interchange::jobs::CleanupGameMembership {
+        user_id: g.user_id.clone(),
+        game_id: g.game_id.clone(),
+        member_id: g.game_member_id.clone(),
+        lobby_id: g.lobby_id.clone(),
+        result: None,
+      };
+      interchange::jobs::Job::CleanupGameMembership(details)
+    })
+    .collect::<Vec<_>>();
 
-  for job in jobs {
-    debug!("adding game membership cleanup job to queue - {:?}", job);
-    context.jobs.queue(&job).await.map_err(stringify_error)?;
-  }
+  debug!("adding game membership cleanup jobs to queue - {:?}", jobs);
+  context
+    .jobs
+    .queue_all(&jobs)
+    .await
+    .map_err(Failure::retryable)?;
 
   if count == 0 {
     info!(
@@ -114,10 +139,10 @@ pub async fn cleanup_inner(
 }
 
 pub async fn cleanup(
-  job_id: &String,
+  job_id: &str,
   details: &interchange::jobs::CleanupLobbyMembership,
   context: &Context,
-) -> interchange::jobs::Job {
+) -> Outcome {
   debug!("job '{}', cleanup '{}'", job_id, details.member_id);
 
   let res = cleanup_inner(&details.member_id, &details.lobby_id, context)
@@ -127,9 +152,73 @@ pub async fn cleanup(
       err
     });
 
-  interchange::jobs::Job::CleanupLobbyMembership(interchange::jobs::CleanupLobbyMembership {
-    member_id: details.member_id.clone(),
-    lobby_id: details.lobby_id.clone(),
-    result: Some(res),
+  Outcome::new(res, |result| {
+    interchange::jobs::Job::CleanupLobbyMembership(interchange::jobs::CleanupLobbyMembership {
+      member_id: details.member_id.clone(),
+      lobby_id: details.lobby_id.clone(),
+      result,
+    })
   })
 }
+
+pub struct CleanupLobbyMembershipHandler;
+
+#[async_trait]
+impl JobHandler for CleanupLobbyMembershipHandler {
+  type Payload = CleanupLobbyMembership;
+  type Output = String;
+
+  fn kind(&self) -> &'static str {
+    "cleanup_lobby_membership"
+  }
+
+  fn payload<'a>(&self, job: &'a Job) -> Option<&'a CleanupLobbyMembership> {
+    match job {
+      Job::CleanupLobbyMembership(details) => Some(details),
+      _ => None,
+    }
+  }
+
+  fn output<'a>(&self, details: &'a CleanupLobbyMembership) -> Option<&'a Result<String, String>> {
+    details.result.as_ref()
+  }
+
+  fn reset(&self, details: &CleanupLobbyMembership) -> Job {
+    Job::CleanupLobbyMembership(CleanupLobbyMembership {
+      result: None,
+      ..details.clone()
+    })
+  }
+
+  fn resources(&self, details: &CleanupLobbyMembership) -> Vec<String> {
+    vec![format!("lobby:{}", details.lobby_id)]
+  }
+
+  async fn handle(&self, id: &str, details: &CleanupLobbyMembership, context: &Context) -> Outcome {
+    cleanup(id, details, context).await
+  }
+
+  // The cleanup saves the id of the lobby when it was closed, and "done" when members remain.
+  fn describe(&self, details: &CleanupLobbyMembership, output: &String) -> JobResult {
+    JobResult::LobbyMembershipCleanup {
+      lobby_id: details.lobby_id.clone(),
+      member_id: details.member_id.clone(),
+      lobby_closed: output == &details.lobby_id,
+    }
+  }
+
+  fn policy(&self) -> RetryPolicy {
+    RetryPolicy::new(5, 2, 120)
+  }
+
+  async fn related(
+    &self,
+    details: &CleanupLobbyMembership,
+    user_id: &str,
+    context: &crate::Context,
+  ) -> std::io::Result<bool> {
+    policy::view_lobby(context, user_id, &details.lobby_id)
+      .await
+      .map(|decision| decision.is_ok())
+  }
+}
@@ -2,3 +2,4 @@ pub mod game_memberships;
 pub mod lobbies;
 pub mod lobby_memberships;
 pub mod rounds;
+pub mod users;
@@ -2,5 +2,5 @@ mod round_completion;
 mod round_fulfillment;
 mod utils;
 
-pub use round_completion::check_round_completion;
-pub use round_fulfillment::check_round_fulfillment;
+pub use round_completion::{check_round_completion, RoundCompletionHandler};
+pub use round_fulfillment::{check_round_fulfillment, RoundFulfillmentHandler};
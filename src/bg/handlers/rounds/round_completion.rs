@@ -1,57 +1,62 @@
 use super::utils::{count_entries, count_members};
-use crate::{bg::context::Context, interchange};
-use log::{debug, info, warn};
+use crate::{
+  bg::context::Context,
+  bg::outcome::{Failure, Outcome},
+  bg::registry::JobHandler,
+  bg::retry::RetryPolicy,
+  interchange,
+  interchange::http::JobResult,
+  interchange::jobs::{CheckRoundCompletion, CheckRoundCompletionResult, Job},
+  policy,
+};
+use async_trait::async_trait;
+use log::{debug, info};
 use sqlx::query_file;
 
-fn warn_and_stringify<E: std::error::Error>(e: E) -> String {
-  warn!("{}", e);
-  format!("{}", e)
-}
-
-async fn count_remaining_rounds(game_id: &String, context: &Context) -> Result<i64, String> {
+async fn count_remaining_rounds(game_id: &String, context: &Context) -> Result<i64, Failure> {
   let mut conn = context
     .records
     .acquire()
     .await
-    .map_err(warn_and_stringify)?;
+    .map_err(Failure::retryable)?;
   query_file!(
     "src/bg/handlers/rounds/data-store/count-remaining-rounds.sql",
     game_id
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(warn_and_stringify)?
+  .map_err(Failure::retryable)?
   .into_iter()
   .nth(0)
   .and_then(|row| row.remaining_rounds.map(Ok))
-  .unwrap_or(Err(format!("Unable to count remaining rows")))
+  .unwrap_or(Err(Failure::permanent("Unable to count remaining rows")))
 }
 
-async fn count_votes(round_id: &String, context: &Context) -> Result<i64, String> {
+async fn count_votes(round_id: &String, context: &Context) -> Result<i64, Failure> {
   let mut conn = context
     .records
     .acquire()
     .await
-    .map_err(warn_and_stringify)?;
+    .map_err(Failure::retryable)?;
   query_file!(
     "src/bg/handlers/rounds/data-store/count-votes-for-round.sql",
     round_id
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(warn_and_stringify)?
+  .map_err(Failure::retryable)?
   .into_iter()
   .nth(0)
   .and_then(|row| row.count.map(Ok))
-  .unwrap_or(Err(format!("Unable to count remaining rows")))
+  .unwrap_or(Err(Failure::permanent("Unable to count remaining rows")))
 }
 
-async fn mark_round_completed(context: &Context, round_id: &String) -> Result<(), String> {
+async fn mark_round_completed(context: &Context, round_id: &String) -> Result<(), Failure> {
   let mut conn = context
     .records
     .acquire()
     .await
-    .map_err(warn_and_stringify)?;
+    .map_err(Failure::retryable)?;
 
   query_file!(
     "src/bg/handlers/rounds/data-store/complete-round.sql",
@@ -59,19 +64,19 @@ async fn mark_round_completed(context: &Context, round_id: &String) -> Result<()
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(warn_and_stringify)?;
+  .map_err(Failure::retryable)?;
   Ok(())
 }
 
 async fn create_round_placements(
   context: &Context,
   round_id: &String,
-) -> Result<Vec<String>, String> {
+) -> Result<Vec<String>, Failure> {
   let mut conn = context
     .records
     .acquire()
     .await
-    .map_err(warn_and_stringify)?;
+    .map_err(Failure::retryable)?;
 
   let placement_ids = query_file!(
     "src/bg/handlers/rounds/data-store/create-round-placements.sql",
@@ -79,7 +84,7 @@ async fn create_round_placements(
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(warn_and_stringify)?
+  .map_err(Failure::retryable)?
   .into_iter()
   .map(|row| row.id)
   .collect();
@@ -90,12 +95,12 @@ async fn create_round_placements(
 async fn create_game_placements(
   context: &Context,
   game_id: &String,
-) -> Result<Vec<String>, String> {
+) -> Result<Vec<String>, Failure> {
   let mut conn = context
     .records
     .acquire()
     .await
-    .map_err(warn_and_stringify)?;
+    .map_err(Failure::retryable)?;
 
   let placement_ids = query_file!(
     "src/bg/handlers/rounds/data-store/create-game-placements.sql",
@@ -103,7 +108,7 @@ async fn create_game_placements(
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(warn_and_stringify)?
+  .map_err(Failure::retryable)?
   .into_iter()
   .map(|row| row.id)
   .collect();
@@ -111,12 +116,12 @@ async fn create_game_placements(
   Ok(placement_ids)
 }
 
-async fn mark_game_ended(context: &Context, game_id: &String) -> Result<(), String> {
+async fn mark_game_ended(context: &Context, game_id: &String) -> Result<(), Failure> {
   let mut conn = context
     .records
     .acquire()
     .await
-    .map_err(warn_and_stringify)?;
+    .map_err(Failure::retryable)?;
 
   query_file!(
     "src/bg/handlers/rounds/data-store/mark-game-ended.sql",
@@ -124,7 +129,7 @@ async fn mark_game_ended(context: &Context, game_id: &String) -> Result<(), Stri
   )
   .execute(&mut conn)
   .await
-  .map_err(warn_and_stringify)?;
+  .map_err(Failure::retryable)?;
 
   Ok(())
 }
@@ -132,7 +137,7 @@ async fn mark_game_ended(context: &Context, game_id: &String) -> Result<(), Stri
 async fn round_completion_result(
   context: &Context,
   details: &interchange::jobs::CheckRoundCompletion,
-) -> Result<interchange::jobs::CheckRoundCompletionResult, String> {
+) -> Result<interchange::jobs::CheckRoundCompletionResult, Failure> {
   info!("checking round completion for round '{}'", details.round_id);
   let member_count = count_members(&context, &details.round_id).await?;
   let vote_count = count_votes(&details.round_id, context).await?;
@@ -176,20 +181,94 @@ async fn round_completion_result(
 pub async fn check_round_completion(
   details: &interchange::jobs::CheckRoundCompletion,
   context: &Context,
-) -> interchange::jobs::Job {
-  let result = Some(round_completion_result(context, details).await);
+) -> Outcome {
+  let result = round_completion_result(context, details).await;
+
+  Outcome::new(result, |result| {
+    interchange::jobs::Job::CheckRoundCompletion(interchange::jobs::CheckRoundCompletion {
+      result,
+      ..details.clone()
+    })
+  })
+}
 
-  let completion = interchange::jobs::CheckRoundCompletion {
-    result,
-    ..details.clone()
-  };
+pub struct RoundCompletionHandler;
+
+#[async_trait]
+impl JobHandler for RoundCompletionHandler {
+  type Payload = CheckRoundCompletion;
+  type Output = CheckRoundCompletionResult;
+
+  fn kind(&self) -> &'static str {
+    "check_round_completion"
+  }
+
+  fn payload<'a>(&self, job: &'a Job) -> Option<&'a CheckRoundCompletion> {
+    match job {
+      Job::CheckRoundCompletion(details) => Some(details),
+      _ => None,
+    }
+  }
+
+  fn output<'a>(
+    &self,
+    details: &'a CheckRoundCompletion,
+  ) -> Option<&'a Result<CheckRoundCompletionResult, String>> {
+    details.result.as_ref()
+  }
+
+  fn reset(&self, details: &CheckRoundCompletion) -> Job {
+    Job::CheckRoundCompletion(CheckRoundCompletion {
+      result: None,
+      ..details.clone()
+    })
+  }
+
+  fn resources(&self, details: &CheckRoundCompletion) -> Vec<String> {
+    vec![
+      format!("round:{}", details.round_id),
+      format!("game:{}", details.game_id),
+    ]
+  }
+
+  fn dedupe_key(&self, details: &CheckRoundCompletion) -> Option<String> {
+    Some(format!("round_completion:{}", details.round_id))
+  }
+
+  async fn handle(&self, _id: &str, details: &CheckRoundCompletion, context: &Context) -> Outcome {
+    check_round_completion(details, context).await
+  }
 
-  interchange::jobs::Job::CheckRoundCompletion(completion)
+  fn describe(
+    &self,
+    details: &CheckRoundCompletion,
+    result: &CheckRoundCompletionResult,
+  ) -> JobResult {
+    JobResult::RoundCompletion {
+      round_id: details.round_id.clone(),
+      result: result.clone(),
+    }
+  }
+
+  fn policy(&self) -> RetryPolicy {
+    RetryPolicy::new(5, 1, 60)
+  }
+
+  async fn related(
+    &self,
+    details: &CheckRoundCompletion,
+    user_id: &str,
+    context: &crate::Context,
+  ) -> std::io::Result<bool> {
+    policy::view_round(context, user_id, &details.round_id)
+      .await
+      .map(|decision| decision.is_ok())
+  }
 }
 
 #[cfg(test)]
 mod test {
-  use super::round_completion_result;
+  use super::{round_completion_result, Failure};
   use crate::{
     bg::handlers::rounds::check_round_fulfillment,
     bg::{context::Context, test_helpers},
@@ -341,7 +420,7 @@ mod test {
     context: &Context,
     test_context: &TestContext,
     round_id: &String,
-  ) -> Result<interchange::jobs::CheckRoundCompletionResult, String> {
+  ) -> Result<interchange::jobs::CheckRoundCompletionResult, Failure> {
     let job = job_from_test_context(&test_context, &round_id);
     round_completion_result(&context, &job).await
   }
@@ -1,14 +1,22 @@
 use super::utils::{count_entries, count_members};
-use crate::{bg::context::Context, interchange};
-use log::{debug, info, warn};
+use crate::{
+  bg::context::Context,
+  bg::outcome::{Failure, Outcome},
+  bg::registry::JobHandler,
+  bg::retry::RetryPolicy,
+  interchange,
+  interchange::http::JobResult,
+  interchange::jobs::{CheckRoundFulfillment, CheckRoundFulfillmentResult, Job},
+  policy,
+};
+use async_trait::async_trait;
+use log::{debug, info};
 use sqlx::query_file;
 
-fn warn_and_stringify<E: std::error::Error>(e: E) -> String {
-  warn!("{}", e);
-  format!("{}", e)
-}
-
-async fn round_fulfillment_result(context: &Context, round_id: &String) -> Result<u8, String> {
+async fn round_fulfillment_result(
+  context: &Context,
+  round_id: &String,
+) -> Result<CheckRoundFulfillmentResult, Failure> {
   info!("checking fulfillment of round '{}'", round_id);
   let entry_count = count_entries(context, round_id).await?;
   let member_count = count_members(context, round_id).await?;
@@ -22,14 +30,14 @@ async fn round_fulfillment_result(context: &Context, round_id: &String) -> Resul
 
   if diff != 0 {
     debug!("round has {} entries remaining, moving on", diff);
-    return Ok(diff);
+    return Ok(CheckRoundFulfillmentResult::Remaining(diff));
   }
 
   let mut conn = context
     .records
     .acquire()
     .await
-    .map_err(warn_and_stringify)?;
+    .map_err(Failure::retryable)?;
 
   let (position, game_id) = query_file!(
     "src/bg/handlers/rounds/data-store/fulfill-round.sql",
@@ -37,44 +45,128 @@ async fn round_fulfillment_result(context: &Context, round_id: &String) -> Resul
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(warn_and_stringify)?
+  .map_err(Failure::retryable)?
   .into_iter()
   .nth(0)
   .map(|row| (row.position, row.game_id))
-  .ok_or(format!("Unable to mark round '{}' fulfilled", round_id))?;
+  .ok_or_else(|| Failure::permanent(format!("Unable to mark round '{}' fulfilled", round_id)))?;
 
   debug!("updated position {} in game '{}'", position, game_id);
 
-  query_file!(
+  // The last round of a game has no next round to start.
+  let next_round_id = query_file!(
     "src/bg/handlers/rounds/data-store/start-next.sql",
     game_id,
     position
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(warn_and_stringify)?;
+  .map_err(Failure::retryable)?
+  .into_iter()
+  .nth(0)
+  .map(|row| row.id);
 
-  Ok(diff)
+  Ok(CheckRoundFulfillmentResult::Fulfilled { next_round_id })
 }
 
 pub async fn check_round_fulfillment(
   details: &interchange::jobs::CheckRoundFulfillment,
   context: &Context,
-) -> interchange::jobs::Job {
-  let result = Some(round_fulfillment_result(context, &details.round_id).await);
+) -> Outcome {
+  let result = round_fulfillment_result(context, &details.round_id).await;
+
+  Outcome::new(result, |result| {
+    interchange::jobs::Job::CheckRoundFulfillment(interchange::jobs::CheckRoundFulfillment {
+      round_id: details.round_id.clone(),
+      result,
+    })
+  })
+}
+
+pub struct RoundFulfillmentHandler;
+
+#[async_trait]
+impl JobHandler for RoundFulfillmentHandler {
+  type Payload = CheckRoundFulfillment;
+  type Output = CheckRoundFulfillmentResult;
+
+  fn kind(&self) -> &'static str {
+    "check_round_fulfillment"
+  }
+
+  fn payload<'a>(&self, job: &'a Job) -> Option<&'a CheckRoundFulfillment> {
+    match job {
+      Job::CheckRoundFulfillment(details) => Some(details),
+      _ => None,
+    }
+  }
+
+  fn output<'a>(
+    &self,
+    details: &'a CheckRoundFulfillment,
+  ) -> Option<&'a Result<CheckRoundFulfillmentResult, String>> {
+    details.result.as_ref()
+  }
+
+  fn reset(&self, details: &CheckRoundFulfillment) -> Job {
+    Job::CheckRoundFulfillment(CheckRoundFulfillment {
+      result: None,
+      ..details.clone()
+    })
+  }
+
+  fn resources(&self, details: &CheckRoundFulfillment) -> Vec<String> {
+    vec![format!("round:{}", details.round_id)]
+  }
+
+  fn dedupe_key(&self, details: &CheckRoundFulfillment) -> Option<String> {
+    Some(format!("round_fulfillment:{}", details.round_id))
+  }
+
+  async fn handle(&self, _id: &str, details: &CheckRoundFulfillment, context: &Context) -> Outcome {
+    check_round_fulfillment(details, context).await
+  }
+
+  // Results saved before the next round was kept only have the count, which was 0 once fulfilled.
+  fn describe(
+    &self,
+    details: &CheckRoundFulfillment,
+    result: &CheckRoundFulfillmentResult,
+  ) -> JobResult {
+    let (remaining, next_round_id) = match result {
+      CheckRoundFulfillmentResult::Remaining(remaining) => (*remaining, None),
+      CheckRoundFulfillmentResult::Fulfilled { next_round_id } => (0, next_round_id.clone()),
+    };
+
+    JobResult::RoundFulfillment {
+      round_id: details.round_id.clone(),
+      remaining,
+      next_round_id,
+    }
+  }
 
-  let details = interchange::jobs::CheckRoundFulfillment {
-    round_id: details.round_id.clone(),
-    result,
-  };
+  // Round checks are cheap and players are waiting on them, so they are retried quickly.
+  fn policy(&self) -> RetryPolicy {
+    RetryPolicy::new(5, 1, 60)
+  }
 
-  interchange::jobs::Job::CheckRoundFulfillment(details)
+  async fn related(
+    &self,
+    details: &CheckRoundFulfillment,
+    user_id: &str,
+    context: &crate::Context,
+  ) -> std::io::Result<bool> {
+    policy::view_round(context, user_id, &details.round_id)
+      .await
+      .map(|decision| decision.is_ok())
+  }
 }
 
 #[cfg(test)]
 mod test {
   use super::{count_entries, round_fulfillment_result};
   use crate::bg::{context::Context, test_helpers};
+  use crate::interchange::jobs::CheckRoundFulfillmentResult;
   use async_std::task::block_on;
   use sqlx::query;
 
@@ -205,7 +297,7 @@ mod test {
       assert_eq!(is_round_fulfilled(&context, &round_id).await, false);
       let result = round_fulfillment_result(&context, &round_id).await;
       assert_eq!(result.is_ok(), true);
-      assert_eq!(result.unwrap(), 1);
+      assert_eq!(result.unwrap(), CheckRoundFulfillmentResult::Remaining(1));
       assert_eq!(is_round_fulfilled(&context, &round_id).await, false);
       cleanup_test_context(&context, &test_context).await;
     });
@@ -222,7 +314,12 @@ mod test {
       create_round_entry(&context, &test_context, &round_id).await;
 
       let result = round_fulfillment_result(&context, &round_id).await;
-      assert_eq!(result.is_ok(), true);
+      assert_eq!(
+        result.unwrap(),
+        CheckRoundFulfillmentResult::Fulfilled {
+          next_round_id: Some(next_round_id.clone())
+        }
+      );
       assert_eq!(is_round_fulfilled(&context, &round_id).await, true);
       assert_eq!(is_round_started(&context, &next_round_id).await, true);
       cleanup_test_context(&context, &test_context).await;
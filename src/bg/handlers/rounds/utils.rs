@@ -1,18 +1,13 @@
 use crate::bg::context::Context;
-use log::warn;
+use crate::bg::outcome::Failure;
 use sqlx::query_file;
 
-fn warn_and_stringify<E: std::error::Error>(e: E) -> String {
-  warn!("{}", e);
-  format!("{}", e)
-}
-
-pub async fn count_entries(context: &Context, round_id: &String) -> Result<i64, String> {
+pub async fn count_entries(context: &Context, round_id: &String) -> Result<i64, Failure> {
   let mut conn = context
     .records
     .acquire()
     .await
-    .map_err(warn_and_stringify)?;
+    .map_err(Failure::retryable)?;
 
   let result = query_file!(
     "src/bg/handlers/rounds/data-store/count-entries-for-round.sql",
@@ -20,20 +15,20 @@ pub async fn count_entries(context: &Context, round_id: &String) -> Result<i64,
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(warn_and_stringify)?;
+  .map_err(Failure::retryable)?;
 
   result
     .into_iter()
     .nth(0)
     .and_then(|row| row.entry_count)
-    .ok_or(format!("Unable to count entries for round '{}'", round_id))
+    .ok_or_else(|| Failure::permanent(format!("Unable to count entries for round '{}'", round_id)))
 }
-pub async fn count_members(context: &Context, round_id: &String) -> Result<i64, String> {
+pub async fn count_members(context: &Context, round_id: &String) -> Result<i64, Failure> {
   let mut conn = context
     .records
     .acquire()
     .await
-    .map_err(warn_and_stringify)?;
+    .map_err(Failure::retryable)?;
 
   query_file!(
     "src/bg/handlers/rounds/data-store/count-members-for-round.sql",
@@ -41,17 +36,17 @@ pub async fn count_members(context: &Context, round_id: &String) -> Result<i64,
   )
   .fetch_all(&mut conn)
   .await
-  .map_err(warn_and_stringify)?
+  .map_err(Failure::retryable)?
   .into_iter()
   .nth(0)
   .and_then(|row| row.member_count)
-  .ok_or(format!("Unable to count members for round '{}'", round_id))
+  .ok_or_else(|| Failure::permanent(format!("Unable to count members for round '{}'", round_id)))
 }
 
 #[cfg(test)]
 mod tests {
   use super::count_members;
-  use crate::bg::{context::Context, test_helpers};
+  use crate::bg::{context::Context, outcome::Failure, test_helpers};
   use async_std::task::block_on;
   use sqlx::query;
 
@@ -101,7 +96,7 @@ mod tests {
       assert_eq!(result.is_err(), true);
       assert_eq!(
         result.unwrap_err(),
-        String::from("Unable to count members for round 'bogus'")
+        Failure::permanent("Unable to count members for round 'bogus'")
       );
     });
   }
@@ -0,0 +1,23 @@
+with identities as (
+  delete from krumnet.identities where user_id = $1 returning id
+), api_tokens as (
+  delete from krumnet.api_tokens where user_id = $1 returning id
+), entries as (
+  update krumnet.game_round_entries set entry = null where user_id = $1 returning id
+) update
+  krumnet.users as users
+set
+  name = $2,
+  default_email = users.id || '@' || $3,
+  display_name = null,
+  avatar_url = null,
+  pronouns = null,
+  timezone = null,
+  deleted_at = now()
+where
+  users.id = $1
+and
+  users.deleted_at is null
+returning
+  users.id as id,
+  (select count(*) from entries) as "entries!";
@@ -0,0 +1,6 @@
+delete from
+  krumnet.users as users
+where
+  users.id = $1
+returning
+  users.id;
@@ -0,0 +1,64 @@
+select
+  json_build_object(
+    'user', (
+      select row_to_json(u) from (
+        select
+          users.id, users.name, users.default_email as email, users.display_name,
+          users.avatar_url, users.pronouns, users.timezone, users.created_at
+        from krumnet.users as users
+        where users.id = $1
+      ) as u
+    ),
+    'identities', coalesce((
+      select json_agg(i) from (
+        select identities.provider, identities.email, identities.name, identities.created_at
+        from krumnet.identities as identities
+        where identities.user_id = $1
+      ) as i
+    ), '[]'::json),
+    'lobbies', coalesce((
+      select json_agg(l) from (
+        select lobbies.id, lobbies.name, members.joined_at, members.left_at
+        from krumnet.lobby_memberships as members
+        inner join krumnet.lobbies as lobbies on lobbies.id = members.lobby_id
+        where members.user_id = $1
+      ) as l
+    ), '[]'::json),
+    'games', coalesce((
+      select json_agg(g) from (
+        select games.id, games.name, games.lobby_id, games.created_at, games.ended_at, members.left_at
+        from krumnet.game_memberships as members
+        inner join krumnet.games as games on games.id = members.game_id
+        where members.user_id = $1
+      ) as g
+    ), '[]'::json),
+    'entries', coalesce((
+      select json_agg(e) from (
+        select entries.id, entries.game_id, entries.round_id, rounds.prompt, entries.entry, entries.created_at
+        from krumnet.game_round_entries as entries
+        inner join krumnet.game_rounds as rounds on rounds.id = entries.round_id
+        where entries.user_id = $1
+      ) as e
+    ), '[]'::json),
+    'votes', coalesce((
+      select json_agg(v) from (
+        select votes.id, votes.game_id, votes.round_id, votes.entry_id, votes.created_at
+        from krumnet.game_round_entry_votes as votes
+        where votes.user_id = $1
+      ) as v
+    ), '[]'::json),
+    'placements', coalesce((
+      select json_agg(p) from (
+        select results.game_id, results.place, results.vote_count
+        from krumnet.game_member_placement_results as results
+        where results.user_id = $1
+      ) as p
+    ), '[]'::json),
+    'round_placements', coalesce((
+      select json_agg(r) from (
+        select results.game_id, results.round_id, results.place, results.vote_count
+        from krumnet.game_member_round_placement_results as results
+        where results.user_id = $1
+      ) as r
+    ), '[]'::json)
+  )::text as "archive!";
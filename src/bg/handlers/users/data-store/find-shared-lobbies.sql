@@ -0,0 +1,12 @@
+select
+  source.lobby_id
+from
+  krumnet.lobby_memberships as source
+inner join
+  krumnet.lobby_memberships as target
+on
+  target.lobby_id = source.lobby_id
+where
+  source.user_id = $1
+and
+  target.user_id = $2;
@@ -0,0 +1,11 @@
+update
+  krumnet.lobby_memberships as memberships
+set
+  left_at = now()
+where
+  memberships.user_id = $1
+and
+  memberships.left_at is null
+returning
+  memberships.id       as member_id,
+  memberships.lobby_id as lobby_id;
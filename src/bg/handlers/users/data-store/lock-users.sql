@@ -0,0 +1,9 @@
+select
+  users.id
+from
+  krumnet.users as users
+where
+  users.id = $1
+or
+  users.id = $2
+for update;
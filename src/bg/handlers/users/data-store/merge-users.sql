@@ -0,0 +1,37 @@
+with identities as (
+  update krumnet.identities set user_id = $2 where user_id = $1 returning id
+), api_tokens as (
+  update krumnet.api_tokens set user_id = $2 where user_id = $1 returning id
+), lobby_memberships as (
+  update krumnet.lobby_memberships
+  set
+    user_id = case when user_id = $1 then $2 else user_id end,
+    invited_by = case when invited_by = $1 then $2 else invited_by end
+  where
+    user_id = $1 or invited_by = $1
+  returning id
+), game_memberships as (
+  update krumnet.game_memberships set user_id = $2 where user_id = $1 returning id
+), entries as (
+  update krumnet.game_round_entries set user_id = $2 where user_id = $1 returning id
+), votes as (
+  update krumnet.game_round_entry_votes set user_id = $2 where user_id = $1 returning id
+), round_results as (
+  update krumnet.game_member_round_placement_results set user_id = $2 where user_id = $1 returning id
+), game_results as (
+  update krumnet.game_member_placement_results set user_id = $2 where user_id = $1 returning id
+), prompts as (
+  update krumnet.prompts set created_by = $2 where created_by = $1 returning id
+) select
+  count(*) as "moved!"
+from (
+  select id from identities
+  union all select id from api_tokens
+  union all select id from lobby_memberships
+  union all select id from game_memberships
+  union all select id from entries
+  union all select id from votes
+  union all select id from round_results
+  union all select id from game_results
+  union all select id from prompts
+) as moved;
@@ -0,0 +1,512 @@
+use async_trait::async_trait;
+use log::{debug, info};
+use sqlx::{query_file, Connection};
+
+use crate::bg::context::Context;
+use crate::bg::outcome::{Failure, Outcome};
+use crate::bg::{registry::JobHandler, retry::RetryPolicy};
+use crate::constants::{DELETED_USER_EMAIL_DOMAIN, DELETED_USER_NAME};
+use crate::interchange::http::JobResult;
+use crate::interchange::jobs::{
+  CleanupLobbyMembership, DeleteUser, ExportUserData, Job, MergeUsers,
+};
+use crate::jobs;
+
+// Moves everything owned by the source user onto the target user inside a single transaction. Lobby
+// memberships are unique per user, so users that have both joined the same lobby cannot be merged.
+async fn merge_users(
+  context: &Context,
+  source: &String,
+  target: &String,
+) -> Result<String, Failure> {
+  if source == target {
+    return Err(Failure::permanent(format!(
+      "Unable to merge user '{}' into itself",
+      source
+    )));
+  }
+
+  let mut conn = context
+    .records
+    .acquire()
+    .await
+    .map_err(Failure::retryable)?;
+  let mut tx = conn.begin().await.map_err(Failure::retryable)?;
+
+  let found = query_file!(
+    "src/bg/handlers/users/data-store/lock-users.sql",
+    source,
+    target
+  )
+  .fetch_all(&mut tx)
+  .await
+  .map_err(Failure::retryable)?;
+
+  if found.len() != 2 {
+    return Err(Failure::permanent(format!(
+      "Unable to find users '{}' and '{}'",
+      source, target
+    )));
+  }
+
+  let shared = query_file!(
+    "src/bg/handlers/users/data-store/find-shared-lobbies.sql",
+    source,
+    target
+  )
+  .fetch_all(&mut tx)
+  .await
+  .map_err(Failure::retryable)?
+  .into_iter()
+  .map(|row| row.lobby_id)
+  .collect::<Vec<String>>();
+
+  if !shared.is_empty() {
+    return Err(Failure::permanent(format!(
+      "Users '{}' and '{}' share lobbies {:?}",
+      source, target, shared
+    )));
+  }
+
+  let moved = query_file!(
+    "src/bg/handlers/users/data-store/merge-users.sql",
+    source,
+    target
+  )
+  .fetch_one(&mut tx)
+  .await
+  .map_err(Failure::retryable)?
+  .moved;
+
+  query_file!("src/bg/handlers/users/data-store/delete-user.sql", source)
+    .fetch_all(&mut tx)
+    .await
+    .map_err(Failure::retryable)?;
+
+  tx.commit().await.map_err(Failure::retryable)?;
+
+  info!(
+    "merged user '{}' into '{}' ({} records moved)",
+    source, target, moved
+  );
+
+  Ok(target.clone())
+}
+
+pub async fn merge(details: &MergeUsers, context: &Context) -> Outcome {
+  let result = merge_users(context, &details.source, &details.target).await;
+
+  Outcome::new(result, |result| {
+    Job::MergeUsers(MergeUsers {
+      source: details.source.clone(),
+      target: details.target.clone(),
+      result,
+    })
+  })
+}
+
+async fn export_user_data(
+  context: &Context,
+  user_id: &String,
+) -> Result<serde_json::Value, Failure> {
+  let mut conn = context
+    .records
+    .acquire()
+    .await
+    .map_err(Failure::retryable)?;
+
+  let archive = query_file!(
+    "src/bg/handlers/users/data-store/export-user-data.sql",
+    user_id
+  )
+  .fetch_one(&mut conn)
+  .await
+  .map_err(Failure::retryable)?
+  .archive;
+
+  let parsed = serde_json::from_str::<serde_json::Value>(&archive)
+    .map_err(|e| Failure::permanent(format!("Invalid archive for user '{}' - {}", user_id, e)))?;
+
+  if parsed["user"].is_null() {
+    return Err(Failure::permanent(format!(
+      "Unable to find user '{}'",
+      user_id
+    )));
+  }
+
+  info!("exported data archive for user '{}'", user_id);
+  Ok(parsed)
+}
+
+pub async fn export(details: &ExportUserData, context: &Context) -> Outcome {
+  let result = export_user_data(context, &details.user_id).await;
+
+  Outcome::new(result, |result| {
+    Job::ExportUserData(ExportUserData {
+      user_id: details.user_id.clone(),
+      result,
+    })
+  })
+}
+
+// Leaving lobbies goes through the same cleanup job as an explicit leave so that any rounds still
+// waiting on the user are filled. Once out of every lobby, the user record itself is anonymized. The
+// leaves and the anonymizing share a transaction that the cleanup jobs are queued within, where the
+// job store allows it, so a failure part way leaves the user as they were for the retry.
+async fn delete_user(context: &Context, user_id: &String) -> Result<String, Failure> {
+  let mut conn = context
+    .records
+    .acquire()
+    .await
+    .map_err(Failure::retryable)?;
+
+  let mut tx = conn.begin().await.map_err(Failure::retryable)?;
+
+  let memberships = query_file!(
+    "src/bg/handlers/users/data-store/leave-lobbies.sql",
+    user_id
+  )
+  .fetch_all(&mut tx)
+  .await
+  .map_err(Failure::retryable)?;
+
+  let jobs = memberships
+    .into_iter()
+    .map(|row| {
+      debug!("user '{}' left lobby '{}'", user_id, row.lobby_id);
+      Job::CleanupLobbyMembership(CleanupLobbyMembership {
+        member_id: row.member_id,
+        lobby_id: row.lobby_id,
+        result: None,
+      })
+    })
+    .collect::<Vec<_>>();
+
+  let entries = query_file!(
+    "src/bg/handlers/users/data-store/anonymize-user.sql",
+    user_id,
+    DELETED_USER_NAME,
+    DELETED_USER_EMAIL_DOMAIN
+  )
+  .fetch_all(&mut tx)
+  .await
+  .map_err(Failure::retryable)?
+  .into_iter()
+  .nth(0)
+  .map(|row| row.entries)
+  .ok_or_else(|| Failure::permanent(format!("Unable to find active user '{}'", user_id)))?;
+
+  jobs::commit_queued_all(context.jobs.as_ref(), tx, &jobs)
+    .await
+    .map_err(Failure::retryable)?;
+
+  info!("deleted user '{}' ({} entries cleared)", user_id, entries);
+  Ok(user_id.clone())
+}
+
+pub async fn delete(details: &DeleteUser, context: &Context) -> Outcome {
+  let result = delete_user(context, &details.user_id).await;
+
+  Outcome::new(result, |result| {
+    Job::DeleteUser(DeleteUser {
+      user_id: details.user_id.clone(),
+      result,
+    })
+  })
+}
+
+pub struct MergeUsersHandler;
+
+#[async_trait]
+impl JobHandler for MergeUsersHandler {
+  type Payload = MergeUsers;
+  type Output = String;
+
+  fn kind(&self) -> &'static str {
+    "merge_users"
+  }
+
+  fn payload<'a>(&self, job: &'a Job) -> Option<&'a MergeUsers> {
+    match job {
+      Job::MergeUsers(details) => Some(details),
+      _ => None,
+    }
+  }
+
+  fn output<'a>(&self, details: &'a MergeUsers) -> Option<&'a Result<String, String>> {
+    details.result.as_ref()
+  }
+
+  fn reset(&self, details: &MergeUsers) -> Job {
+    Job::MergeUsers(MergeUsers {
+      result: None,
+      ..details.clone()
+    })
+  }
+
+  fn resources(&self, details: &MergeUsers) -> Vec<String> {
+    vec![
+      format!("user:{}", details.source),
+      format!("user:{}", details.target),
+    ]
+  }
+
+  async fn handle(&self, _id: &str, details: &MergeUsers, context: &Context) -> Outcome {
+    merge(details, context).await
+  }
+
+  fn describe(&self, _details: &MergeUsers, id: &String) -> JobResult {
+    JobResult::MergedUser { id: id.clone() }
+  }
+
+  // Account level jobs touch a lot of rows and are given more room between attempts.
+  fn policy(&self) -> RetryPolicy {
+    RetryPolicy::new(5, 5, 300)
+  }
+
+  fn user(&self, details: &MergeUsers) -> Option<String> {
+    Some(details.target.clone())
+  }
+}
+
+pub struct ExportUserDataHandler;
+
+#[async_trait]
+impl JobHandler for ExportUserDataHandler {
+  type Payload = ExportUserData;
+  type Output = serde_json::Value;
+
+  fn kind(&self) -> &'static str {
+    "export_user_data"
+  }
+
+  fn payload<'a>(&self, job: &'a Job) -> Option<&'a ExportUserData> {
+    match job {
+      Job::ExportUserData(details) => Some(details),
+      _ => None,
+    }
+  }
+
+  fn output<'a>(
+    &self,
+    details: &'a ExportUserData,
+  ) -> Option<&'a Result<serde_json::Value, String>> {
+    details.result.as_ref()
+  }
+
+  fn reset(&self, details: &ExportUserData) -> Job {
+    Job::ExportUserData(ExportUserData {
+      result: None,
+      ..details.clone()
+    })
+  }
+
+  fn resources(&self, details: &ExportUserData) -> Vec<String> {
+    vec![format!("user:{}", details.user_id)]
+  }
+
+  async fn handle(&self, _id: &str, details: &ExportUserData, context: &Context) -> Outcome {
+    export(details, context).await
+  }
+
+  fn describe(&self, _details: &ExportUserData, archive: &serde_json::Value) -> JobResult {
+    JobResult::UserExport {
+      archive: archive.clone(),
+    }
+  }
+
+  fn policy(&self) -> RetryPolicy {
+    RetryPolicy::new(3, 5, 60)
+  }
+
+  fn user(&self, details: &ExportUserData) -> Option<String> {
+    Some(details.user_id.clone())
+  }
+}
+
+pub struct DeleteUserHandler;
+
+#[async_trait]
+impl JobHandler for DeleteUserHandler {
+  type Payload = DeleteUser;
+  type Output = String;
+
+  fn kind(&self) -> &'static str {
+    "delete_user"
+  }
+
+  fn payload<'a>(&self, job: &'a Job) -> Option<&'a DeleteUser> {
+    match job {
+      Job::DeleteUser(details) => Some(details),
+      _ => None,
+    }
+  }
+
+  fn output<'a>(&self, details: &'a DeleteUser) -> Option<&'a Result<String, String>> {
+    details.result.as_ref()
+  }
+
+  fn reset(&self, details: &DeleteUser) -> Job {
+    Job::DeleteUser(DeleteUser {
+      result: None,
+      ..details.clone()
+    })
+  }
+
+  fn resources(&self, details: &DeleteUser) -> Vec<String> {
+    vec![format!("user:{}", details.user_id)]
+  }
+
+  async fn handle(&self, _id: &str, details: &DeleteUser, context: &Context) -> Outcome {
+    delete(details, context).await
+  }
+
+  fn describe(&self, _details: &DeleteUser, _output: &String) -> JobResult {
+    JobResult::Nothing
+  }
+
+  fn policy(&self) -> RetryPolicy {
+    RetryPolicy::new(5, 5, 300)
+  }
+
+  fn user(&self, details: &DeleteUser) -> Option<String> {
+    Some(details.user_id.clone())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{delete_user, export_user_data, merge_users};
+  use crate::bg::{context::Context, test_helpers};
+  use async_std::task::block_on;
+  use sqlx::query;
+
+  async fn lobby_owner(context: &Context, lobby_id: &String) -> String {
+    let mut conn = context.records.acquire().await.expect("unable to connect");
+    query!(
+      "select members.user_id from krumnet.lobby_memberships as members where members.lobby_id = $1",
+      lobby_id
+    )
+    .fetch_one(&mut conn)
+    .await
+    .expect("unable to find membership")
+    .user_id
+  }
+
+  async fn user_exists(context: &Context, user_id: &String) -> bool {
+    let mut conn = context.records.acquire().await.expect("unable to connect");
+    query!(
+      "select users.id from krumnet.users as users where users.id = $1",
+      user_id
+    )
+    .fetch_all(&mut conn)
+    .await
+    .expect("unable to query")
+    .len()
+      == 1
+  }
+
+  #[test]
+  fn merge_moves_memberships() {
+    block_on(async {
+      let (context, target) =
+        test_helpers::get_test_context_with_user("bg.users.merge.target").await;
+      let source = test_helpers::make_user(&context, "bg.users.merge.source").await;
+      let lobby_id = test_helpers::make_lobby(&context, &source).await;
+
+      let result = merge_users(&context, &source, &target).await;
+      assert_eq!(result, Ok(target.clone()));
+      assert_eq!(lobby_owner(&context, &lobby_id).await, target);
+      assert!(!user_exists(&context, &source).await);
+
+      test_helpers::cleanup_lobby(&context, &lobby_id).await;
+      test_helpers::cleanup_user(&context, &target).await;
+    });
+  }
+
+  #[test]
+  fn merge_rejects_shared_lobbies() {
+    block_on(async {
+      let (context, target) =
+        test_helpers::get_test_context_with_user("bg.users.shared.target").await;
+      let source = test_helpers::make_user(&context, "bg.users.shared.source").await;
+      let lobby_id = test_helpers::make_lobby(&context, &target).await;
+      let mut conn = context.records.acquire().await.expect("unable to connect");
+      query!(
+        "insert into krumnet.lobby_memberships (user_id, lobby_id) values ($1, $2)",
+        source,
+        lobby_id
+      )
+      .execute(&mut conn)
+      .await
+      .expect("unable to join lobby");
+
+      let result = merge_users(&context, &source, &target).await;
+      assert!(result.is_err());
+      assert!(user_exists(&context, &source).await);
+
+      test_helpers::cleanup_lobby(&context, &lobby_id).await;
+      test_helpers::cleanup_user(&context, &source).await;
+      test_helpers::cleanup_user(&context, &target).await;
+    });
+  }
+
+  #[test]
+  fn merge_rejects_self() {
+    block_on(async {
+      let context = test_helpers::get_test_context().await;
+      let id = String::from("bogus");
+      assert!(merge_users(&context, &id, &id).await.is_err());
+    });
+  }
+
+  #[test]
+  fn export_includes_lobbies() {
+    block_on(async {
+      let (context, user_id) = test_helpers::get_test_context_with_user("bg.users.export").await;
+      let lobby_id = test_helpers::make_lobby(&context, &user_id).await;
+
+      let archive = export_user_data(&context, &user_id).await.unwrap();
+      assert_eq!(archive["user"]["id"], user_id.as_str());
+      assert_eq!(archive["lobbies"][0]["id"], lobby_id.as_str());
+      assert_eq!(archive["entries"].as_array().map(|e| e.len()), Some(0));
+
+      test_helpers::cleanup_lobby(&context, &lobby_id).await;
+      test_helpers::cleanup_user(&context, &user_id).await;
+    });
+  }
+
+  #[test]
+  fn delete_anonymizes_user() {
+    block_on(async {
+      let (context, user_id) = test_helpers::get_test_context_with_user("bg.users.delete").await;
+      let lobby_id = test_helpers::make_lobby(&context, &user_id).await;
+
+      assert_eq!(delete_user(&context, &user_id).await, Ok(user_id.clone()));
+      assert!(delete_user(&context, &user_id).await.is_err());
+
+      let mut conn = context.records.acquire().await.expect("unable to connect");
+      let row = query!(
+        "select users.name, users.deleted_at from krumnet.users as users where users.id = $1",
+        user_id
+      )
+      .fetch_one(&mut conn)
+      .await
+      .expect("unable to find user");
+      assert_eq!(row.name, "deleted user");
+      assert!(row.deleted_at.is_some());
+
+      let left = query!(
+        "select members.left_at from krumnet.lobby_memberships as members where members.lobby_id = $1",
+        lobby_id
+      )
+      .fetch_one(&mut conn)
+      .await
+      .expect("unable to find membership");
+      assert!(left.left_at.is_some());
+
+      test_helpers::cleanup_lobby(&context, &lobby_id).await;
+      test_helpers::cleanup_user(&context, &user_id).await;
+    });
+  }
+}
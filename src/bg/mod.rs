@@ -1,5 +1,9 @@
+pub mod concurrency;
 pub mod context;
 pub mod handlers;
+pub mod outcome;
+pub mod registry;
+pub mod retry;
 
 #[cfg(test)]
 pub mod test_helpers {
@@ -9,7 +13,8 @@ pub mod test_helpers {
       handlers::lobbies::{make_game as create_game, make_lobby as create_lobby},
     },
     configuration::test_helpers::load_test_config,
-    JobStore, RecordStore,
+    jobs::MemoryJobStore,
+    RecordStore,
   };
   use async_std::sync::Arc;
   use sqlx::query;
@@ -131,13 +136,10 @@ pub mod test_helpers {
       .await
       .expect("unable to open record store");
 
-    let jobs = JobStore::open(&config)
-      .await
-      .expect("unable to open job store");
-
+    // Handler tests only need somewhere to queue follow up jobs, which is kept in memory.
     Context {
       records: Arc::new(records),
-      jobs: Arc::new(jobs),
+      jobs: Arc::new(MemoryJobStore::new()),
     }
   }
 
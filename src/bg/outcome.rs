@@ -0,0 +1,66 @@
+use log::warn;
+use std::fmt;
+
+use crate::interchange::jobs::Job;
+
+// Why a job handler gave up. Errors from the record or job store are usually temporary and worth
+// another attempt, while problems with the job itself will fail the same way every time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+  Retryable(String),
+  Permanent(String),
+}
+
+impl Failure {
+  pub fn retryable<E: fmt::Display>(error: E) -> Self {
+    warn!("retryable job error - {}", error);
+    Failure::Retryable(format!("{}", error))
+  }
+
+  pub fn permanent<S: Into<String>>(message: S) -> Self {
+    Failure::Permanent(message.into())
+  }
+
+  pub fn is_retryable(&self) -> bool {
+    match self {
+      Failure::Retryable(_) => true,
+      Failure::Permanent(_) => false,
+    }
+  }
+}
+
+impl fmt::Display for Failure {
+  fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Failure::Retryable(message) | Failure::Permanent(message) => write!(formatter, "{}", message),
+    }
+  }
+}
+
+impl From<Failure> for String {
+  fn from(failure: Failure) -> String {
+    format!("{}", failure)
+  }
+}
+
+// The job as it should be saved once its handler has run, along with how it failed, if it did.
+#[derive(Debug)]
+pub struct Outcome {
+  pub job: Job,
+  pub failure: Option<Failure>,
+}
+
+impl Outcome {
+  // Builds the outcome of a handler from its result, using `build` to put the result on the job.
+  pub fn new<T, F>(result: Result<T, Failure>, build: F) -> Self
+  where
+    F: FnOnce(Option<Result<T, String>>) -> Job,
+  {
+    let failure = result.as_ref().err().cloned();
+
+    Outcome {
+      job: build(Some(result.map_err(String::from))),
+      failure,
+    }
+  }
+}
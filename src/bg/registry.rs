@@ -0,0 +1,547 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+
+use crate::bg::{
+  context::Context,
+  handlers::{game_memberships, lobbies, lobby_memberships, rounds, users},
+  outcome::{Failure, Outcome},
+  retry::RetryPolicy,
+};
+use crate::interchange::http::{JobHandle, JobResult, WrappedJobResult};
+use crate::interchange::jobs::{Job, QueuedJob};
+
+// Everything the worker and the web api need to know about one kind of job. The job's details are
+// still stored in a `Job` variant so that its serialized `t`/`c` form stays the same; the handler
+// only needs to know how to find them.
+#[async_trait]
+pub trait JobHandler: Send + Sync + 'static {
+  // The details stored in the job's `Job` variant.
+  type Payload: Send + Sync;
+
+  // What a successful run of the job saves as its result.
+  type Output: Send + Sync;
+
+  // The tag the job is serialized with, matching `Job::kind`.
+  fn kind(&self) -> &'static str;
+
+  // Returns the job's details when the job is of this handler's kind.
+  fn payload<'a>(&self, job: &'a Job) -> Option<&'a Self::Payload>;
+
+  // Returns the result saved on the details, if the job has run.
+  fn output<'a>(&self, payload: &'a Self::Payload) -> Option<&'a Result<Self::Output, String>>;
+
+  // Returns the job again with any previous result cleared, so that it can be worked again.
+  fn reset(&self, payload: &Self::Payload) -> Job;
+
+  async fn handle(&self, id: &str, payload: &Self::Payload, context: &Context) -> Outcome;
+
+  // How the output of a successful run is shown to clients polling for the job.
+  fn describe(&self, payload: &Self::Payload, output: &Self::Output) -> JobResult;
+
+  fn policy(&self) -> RetryPolicy;
+
+  // The records the job reads and writes in ways that are unsafe to interleave with another job
+  // touching the same records, e.g two fulfillment checks both starting the next round.
+  fn resources(&self, _payload: &Self::Payload) -> Vec<String> {
+    vec![]
+  }
+
+  // Jobs that only check on the state of their records gain nothing from being queued twice; every
+  // vote or entry in a round queues the same check. Jobs sharing a key are coalesced when queued.
+  fn dedupe_key(&self, _payload: &Self::Payload) -> Option<String> {
+    None
+  }
+
+  // The user allowed to see the job and its result. Jobs without one are only shown to users that
+  // are `related` to them, when looked up by id.
+  fn user(&self, _payload: &Self::Payload) -> Option<String> {
+    None
+  }
+
+  // Whether someone other than the job's user may see it, e.g the players of the round a check ran
+  // for. Unlike `user`, this may need to look at the records, so it is only asked when a job is
+  // looked up by id.
+  async fn related(
+    &self,
+    _payload: &Self::Payload,
+    _user_id: &str,
+    _context: &crate::Context,
+  ) -> std::io::Result<bool> {
+    Ok(false)
+  }
+
+  fn result(&self, payload: &Self::Payload) -> Option<WrappedJobResult> {
+    self.output(payload).map(|result| match result {
+      Ok(output) => WrappedJobResult::Success(self.describe(payload, output)),
+      Err(e) => WrappedJobResult::Failure(e.clone()),
+    })
+  }
+}
+
+// Handlers are stored without their payload and output types so that handlers of every kind can
+// live in the same registry.
+#[async_trait]
+trait Entry: Send + Sync {
+  async fn execute(&self, job: &QueuedJob, context: &Context) -> Outcome;
+  fn policy(&self) -> RetryPolicy;
+  fn reset(&self, job: &Job) -> Option<Job>;
+  fn resources(&self, job: &Job) -> Vec<String>;
+  fn dedupe_key(&self, job: &Job) -> Option<String>;
+  fn user(&self, job: &Job) -> Option<String>;
+  async fn related(
+    &self,
+    job: &Job,
+    user_id: &str,
+    context: &crate::Context,
+  ) -> std::io::Result<bool>;
+  fn result(&self, job: &Job) -> Option<WrappedJobResult>;
+}
+
+struct Registered<H>(H);
+
+#[async_trait]
+impl<H: JobHandler> Entry for Registered<H> {
+  async fn execute(&self, job: &QueuedJob, context: &Context) -> Outcome {
+    match self.0.payload(&job.job) {
+      Some(payload) => self.0.handle(&job.id, payload, context).await,
+      None => unhandled(job),
+    }
+  }
+
+  fn policy(&self) -> RetryPolicy {
+    self.0.policy()
+  }
+
+  fn reset(&self, job: &Job) -> Option<Job> {
+    self.0.payload(job).map(|payload| self.0.reset(payload))
+  }
+
+  fn resources(&self, job: &Job) -> Vec<String> {
+    self
+      .0
+      .payload(job)
+      .map(|payload| self.0.resources(payload))
+      .unwrap_or_default()
+  }
+
+  fn dedupe_key(&self, job: &Job) -> Option<String> {
+    self
+      .0
+      .payload(job)
+      .and_then(|payload| self.0.dedupe_key(payload))
+  }
+
+  fn user(&self, job: &Job) -> Option<String> {
+    self.0.payload(job).and_then(|payload| self.0.user(payload))
+  }
+
+  async fn related(
+    &self,
+    job: &Job,
+    user_id: &str,
+    context: &crate::Context,
+  ) -> std::io::Result<bool> {
+    match self.0.payload(job) {
+      Some(payload) => self.0.related(payload, user_id, context).await,
+      None => Ok(false),
+    }
+  }
+
+  fn result(&self, job: &Job) -> Option<WrappedJobResult> {
+    self
+      .0
+      .payload(job)
+      .and_then(|payload| self.0.result(payload))
+  }
+}
+
+fn unhandled(job: &QueuedJob) -> Outcome {
+  Outcome {
+    job: job.job.clone(),
+    failure: Some(Failure::permanent(format!(
+      "no handler registered for '{}' jobs",
+      job.job.kind()
+    ))),
+  }
+}
+
+// Used by jobs whose kind has not been registered, which should never make it past the tests.
+fn fallback_policy() -> RetryPolicy {
+  RetryPolicy::new(1, 1, 1)
+}
+
+// The handlers known to a process, keyed by the kind of job they work off. The default registry
+// holds a handler for every `Job` variant.
+pub struct Registry {
+  _handlers: HashMap<&'static str, Box<dyn Entry>>,
+}
+
+impl Registry {
+  pub fn new() -> Self {
+    Registry {
+      _handlers: HashMap::new(),
+    }
+  }
+
+  // Adds the handler, replacing any handler previously registered for the same kind.
+  pub fn register<H: JobHandler>(mut self, handler: H) -> Self {
+    self
+      ._handlers
+      .insert(handler.kind(), Box::new(Registered(handler)));
+    self
+  }
+
+  pub fn handles(&self, kind: &str) -> bool {
+    self._handlers.contains_key(kind)
+  }
+
+  fn entry(&self, job: &Job) -> Option<&dyn Entry> {
+    self._handlers.get(job.kind()).map(|entry| entry.as_ref())
+  }
+
+  pub async fn execute(&self, job: &QueuedJob, context: &Context) -> Outcome {
+    match self.entry(&job.job) {
+      Some(entry) => entry.execute(job, context).await,
+      None => unhandled(job),
+    }
+  }
+
+  pub fn policy(&self, job: &Job) -> RetryPolicy {
+    self
+      .entry(job)
+      .map(|entry| entry.policy())
+      .unwrap_or_else(fallback_policy)
+  }
+
+  // Jobs of kinds that have not been registered are returned as they are.
+  pub fn reset(&self, job: &Job) -> Job {
+    self
+      .entry(job)
+      .and_then(|entry| entry.reset(job))
+      .unwrap_or_else(|| job.clone())
+  }
+
+  pub fn resources(&self, job: &Job) -> Vec<String> {
+    self
+      .entry(job)
+      .map(|entry| entry.resources(job))
+      .unwrap_or_default()
+  }
+
+  pub fn dedupe_key(&self, job: &Job) -> Option<String> {
+    self.entry(job).and_then(|entry| entry.dedupe_key(job))
+  }
+
+  pub fn user(&self, job: &Job) -> Option<String> {
+    self.entry(job).and_then(|entry| entry.user(job))
+  }
+
+  // The job's own user may always see it; anyone else has to be related to what the job worked on.
+  pub async fn visible(
+    &self,
+    job: &Job,
+    user_id: &str,
+    context: &crate::Context,
+  ) -> std::io::Result<bool> {
+    if self.user(job).as_deref() == Some(user_id) {
+      return Ok(true);
+    }
+
+    match self.entry(job) {
+      Some(entry) => entry.related(job, user_id, context).await,
+      None => Ok(false),
+    }
+  }
+
+  pub fn handle(&self, job: &QueuedJob) -> JobHandle {
+    JobHandle {
+      id: job.id.clone(),
+      result: self
+        .entry(&job.job)
+        .and_then(|entry| entry.result(&job.job)),
+    }
+  }
+}
+
+impl Default for Registry {
+  fn default() -> Self {
+    Registry::new()
+      .register(lobbies::CreateLobbyHandler)
+      .register(lobbies::CreateGameHandler)
+      .register(rounds::RoundFulfillmentHandler)
+      .register(rounds::RoundCompletionHandler)
+      .register(lobby_memberships::CleanupLobbyMembershipHandler)
+      .register(game_memberships::CleanupGameMembershipHandler)
+      .register(users::MergeUsersHandler)
+      .register(users::ExportUserDataHandler)
+      .register(users::DeleteUserHandler)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::Registry;
+  use crate::interchange::http::{JobResult, WrappedJobResult};
+  use crate::interchange::jobs::{
+    CheckRoundCompletion, CheckRoundFulfillment, CheckRoundFulfillmentResult,
+    CleanupGameMembership, CleanupLobbyMembership, CreateGame, CreateLobby, DeleteUser,
+    ExportUserData, Job, MergeUsers, QueuedJob,
+  };
+
+  fn every_kind() -> Vec<Job> {
+    vec![
+      Job::CreateLobby(CreateLobby {
+        creator: String::from("user"),
+        result: None,
+      }),
+      Job::CheckRoundFulfillment(CheckRoundFulfillment {
+        round_id: String::from("round"),
+        result: None,
+      }),
+      Job::CreateGame(CreateGame {
+        creator: String::from("user"),
+        lobby_id: String::from("lobby"),
+        result: None,
+      }),
+      Job::CleanupLobbyMembership(CleanupLobbyMembership {
+        member_id: String::from("member"),
+        lobby_id: String::from("lobby"),
+        result: None,
+      }),
+      Job::CheckRoundCompletion(CheckRoundCompletion {
+        round_id: String::from("round"),
+        game_id: String::from("game"),
+        result: None,
+      }),
+      Job::CleanupGameMembership(CleanupGameMembership {
+        user_id: String::from("user"),
+        member_id: String::from("member"),
+        lobby_id: String::from("lobby"),
+        game_id: String::from("game"),
+        result: None,
+      }),
+      Job::MergeUsers(MergeUsers {
+        source: String::from("other"),
+        target: String::from("user"),
+        result: None,
+      }),
+      Job::ExportUserData(ExportUserData {
+        user_id: String::from("user"),
+        result: None,
+      }),
+      Job::DeleteUser(DeleteUser {
+        user_id: String::from("user"),
+        result: None,
+      }),
+    ]
+  }
+
+  #[test]
+  fn default_handles_every_kind() {
+    let registry = Registry::default();
+
+    for job in every_kind() {
+      assert!(
+        registry.handles(job.kind()),
+        "no handler for '{}'",
+        job.kind()
+      );
+    }
+  }
+
+  #[test]
+  fn only_user_jobs_are_visible() {
+    let registry = Registry::default();
+    let visible = every_kind()
+      .into_iter()
+      .filter(|job| registry.user(job).is_some())
+      .map(|job| job.kind())
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      visible,
+      vec![
+        "create_lobby",
+        "create_game",
+        "cleanup_game_membership",
+        "merge_users",
+        "export_user_data",
+        "delete_user"
+      ]
+    );
+  }
+
+  #[test]
+  fn resets_clear_results() {
+    let registry = Registry::default();
+
+    for job in every_kind() {
+      let finished = serde_json::to_value(&job)
+        .map(|mut value| {
+          value["c"]["result"] = serde_json::json!({ "Err": "failed" });
+          value
+        })
+        .and_then(serde_json::from_value::<Job>)
+        .unwrap();
+
+      assert_ne!(finished, job);
+      assert_eq!(registry.reset(&finished), job, "'{}' not reset", job.kind());
+    }
+  }
+
+  #[test]
+  fn only_checks_are_coalesced() {
+    let registry = Registry::default();
+    let keys = every_kind()
+      .iter()
+      .filter_map(|job| registry.dedupe_key(job))
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      keys,
+      vec!["round_fulfillment:round", "round_completion:round"]
+    );
+  }
+
+  #[test]
+  fn resources_name_records() {
+    let registry = Registry::default();
+    let resources = every_kind()
+      .iter()
+      .map(|job| registry.resources(job))
+      .collect::<Vec<_>>();
+
+    assert_eq!(resources[0], Vec::<String>::new());
+    assert_eq!(resources[1], vec!["round:round"]);
+    assert_eq!(resources[4], vec!["round:round", "game:game"]);
+    assert_eq!(resources[6], vec!["user:other", "user:user"]);
+  }
+
+  #[test]
+  fn handle_wraps_results() {
+    let registry = Registry::default();
+    let job = QueuedJob::new(
+      "job",
+      Job::CreateGame(CreateGame {
+        creator: String::from("user"),
+        lobby_id: String::from("lobby"),
+        result: Some(Ok(String::from("game"))),
+      }),
+    );
+
+    match registry.handle(&job).result {
+      Some(WrappedJobResult::Success(JobResult::NewGame { id })) => assert_eq!(id, "game"),
+      other => panic!("unexpected result {:?}", other),
+    }
+  }
+
+  #[test]
+  fn handle_describes_round_checks() {
+    let registry = Registry::default();
+    let job = QueuedJob::new(
+      "job",
+      Job::CheckRoundFulfillment(CheckRoundFulfillment {
+        round_id: String::from("round"),
+        result: Some(Ok(CheckRoundFulfillmentResult::Fulfilled {
+          next_round_id: Some(String::from("next")),
+        })),
+      }),
+    );
+
+    match registry.handle(&job).result {
+      Some(WrappedJobResult::Success(JobResult::RoundFulfillment {
+        round_id,
+        remaining,
+        next_round_id,
+      })) => {
+        assert_eq!(round_id, "round");
+        assert_eq!(remaining, 0);
+        assert_eq!(next_round_id.as_deref(), Some("next"));
+      }
+      other => panic!("unexpected result {:?}", other),
+    }
+  }
+
+  #[test]
+  fn handle_reads_counted_fulfillments() {
+    let registry = Registry::default();
+    let job = serde_json::from_str::<Job>(
+      r#"{"t":"check_round_fulfillment","c":{"round_id":"round","result":{"Ok":2}}}"#,
+    )
+    .expect("unable to parse job");
+
+    match registry.handle(&QueuedJob::new("job", job)).result {
+      Some(WrappedJobResult::Success(JobResult::RoundFulfillment {
+        remaining,
+        next_round_id,
+        ..
+      })) => {
+        assert_eq!(remaining, 2);
+        assert_eq!(next_round_id, None);
+      }
+      other => panic!("unexpected result {:?}", other),
+    }
+  }
+
+  #[test]
+  fn handle_describes_cleanups() {
+    let registry = Registry::default();
+    let job = QueuedJob::new(
+      "job",
+      Job::CleanupLobbyMembership(CleanupLobbyMembership {
+        member_id: String::from("member"),
+        lobby_id: String::from("lobby"),
+        result: Some(Ok(String::from("lobby"))),
+      }),
+    );
+
+    match registry.handle(&job).result {
+      Some(WrappedJobResult::Success(JobResult::LobbyMembershipCleanup {
+        lobby_closed, ..
+      })) => assert!(lobby_closed),
+      other => panic!("unexpected result {:?}", other),
+    }
+  }
+
+  #[test]
+  fn handle_surfaces_failures() {
+    let registry = Registry::default();
+    let job = QueuedJob::new(
+      "job",
+      Job::CheckRoundCompletion(CheckRoundCompletion {
+        round_id: String::from("round"),
+        game_id: String::from("game"),
+        result: Some(Err(String::from("no such round"))),
+      }),
+    );
+
+    match registry.handle(&job).result {
+      Some(WrappedJobResult::Failure(reason)) => assert_eq!(reason, "no such round"),
+      other => panic!("unexpected result {:?}", other),
+    }
+  }
+
+  #[test]
+  fn unregistered_kinds_are_not_handled() {
+    let registry = Registry::new();
+    let job = QueuedJob::new("job", every_kind().remove(0));
+
+    assert!(!registry.handles(job.job.kind()));
+    assert_eq!(registry.user(&job.job), None);
+    assert!(registry.handle(&job).result.is_none());
+  }
+
+  #[test]
+  fn tags_are_unchanged() {
+    let job = serde_json::from_str::<Job>(r#"{"t":"create_lobby","c":{"creator":"user"}}"#)
+      .expect("unable to parse job");
+
+    assert_eq!(
+      job,
+      Job::CreateLobby(CreateLobby {
+        creator: String::from("user"),
+        result: None,
+      })
+    );
+  }
+}
@@ -0,0 +1,72 @@
+use rand::{thread_rng, Rng};
+use std::time::Duration;
+
+// How many times a job may be attempted before it is moved to the dead-letter list, and how long
+// to wait between attempts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+  pub max_attempts: u32,
+  pub base_delay: Duration,
+  pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+  pub fn new(max_attempts: u32, base_delay: u64, max_delay: u64) -> Self {
+    RetryPolicy {
+      max_attempts,
+      base_delay: Duration::from_secs(base_delay),
+      max_delay: Duration::from_secs(max_delay),
+    }
+  }
+
+  pub fn exhausted(&self, attempts: u32) -> bool {
+    attempts >= self.max_attempts
+  }
+
+  // Doubles the base delay for every failed attempt up to the maximum. The actual delay is picked at
+  // random from the upper half of that so that jobs that failed together do not retry together.
+  pub fn delay(&self, attempts: u32) -> Duration {
+    let exponent = attempts.saturating_sub(1).min(16);
+    let ceiling = self
+      .base_delay
+      .checked_mul(1 << exponent)
+      .unwrap_or(self.max_delay)
+      .min(self.max_delay);
+    let millis = ceiling.as_millis() as u64;
+
+    if millis == 0 {
+      return ceiling;
+    }
+
+    Duration::from_millis(thread_rng().gen_range(millis / 2..=millis))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::RetryPolicy;
+  use std::time::Duration;
+
+  #[test]
+  fn delay_grows_within_bounds() {
+    let policy = RetryPolicy::new(5, 1, 10);
+
+    for _ in 0..20 {
+      let first = policy.delay(1);
+      assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+
+      let third = policy.delay(3);
+      assert!(third >= Duration::from_secs(2) && third <= Duration::from_secs(4));
+
+      let capped = policy.delay(40);
+      assert!(capped >= Duration::from_secs(5) && capped <= Duration::from_secs(10));
+    }
+  }
+
+  #[test]
+  fn exhausted_after_max_attempts() {
+    let policy = RetryPolicy::new(3, 1, 10);
+    assert!(!policy.exhausted(2));
+    assert!(policy.exhausted(3));
+  }
+}
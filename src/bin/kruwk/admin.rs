@@ -0,0 +1,203 @@
+use gumdrop::Options as Gumdrop;
+use std::io::Result;
+use std::time::Duration;
+
+use krumnet::{
+  errors,
+  interchange::jobs::{Job, JobStatus, QueuedJob},
+  JobStore,
+};
+
+// Commands for looking after the job store by hand, e.g when a game is stuck waiting on a job.
+// They go through the configured job store, so they work the same whichever backend is in use.
+#[derive(Debug, Gumdrop)]
+pub enum Command {
+  #[options(help = "list waiting, running, finished, failed or dead jobs")]
+  List(ListOptions),
+
+  #[options(help = "show the full payload and result of jobs by id")]
+  Show(IdOptions),
+
+  #[options(help = "clear the result of jobs by id and queue them again")]
+  Requeue(IdOptions),
+
+  #[options(help = "queue dead jobs by id again")]
+  Replay(IdOptions),
+
+  #[options(help = "forget finished jobs")]
+  Purge(PurgeOptions),
+
+  #[options(help = "queue jobs given in their serialized form")]
+  Enqueue(EnqueueOptions),
+}
+
+#[derive(Debug, Gumdrop)]
+pub struct ListOptions {
+  #[options(help = "display the help text")]
+  help: bool,
+
+  #[options(
+    free,
+    help = "waiting, running, finished, failed or dead (all but finished by default)"
+  )]
+  lists: Vec<String>,
+}
+
+#[derive(Debug, Gumdrop)]
+pub struct IdOptions {
+  #[options(help = "display the help text")]
+  help: bool,
+
+  #[options(free, help = "job ids")]
+  ids: Vec<String>,
+}
+
+#[derive(Debug, Gumdrop)]
+pub struct PurgeOptions {
+  #[options(help = "display the help text")]
+  help: bool,
+
+  #[options(
+    help = "only purge jobs that finished this many seconds ago",
+    meta = "SECONDS"
+  )]
+  older_than: u64,
+}
+
+#[derive(Debug, Gumdrop)]
+pub struct EnqueueOptions {
+  #[options(help = "display the help text")]
+  help: bool,
+
+  #[options(help = "wait this many seconds before queueing", meta = "SECONDS")]
+  delay: Option<u64>,
+
+  #[options(free, help = r#"jobs, e.g '{"t":"check_round_completion","c":{...}}'"#)]
+  jobs: Vec<String>,
+}
+
+const DEFAULT_LISTS: [&'static str; 4] = ["waiting", "running", "failed", "dead"];
+
+fn summary(job: &QueuedJob) -> String {
+  format!(
+    "{}\t{}\t{:?}\tattempts={}\tworker={}\t{}",
+    job.id,
+    job.job.kind(),
+    job.status,
+    job.attempts,
+    job.worker.as_deref().unwrap_or("-"),
+    job.last_error.as_deref().unwrap_or("")
+  )
+}
+
+async fn list(jobs: &dyn JobStore, name: &str) -> Result<Vec<QueuedJob>> {
+  match name {
+    "waiting" => jobs.waiting().await,
+    "running" => jobs.running().await,
+    "finished" => jobs.finished().await,
+    "failed" => jobs.finished().await.map(|finished| {
+      finished
+        .into_iter()
+        .filter(|job| job.status == JobStatus::Failed)
+        .collect()
+    }),
+    "dead" => jobs.dead_letters().await,
+    other => Err(errors::e(format!("unknown job list '{}'", other))),
+  }
+}
+
+// Each id is looked at on its own; ids that could not be found are reported once every id has been.
+fn missing(ids: Vec<&String>) -> Result<()> {
+  if ids.is_empty() {
+    return Ok(());
+  }
+
+  let ids = ids.into_iter().cloned().collect::<Vec<_>>();
+  Err(errors::e(format!("no job found for {}", ids.join(", "))))
+}
+
+pub async fn run(jobs: &dyn JobStore, command: Command) -> Result<()> {
+  match command {
+    Command::List(options) => {
+      let names = match options.lists.is_empty() {
+        true => DEFAULT_LISTS.iter().map(|name| name.to_string()).collect(),
+        false => options.lists,
+      };
+
+      for name in names {
+        let listed = list(jobs, &name).await?;
+        println!("{} ({})", name, listed.len());
+
+        for job in listed {
+          println!("  {}", summary(&job));
+        }
+      }
+
+      Ok(())
+    }
+    Command::Show(options) => {
+      let mut unknown = Vec::new();
+
+      for id in &options.ids {
+        match jobs.lookup(id).await? {
+          Some(job) => println!("{}", serde_json::to_string_pretty(&job)?),
+          None => unknown.push(id),
+        }
+      }
+
+      missing(unknown)
+    }
+    Command::Requeue(options) => {
+      let mut unknown = Vec::new();
+
+      for id in &options.ids {
+        match jobs.requeue(id).await? {
+          Some(id) => println!("requeued '{}'", id),
+          None => unknown.push(id),
+        }
+      }
+
+      missing(unknown)
+    }
+    Command::Replay(options) => {
+      let mut unknown = Vec::new();
+
+      for id in &options.ids {
+        match jobs.replay(id).await? {
+          Some(id) => println!("replayed '{}'", id),
+          None => unknown.push(id),
+        }
+      }
+
+      missing(unknown)
+    }
+    Command::Purge(options) => {
+      let expired = jobs.expire(Duration::from_secs(options.older_than)).await?;
+
+      for id in &expired {
+        println!("purged '{}'", id);
+      }
+
+      println!("purged {} finished jobs", expired.len());
+      Ok(())
+    }
+    // Every job is parsed before any is queued, so that a typo does not leave half of them queued.
+    Command::Enqueue(options) => {
+      let mut parsed = Vec::with_capacity(options.jobs.len());
+
+      for source in &options.jobs {
+        parsed.push(serde_json::from_str::<Job>(source)?);
+      }
+
+      for job in &parsed {
+        let id = match options.delay {
+          Some(delay) => jobs.queue_in(job, Duration::from_secs(delay)).await?,
+          None => jobs.queue(job).await?,
+        };
+        println!("queued {} '{}'", job.kind(), id);
+      }
+
+      Ok(())
+    }
+  }
+}
@@ -1,19 +1,29 @@
 use async_std::sync::Arc;
-use async_std::task::block_on;
+use async_std::task::{block_on, sleep, spawn};
 use gumdrop::{parse_args_default_or_exit, Options as Gumdrop};
 use log::{debug, info, warn};
 use std::env::args;
 use std::io::Result;
 use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use krumnet::{
   bg::context::Context,
-  bg::handlers::{game_memberships, lobbies, lobby_memberships, rounds},
-  interchange::jobs::{Job, QueuedJob},
-  version, Configuration, JobStore, RecordStore,
+  bg::{
+    concurrency::Limiter,
+    outcome::{Failure, Outcome},
+    registry::Registry,
+  },
+  constants::{
+    JOB_HEARTBEAT_INTERVAL, JOB_REAP_INTERVAL, JOB_RETENTION, JOB_TIMEOUT, WORKER_DEFER_DELAY,
+    WORKER_FAILURE_BACKOFF, WORKER_MAINTENANCE_INTERVAL, WORKER_MAX_FAILURE_BACKOFF,
+  },
+  interchange::jobs::{JobStatus, QueuedJob},
+  jobs, version, Configuration, JobStore, RecordStore,
 };
 
-const MAX_WORKER_FAILS: u8 = 10;
+mod admin;
 
 #[derive(Debug, Gumdrop)]
 struct Options {
@@ -25,23 +35,189 @@ struct Options {
 
   #[options(help = "display the version and exit")]
   version: bool,
+
+  // Without a command, the process works off the queue.
+  #[options(command)]
+  command: Option<admin::Command>,
+}
+
+// Saves the result of a job and acknowledges it. Jobs that failed in a way worth retrying are
+// scheduled to run again until their policy runs out, at which point they are buried along with
+// their last result.
+async fn settle(
+  jobs: &dyn JobStore,
+  registry: &Registry,
+  job: &QueuedJob,
+  outcome: Outcome,
+) -> Result<()> {
+  let reason = match outcome.failure {
+    Some(Failure::Retryable(reason)) => reason,
+    failure => {
+      let status = match failure {
+        Some(_) => JobStatus::Failed,
+        None => JobStatus::Succeeded,
+      };
+      let finished = QueuedJob {
+        job: outcome.job,
+        last_error: failure.map(String::from).or_else(|| job.last_error.clone()),
+        ..job.with_status(status)
+      };
+      jobs.update(&job.id, &finished).await?;
+      return jobs.ack(&job.id).await;
+    }
+  };
+
+  let policy = registry.policy(&job.job);
+  let attempts = job.attempts.saturating_add(1);
+
+  if policy.exhausted(attempts) {
+    let failed = QueuedJob {
+      job: outcome.job,
+      attempts,
+      last_error: Some(reason),
+      ..job.clone()
+    };
+    return jobs.bury(&failed).await;
+  }
+
+  let delay = policy.delay(attempts);
+  warn!(
+    "job '{}' failed (attempt {} of {}), retrying in {:?} - {}",
+    job.id, attempts, policy.max_attempts, delay, reason
+  );
+
+  let pending = QueuedJob {
+    job: registry.reset(&job.job),
+    attempts,
+    last_error: Some(reason),
+    ..job.clone()
+  };
+  jobs.retry(&pending, delay).await
+}
+
+// Keeps the heartbeat of a job fresh until it is done, so that the reaper leaves it alone while it
+// is still being worked on.
+async fn heartbeat(jobs: Arc<dyn JobStore>, id: String, interval: Duration, done: Arc<AtomicBool>) {
+  loop {
+    sleep(interval).await;
+
+    if done.load(Ordering::SeqCst) {
+      break;
+    }
+
+    if let Err(e) = jobs.heartbeat(&id).await {
+      warn!("unable to send heartbeat for job '{}' - {}", id, e);
+    }
+  }
+}
+
+// Shared by every worker task.
+struct Worker {
+  ctx: Context,
+  jobs: Arc<dyn JobStore>,
+  registry: Arc<Registry>,
+  limiter: Arc<Limiter>,
+  interval: Duration,
 }
 
-async fn execute<'a>(ctx: &Context, job: &QueuedJob) -> QueuedJob {
-  let job_result = match &job.job {
-    Job::CheckRoundFulfillment(details) => rounds::check_round_fulfillment(&details, &ctx).await,
-    Job::CreateLobby(details) => lobbies::create_lobby(&job.id, &details, &ctx.records).await,
-    Job::CleanupLobbyMembership(details) => {
-      lobby_memberships::cleanup(&job.id, &details, &ctx).await
+// Works off a single job. Jobs that cannot start yet, because their kind is at its limit or another
+// task is working on the same records, are deferred rather than waited on so that the task can
+// move on to other work.
+async fn run(worker: &Worker, job: QueuedJob) {
+  let permit = match worker.limiter.admit(&job.job) {
+    Some(permit) => permit,
+    None => {
+      debug!("job '{}' ({}) blocked, deferring", job.id, job.job.kind());
+      let delay = Duration::from_millis(WORKER_DEFER_DELAY);
+      if let Err(e) = worker.jobs.retry(&job, delay).await {
+        warn!("unable to defer job '{}' - {}", job.id, e);
+      }
+      return;
     }
-    Job::CreateGame(details) => lobbies::create_game(&job.id, &details, &ctx.records).await,
-    Job::CleanupGameMembership(details) => game_memberships::cleanup(&details, &ctx).await,
-    Job::CheckRoundCompletion(details) => rounds::check_round_completion(&details, &ctx).await,
   };
 
-  QueuedJob {
-    id: job.id.clone(),
-    job: job_result,
+  let done = Arc::new(AtomicBool::new(false));
+  spawn(heartbeat(
+    worker.jobs.clone(),
+    job.id.clone(),
+    worker.interval,
+    done.clone(),
+  ));
+
+  let outcome = worker.registry.execute(&job, &worker.ctx).await;
+  done.store(true, Ordering::SeqCst);
+  drop(permit);
+
+  // Jobs whose results could not be saved are left unacknowledged so they will be retried once
+  // they time out.
+  if let Err(e) = settle(worker.jobs.as_ref(), &worker.registry, &job, outcome).await {
+    warn!("unable to settle job '{}' - {}", job.id, e);
+  }
+}
+
+async fn work(worker: Arc<Worker>, index: usize) {
+  let mut fails: u32 = 0;
+
+  loop {
+    match worker.jobs.dequeue().await {
+      Ok(Some(job)) => {
+        info!("worker {} pulled next job off queue - {:?}", index, job.id);
+        run(&worker, job).await;
+        fails = 0;
+      }
+      Ok(None) => {
+        debug!("worker {} found nothing to work off, skipping", index);
+        fails = 0;
+      }
+      // The job store reconnects on its own; back off while it does rather than spinning.
+      Err(e) => {
+        fails = fails.saturating_add(1);
+        let delay = WORKER_FAILURE_BACKOFF
+          .saturating_mul(fails as u64)
+          .min(WORKER_MAX_FAILURE_BACKOFF);
+
+        warn!(
+          "worker {} failed job store dequeue attempt ({} in a row), waiting {}ms - {}",
+          index, fails, delay, e
+        );
+        sleep(Duration::from_millis(delay)).await;
+      }
+    }
+  }
+}
+
+// Requeues jobs that have timed out, queues jobs that are due to be retried and forgets jobs that
+// finished long ago, on behalf of every worker task.
+async fn maintain(jobs: Arc<dyn JobStore>, timeout: Duration, retention: Duration) {
+  let mut reaped: Option<Instant> = None;
+
+  loop {
+    let reap_due = reaped
+      .map(|at| at.elapsed() >= Duration::from_secs(JOB_REAP_INTERVAL))
+      .unwrap_or(true);
+
+    if reap_due {
+      match jobs.reap(timeout).await {
+        Ok(ids) if !ids.is_empty() => info!("requeued {} timed out jobs", ids.len()),
+        Ok(_) => debug!("no timed out jobs to requeue"),
+        Err(e) => warn!("unable to reap timed out jobs - {}", e),
+      }
+
+      match jobs.expire(retention).await {
+        Ok(ids) if !ids.is_empty() => info!("expired {} finished jobs", ids.len()),
+        Ok(_) => debug!("no finished jobs to expire"),
+        Err(e) => warn!("unable to expire finished jobs - {}", e),
+      }
+      reaped = Some(Instant::now());
+    }
+
+    match jobs.promote().await {
+      Ok(ids) if !ids.is_empty() => info!("queued {} jobs due for retry", ids.len()),
+      Ok(_) => (),
+      Err(e) => warn!("unable to queue jobs due for retry - {}", e),
+    }
+
+    sleep(Duration::from_millis(WORKER_MAINTENANCE_INTERVAL)).await;
   }
 }
 
@@ -60,48 +236,63 @@ fn main() -> Result<()> {
     exit(0);
   }
 
+  if let Some(command) = opts.command {
+    let config = opts.config;
+
+    return block_on(async move {
+      let jobs = jobs::open(&config, Arc::new(Registry::default())).await?;
+      admin::run(jobs.as_ref(), command).await
+    });
+  }
+
   info!("starting worker process (version {})", version::version());
 
+  let mut config = opts.config.clone();
+  let concurrency = config.worker.concurrency.max(1);
+
+  // Every task may hold a connection for the length of a blocking dequeue; leave room for the
+  // heartbeats and results of the jobs being worked on.
+  config.redis.pool_size = config.redis.pool_size.max(concurrency + 2);
+
   block_on(async {
-    let jobs = Arc::new(JobStore::open(&opts.config).await?);
+    let registry = Arc::new(Registry::default());
+    let jobs = jobs::open(&config, registry.clone()).await?;
 
-    let ctx = Context {
-      records: Arc::new(RecordStore::open(&opts.config).await?),
-      jobs: Arc::new(JobStore::open(&opts.config).await?),
+    let timeout = match config.job_store.job_timeout {
+      0 => Duration::from_secs(JOB_TIMEOUT),
+      secs => Duration::from_secs(secs),
+    };
+    let retention = match config.job_store.retention {
+      0 => Duration::from_secs(JOB_RETENTION),
+      secs => Duration::from_secs(secs),
     };
 
-    let mut fails = 0;
-
-    info!("backend stores connected successfully, starting dequeue");
-
-    loop {
-      let next = jobs.dequeue().await;
-
-      match next {
-        Ok(Some(job)) => {
-          info!("pulled next job off queue - {:?}", job.id);
-          let next = execute(&ctx, &job).await;
-          if let Err(e) = jobs.update(&job.id, &next).await {
-            warn!("unable to update job - {}", e);
-          }
-          fails = 0;
-        }
-        Ok(None) => {
-          info!("nothing to work off, skppping");
-          fails = 0;
-        }
-        Err(e) => {
-          fails = fails + 1;
-
-          if fails > MAX_WORKER_FAILS {
-            warn!("final failure on job dequeue attempt - {}, exiting", e);
-            break;
-          }
-
-          warn!("failed job store dequeue attempt - {}", e);
-          continue;
-        }
-      }
+    let worker = Arc::new(Worker {
+      ctx: Context {
+        records: Arc::new(RecordStore::open(&config).await?),
+        jobs: jobs.clone(),
+      },
+      jobs: jobs.clone(),
+      registry: registry.clone(),
+      limiter: Limiter::new(&config.worker.limits, registry),
+      interval: Duration::from_secs(JOB_HEARTBEAT_INTERVAL)
+        .min(timeout / 3)
+        .max(Duration::from_secs(1)),
+    });
+
+    info!(
+      "backend stores connected successfully, starting {} workers",
+      concurrency
+    );
+
+    spawn(maintain(jobs, timeout, retention));
+
+    let tasks = (0..concurrency)
+      .map(|index| spawn(work(worker.clone(), index)))
+      .collect::<Vec<_>>();
+
+    for task in tasks {
+      task.await;
     }
 
     Ok(())
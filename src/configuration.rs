@@ -2,12 +2,20 @@ extern crate serde;
 
 use log::{debug, warn};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::env::var_os;
 use std::fs::read;
 use std::io::{Error, ErrorKind};
 use std::path::Path;
 use std::str::FromStr;
 
+use crate::constants::{
+  GOOGLE_AUTH_URL, GOOGLE_INFO_URL, GOOGLE_ISSUERS, GOOGLE_JWKS_URL, GOOGLE_TOKEN_URL,
+  JOB_RETENTION, JOB_TIMEOUT, REDIS_COMMAND_TIMEOUT, REDIS_CONNECT_TIMEOUT,
+  REDIS_MAX_RECONNECT_BACKOFF, REDIS_POOL_SIZE, REDIS_RECONNECT_BACKOFF, SESSION_COOKIE_SAME_SITE,
+  WORKER_CONCURRENCY,
+};
+
 const DEFAULT_CONFIG_FILE: &'static str = "krumnet-config.json";
 const DEFAULT_POSTGRES_URI: &'static str = "postgresql://postgres@0.0.0.0:5432/krumnet";
 
@@ -28,6 +36,15 @@ pub struct Configuration {
   #[serde(default)]
   pub job_store: JobStoreConfiguration,
 
+  #[serde(default)]
+  pub dev_auth: DevAuthConfiguration,
+
+  #[serde(default)]
+  pub redis: RedisConfiguration,
+
+  #[serde(default)]
+  pub worker: WorkerConfiguration,
+
   #[serde(default)]
   pub addr: String,
 }
@@ -62,6 +79,9 @@ impl Default for Configuration {
       session_store: SessionStoreConfiguration::default(),
       record_store: RecordStoreConfiguration::default(),
       job_store: JobStoreConfiguration::default(),
+      dev_auth: DevAuthConfiguration::default(),
+      redis: RedisConfiguration::default(),
+      worker: WorkerConfiguration::default(),
     }
   }
 }
@@ -92,6 +112,24 @@ pub struct GoogleCredentials {
 
   #[serde(default)]
   pub redirect_uri: String,
+
+  // The google endpoints are configurable so that tests and local development can point the oauth
+  // flow at a stub server.
+  #[serde(default = "GoogleCredentials::default_auth_url")]
+  pub auth_url: String,
+
+  #[serde(default = "GoogleCredentials::default_token_url")]
+  pub token_url: String,
+
+  #[serde(default = "GoogleCredentials::default_info_url")]
+  pub info_url: String,
+
+  // Id tokens are verified against these keys; a `file://` url may be used to load them from disk.
+  #[serde(default = "GoogleCredentials::default_jwks_url")]
+  pub jwks_url: String,
+
+  #[serde(default = "GoogleCredentials::default_issuers")]
+  pub issuers: Vec<String>,
 }
 
 impl Default for GoogleCredentials {
@@ -119,8 +157,36 @@ impl GoogleCredentials {
       client_id,
       client_secret,
       redirect_uri,
+      auth_url: GoogleCredentials::default_auth_url(),
+      token_url: GoogleCredentials::default_token_url(),
+      info_url: GoogleCredentials::default_info_url(),
+      jwks_url: GoogleCredentials::default_jwks_url(),
+      issuers: GoogleCredentials::default_issuers(),
     }
   }
+
+  pub fn default_auth_url() -> String {
+    String::from(GOOGLE_AUTH_URL)
+  }
+
+  pub fn default_token_url() -> String {
+    String::from(GOOGLE_TOKEN_URL)
+  }
+
+  pub fn default_info_url() -> String {
+    String::from(GOOGLE_INFO_URL)
+  }
+
+  pub fn default_jwks_url() -> String {
+    String::from(GOOGLE_JWKS_URL)
+  }
+
+  pub fn default_issuers() -> Vec<String> {
+    GOOGLE_ISSUERS
+      .iter()
+      .map(|issuer| String::from(*issuer))
+      .collect()
+  }
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -132,8 +198,52 @@ pub struct KrumiConfiguration {
   pub cors_origin: String,
 }
 
+// The dev auth provider allows anyone to sign in as any user by name, skipping google entirely. It is
+// intended for offline local development and must be explicitly enabled.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct DevAuthConfiguration {
+  #[serde(default)]
+  pub enabled: bool,
+}
+
+// Tuning for the redis connection pools used by the session and job stores. Times are in
+// milliseconds.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RedisConfiguration {
+  pub pool_size: usize,
+  pub connect_timeout: u64,
+  pub command_timeout: u64,
+  pub reconnect_backoff: u64,
+  pub max_reconnect_backoff: u64,
+}
+
+impl Default for RedisConfiguration {
+  fn default() -> Self {
+    RedisConfiguration {
+      pool_size: REDIS_POOL_SIZE,
+      connect_timeout: REDIS_CONNECT_TIMEOUT,
+      command_timeout: REDIS_COMMAND_TIMEOUT,
+      reconnect_backoff: REDIS_RECONNECT_BACKOFF,
+      max_reconnect_backoff: REDIS_MAX_RECONNECT_BACKOFF,
+    }
+  }
+}
+
+// Where jobs are kept. The postgres backend uses the `krumnet.jobs` table in the record store's
+// database, and ignores `redis_uri`, `map_key` and `dequeue_key`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobBackend {
+  #[default]
+  Redis,
+  Postgres,
+}
+
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct JobStoreConfiguration {
+  #[serde(default)]
+  pub backend: JobBackend,
   #[serde(default)]
   pub queue_key: String,
   #[serde(default)]
@@ -144,6 +254,42 @@ pub struct JobStoreConfiguration {
   pub redis_uri: String,
   #[serde(default)]
   pub queue_delay: u64,
+
+  // Seconds a dequeued job may go without a heartbeat from its worker before it is requeued.
+  #[serde(default = "JobStoreConfiguration::default_job_timeout")]
+  pub job_timeout: u64,
+
+  // Seconds a finished job, and its result, is kept around before it is forgotten.
+  #[serde(default = "JobStoreConfiguration::default_retention")]
+  pub retention: u64,
+}
+
+impl JobStoreConfiguration {
+  pub fn default_job_timeout() -> u64 {
+    JOB_TIMEOUT
+  }
+
+  pub fn default_retention() -> u64 {
+    JOB_RETENTION
+  }
+}
+
+// Tuning for the `kruwk` worker process. `concurrency` is the number of jobs worked on at once, and
+// `limits` caps how many of those may be of a given kind, keyed by the job's tag (e.g `create_game`).
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WorkerConfiguration {
+  pub concurrency: usize,
+  pub limits: HashMap<String, usize>,
+}
+
+impl Default for WorkerConfiguration {
+  fn default() -> Self {
+    WorkerConfiguration {
+      concurrency: WORKER_CONCURRENCY,
+      limits: HashMap::new(),
+    }
+  }
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
@@ -168,6 +314,39 @@ pub struct SessionStoreConfiguration {
   pub secret: String,
   pub session_prefix: String,
   pub expiration_timeout: Option<u64>,
+
+  #[serde(default)]
+  pub cookie: SessionCookieConfiguration,
+}
+
+// When enabled, sessions are handed to the browser in an http-only cookie instead of a token in the
+// krumi callback url. Mutating requests authenticated by cookie must carry a matching csrf header.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SessionCookieConfiguration {
+  #[serde(default)]
+  pub enabled: bool,
+
+  #[serde(default)]
+  pub domain: Option<String>,
+
+  #[serde(default = "SessionCookieConfiguration::default_same_site")]
+  pub same_site: String,
+}
+
+impl SessionCookieConfiguration {
+  pub fn default_same_site() -> String {
+    String::from(SESSION_COOKIE_SAME_SITE)
+  }
+}
+
+impl Default for SessionCookieConfiguration {
+  fn default() -> Self {
+    SessionCookieConfiguration {
+      enabled: false,
+      domain: None,
+      same_site: SessionCookieConfiguration::default_same_site(),
+    }
+  }
 }
 
 #[cfg(test)]
@@ -186,6 +365,7 @@ pub mod test_helpers {
 
 #[cfg(test)]
 mod test {
+  use super::JobBackend;
   use crate::Configuration;
 
   #[test]
@@ -194,6 +374,31 @@ mod test {
     assert_eq!(result.is_ok(), true);
   }
 
+  #[test]
+  fn dev_auth_disabled_by_default() {
+    let result = Configuration::load("ci/github-actions/krumnet-config.json");
+    assert!(!result.unwrap().dev_auth.enabled);
+  }
+
+  #[test]
+  fn session_cookies_disabled_by_default() {
+    let result = Configuration::load("ci/github-actions/krumnet-config.json").unwrap();
+    assert!(!result.session_store.cookie.enabled);
+    assert_eq!(result.session_store.cookie.same_site, "Lax");
+  }
+
+  #[test]
+  fn google_urls_default() {
+    let result = Configuration::load("ci/github-actions/krumnet-config.json").unwrap();
+    assert_eq!(result.google.token_url, crate::constants::GOOGLE_TOKEN_URL);
+  }
+
+  #[test]
+  fn job_backend_defaults_to_redis() {
+    let result = Configuration::load("ci/github-actions/krumnet-config.json").unwrap();
+    assert_eq!(result.job_store.backend, JobBackend::Redis);
+  }
+
   #[test]
   fn from_file_not_exists() {
     let result = Configuration::load("does-not-exist");
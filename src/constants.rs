@@ -4,39 +4,69 @@ pub const MAX_LOBBY_MEMBERS: u8 = 10;
 pub const GOOGLE_TOKEN_URL: &'static str = "https://www.googleapis.com/oauth2/v4/token";
 pub const GOOGLE_AUTH_URL: &'static str = "https://accounts.google.com/o/oauth2/v2/auth";
 pub const GOOGLE_INFO_URL: &'static str = "https://openidconnect.googleapis.com/v1/userinfo";
+pub const GOOGLE_JWKS_URL: &'static str = "https://www.googleapis.com/oauth2/v3/certs";
+pub const GOOGLE_ISSUERS: [&'static str; 2] =
+  ["https://accounts.google.com", "accounts.google.com"];
 
 pub const GOOGLE_AUTH_RESPONSE_TYPE_KEY: &'static str = "response_type";
 pub const GOOGLE_AUTH_RESPONSE_TYPE_VALUE: &'static str = "code";
 pub const GOOGLE_AUTH_CLIENT_ID_KEY: &'static str = "client_id";
 pub const GOOGLE_AUTH_REDIRECT_URI_KEY: &'static str = "redirect_uri";
 pub const GOOGLE_AUTH_SCOPE_KEY: &'static str = "scope";
-pub const GOOGLE_AUTH_SCOPE_VALUE: &'static str = "email profile";
+pub const GOOGLE_AUTH_SCOPE_VALUE: &'static str = "openid email profile";
+pub const GOOGLE_AUTH_STATE_KEY: &'static str = "state";
+pub const GOOGLE_AUTH_NONCE_KEY: &'static str = "nonce";
+
+pub const OUTBOUND_REQUEST_TIMEOUT: u64 = 5000;
+pub const OUTBOUND_REQUEST_RETRIES: u8 = 2;
+pub const OUTBOUND_RETRY_BACKOFF: u64 = 200;
+
+pub const REDIS_POOL_SIZE: usize = 4;
+pub const REDIS_CONNECT_TIMEOUT: u64 = 1000;
+pub const REDIS_COMMAND_TIMEOUT: u64 = 2000;
+pub const REDIS_RECONNECT_BACKOFF: u64 = 100;
+pub const REDIS_MAX_RECONNECT_BACKOFF: u64 = 5000;
+
+pub const JOB_TIMEOUT: u64 = 300;
+pub const JOB_HEARTBEAT_INTERVAL: u64 = 30;
+pub const JOB_REAP_INTERVAL: u64 = 60;
+pub const JOB_RETENTION: u64 = 86400;
+pub const JOB_MAX_WAIT: u64 = 30;
+pub const JOB_WAIT_POLL_INTERVAL: u64 = 250;
+
+pub const WORKER_FAILURE_BACKOFF: u64 = 500;
+pub const WORKER_MAX_FAILURE_BACKOFF: u64 = 30000;
+pub const WORKER_CONCURRENCY: usize = 4;
+pub const WORKER_DEFER_DELAY: u64 = 1000;
+pub const WORKER_MAINTENANCE_INTERVAL: u64 = 1000;
+
+pub const GOOGLE_IDENTITY_PROVIDER: &'static str = "google";
+pub const OAUTH_STATE_TTL: u64 = 600;
+pub const OAUTH_TAKEN_EMAIL_DOMAIN: &'static str = "taken.krumnet.local";
+pub const JWKS_CACHE_TTL: u64 = 3600;
+pub const JWKS_MIN_REFRESH: u64 = 60;
+
+pub const DEV_AUTH_EMAIL_DOMAIN: &'static str = "dev.krumnet.local";
+pub const DEV_AUTH_MAX_NAME_LENGTH: usize = 32;
+
+pub const API_TOKEN_PREFIX: &'static str = "krt_";
+pub const API_TOKEN_LENGTH: usize = 40;
+pub const API_TOKEN_MAX_NAME_LENGTH: usize = 64;
+
+pub const PROFILE_DISPLAY_NAME_MIN_LENGTH: usize = 2;
+pub const PROFILE_DISPLAY_NAME_MAX_LENGTH: usize = 32;
+pub const PROFILE_PRONOUNS_MAX_LENGTH: usize = 32;
+pub const PROFILE_AVATAR_URL_MAX_LENGTH: usize = 512;
+
+pub const DELETED_USER_NAME: &'static str = "deleted user";
+pub const DELETED_USER_EMAIL_DOMAIN: &'static str = "deleted.krumnet.local";
 
 pub const KRUMI_SESSION_ID_KEY: &'static str = "session_id";
 
-#[cfg(not(test))]
-pub fn google_info_url() -> String {
-  String::from(GOOGLE_INFO_URL)
-}
-#[cfg(test)]
-pub fn google_info_url() -> String {
-  String::from(&mockito::server_url())
-}
-
-#[cfg(not(test))]
-pub fn google_auth_url() -> String {
-  String::from(GOOGLE_AUTH_URL)
-}
-#[cfg(test)]
-pub fn google_auth_url() -> String {
-  String::from(&mockito::server_url())
-}
-
-#[cfg(not(test))]
-pub fn google_token_url() -> String {
-  String::from(GOOGLE_TOKEN_URL)
-}
-#[cfg(test)]
-pub fn google_token_url() -> String {
-  String::from(&mockito::server_url())
-}
+pub const SESSION_COOKIE_NAME: &'static str = "krumnet_session";
+pub const SESSION_COOKIE_SAME_SITE: &'static str = "Lax";
+pub const CSRF_COOKIE_NAME: &'static str = "krumnet_csrf";
+pub const CSRF_HEADER_NAME: &'static str = "X-CSRF-Token";
+pub const LINK_COOKIE_NAME: &'static str = "krumnet_link";
+pub const LINK_CONFLICT_KEY: &'static str = "link_conflict";
+pub const BEARER_PREFIX: &'static str = "Bearer ";
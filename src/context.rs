@@ -1,19 +1,27 @@
 use async_std::sync::Arc;
-use elaine::Head;
+use elaine::{Head, RequestMethod};
 use log::{debug, warn};
-use sqlx::query_file;
 use std::io::Result;
 
-use crate::http::AUTHORIZATION;
+use crate::bg::registry::Registry;
+use crate::constants::{BEARER_PREFIX, CSRF_HEADER_NAME, SESSION_COOKIE_NAME};
+use crate::http::{AUTHORIZATION, COOKIE};
+use crate::oidc::KeyCache;
+use crate::outbound::HttpClient;
+use crate::records::UserRecords;
 use crate::{
-  errors, Authority, Configuration, JobStore, RecordConnection, RecordStore, SessionStore,
+  api_tokens, cookies, errors, session, Authority, Configuration, JobStore, RecordConnection,
+  RecordStore, SessionStore,
 };
 
 pub struct Context {
   _auth: Authority,
-  _session: Arc<SessionStore>,
+  _session: Arc<dyn SessionStore>,
   _records: Arc<RecordStore>,
-  _jobs: Arc<JobStore>,
+  _jobs: Arc<dyn JobStore>,
+  _http: Arc<dyn HttpClient>,
+  _keys: Arc<KeyCache>,
+  _registry: Arc<Registry>,
   _config: Configuration,
   _pending: usize,
 }
@@ -27,16 +35,28 @@ impl Context {
     self._pending
   }
 
-  pub fn jobs(&self) -> &JobStore {
-    &self._jobs
+  pub fn jobs(&self) -> &dyn JobStore {
+    self._jobs.as_ref()
+  }
+
+  pub fn http(&self) -> &dyn HttpClient {
+    self._http.as_ref()
+  }
+
+  pub fn keys(&self) -> &KeyCache {
+    &self._keys
+  }
+
+  pub fn registry(&self) -> &Registry {
+    &self._registry
   }
 
   pub fn authority(&self) -> &Authority {
     &self._auth
   }
 
-  pub fn session(&self) -> &SessionStore {
-    &self._session
+  pub fn session(&self) -> &dyn SessionStore {
+    self._session.as_ref()
   }
 
   pub fn records(&self) -> &RecordStore {
@@ -64,58 +84,108 @@ impl std::fmt::Debug for Context {
 
 #[derive(Default)]
 pub struct ContextBuilder {
-  _session: Option<Arc<SessionStore>>,
+  _session: Option<Arc<dyn SessionStore>>,
   _records: Option<Arc<RecordStore>>,
-  _jobs: Option<Arc<JobStore>>,
+  _jobs: Option<Arc<dyn JobStore>>,
+  _http: Option<Arc<dyn HttpClient>>,
+  _keys: Option<Arc<KeyCache>>,
+  _registry: Option<Arc<Registry>>,
   _config: Option<Configuration>,
 }
 
-// Attempts to exchange an authorization token for a user id from the session store, subsequently
-// loading the actual user information from the record store.
+// Personal api tokens are looked up by their digest in the record store, bumping the token's last
+// used timestamp along the way. Revoked and expired tokens will not match. Any other token is
+// exchanged for a user id from the session store, subsequently loading the actual user information
+// from the record store.
 pub async fn load_authorization(
   token: String,
-  session: &SessionStore,
-  records: &RecordStore,
+  session: &dyn SessionStore,
+  records: &dyn UserRecords,
 ) -> Result<Authority> {
-  let uid = session.get(&token).await?;
-  let mut conn = records.acquire().await?;
-  let tenant = query_file!("src/data-store/user-for-session.sql", uid)
-    .fetch_all(&mut conn)
-    .await
-    .map_err(errors::humanize_error)?
-    .into_iter()
-    .nth(0)
-    .and_then(|row| {
-      let id = row.user_id;
-
-      debug!("found user '{:?}'", id);
-
-      Some(Authority::User {
-        id,
+  let grant = if api_tokens::is_api_token(&token) {
+    records.api_token_user(&api_tokens::hash(&token)).await?
+  } else {
+    let uid = session.get(&token).await?;
+    records.session_user(&uid).await?
+  };
+
+  Ok(
+    grant
+      .map(|grant| Authority::User {
+        id: grant.user_id,
         token: token.clone(),
+        scopes: grant.scopes,
+        role: grant.role,
       })
-    });
+      .unwrap_or(Authority::None),
+  )
+}
+
+// Where the token for a request came from. Browsers attach cookies to every request on their own, so
+// cookie credentials are only trusted for mutating requests that also carry the csrf header.
+#[derive(Debug, PartialEq)]
+enum Credential {
+  Header(String),
+  Cookie(String),
+}
+
+fn find_credential(head: &Head, config: &Configuration) -> Option<Credential> {
+  if let Some(value) = head.find_header(AUTHORIZATION) {
+    let token = value.strip_prefix(BEARER_PREFIX).unwrap_or(&value).trim();
+    return Some(Credential::Header(String::from(token)));
+  }
+
+  if !config.session_store.cookie.enabled {
+    return None;
+  }
+
+  head
+    .find_header(COOKIE)
+    .and_then(|header| cookies::find(&header, SESSION_COOKIE_NAME))
+    .map(Credential::Cookie)
+}
 
-  Ok(tenant.unwrap_or(Authority::None))
+fn is_mutation(head: &Head) -> bool {
+  !matches!(
+    head.method(),
+    Some(RequestMethod::GET) | Some(RequestMethod::HEAD) | Some(RequestMethod::OPTIONS) | None
+  )
 }
 
 async fn load_auth(
   head: &Head,
-  session: &SessionStore,
-  records: &RecordStore,
+  config: &Configuration,
+  session: &dyn SessionStore,
+  records: &dyn UserRecords,
 ) -> Result<Authority> {
-  if let Some(value) = head.find_header(AUTHORIZATION) {
-    debug!("found authorization header - {}", value);
-    return load_authorization(value, session, records)
-      .await
-      .or_else(|e| {
-        warn!("unable to load authorization - {}", e);
-        Ok(Authority::None)
-      });
-  }
+  let token = match find_credential(head, config) {
+    Some(Credential::Header(token)) => {
+      debug!("found authorization header - {}", token);
+      token
+    }
+    Some(Credential::Cookie(token)) if is_mutation(head) => {
+      let sent = head.find_header(CSRF_HEADER_NAME).unwrap_or_default();
+
+      if !session::csrf_matches(config, &token, &sent)? {
+        warn!("session cookie without matching csrf header on mutating request");
+        return Ok(Authority::None);
+      }
 
-  debug!("no authorization header present");
-  Ok(Authority::None)
+      token
+    }
+    Some(Credential::Cookie(token)) => token,
+    None => {
+      debug!("no authorization header present");
+      return Ok(Authority::None);
+    }
+  };
+
+  load_authorization(token, session, records)
+    .await
+    .or_else(|e| {
+      warn!("unable to load authorization - {}", e);
+      Ok(Authority::None)
+    })
 }
 
 impl ContextBuilder {
@@ -133,14 +203,35 @@ impl ContextBuilder {
     }
   }
 
-  pub fn jobs(self, jobs: Arc<JobStore>) -> Self {
+  pub fn jobs(self, jobs: Arc<dyn JobStore>) -> Self {
     ContextBuilder {
       _jobs: Some(jobs),
       ..self
     }
   }
 
-  pub fn session(self, session: Arc<SessionStore>) -> Self {
+  pub fn http(self, http: Arc<dyn HttpClient>) -> Self {
+    ContextBuilder {
+      _http: Some(http),
+      ..self
+    }
+  }
+
+  pub fn keys(self, keys: Arc<KeyCache>) -> Self {
+    ContextBuilder {
+      _keys: Some(keys),
+      ..self
+    }
+  }
+
+  pub fn registry(self, registry: Arc<Registry>) -> Self {
+    ContextBuilder {
+      _registry: Some(registry),
+      ..self
+    }
+  }
+
+  pub fn session(self, session: Arc<dyn SessionStore>) -> Self {
     ContextBuilder {
       _session: Some(session),
       ..self
@@ -164,9 +255,20 @@ impl ContextBuilder {
       ._session
       .ok_or(errors::e("missing session configuration for context"))?;
 
+    let _http = self
+      ._http
+      .ok_or(errors::e("missing http client for context"))?;
+
+    // Without a shared cache, signing keys are loaded at most once for the lifetime of the context.
+    let _keys = self._keys.unwrap_or_default();
+    let _registry = self._registry.unwrap_or_default();
+
     Ok(Context {
       _auth: auth,
       _jobs,
+      _http,
+      _keys,
+      _registry,
       _config,
       _session,
       _records,
@@ -185,7 +287,12 @@ impl ContextBuilder {
       .as_ref()
       .ok_or(errors::e("missing session configuration for context"))?;
 
-    let auth = load_auth(head, session, records).await?;
+    let config = self
+      ._config
+      .as_ref()
+      .ok_or(errors::e("missing configuraiton from context"))?;
+
+    let auth = load_auth(head, config, session.as_ref(), records.as_ref()).await?;
     Ok(Context {
       _pending: head.len().unwrap_or_default(),
       ..self.with_authority(auth)?
@@ -196,8 +303,12 @@ impl ContextBuilder {
 #[cfg(test)]
 pub mod test_helpers {
   use super::Context;
+  use crate::authority::{Role, Scopes};
   pub use crate::configuration::test_helpers::load_test_config as load_config;
-  use crate::{Authority, JobStore, RecordStore, SessionStore};
+  use crate::jobs::MemoryJobStore;
+  use crate::outbound::IsahcClient;
+  use crate::session::MemorySessionStore;
+  use crate::{Authority, Configuration, RecordStore};
   use async_std::task::block_on;
   use sqlx::query;
   use std::sync::Arc;
@@ -240,12 +351,14 @@ pub mod test_helpers {
     let config = load_config().unwrap();
     let records = RecordStore::open(&config).await.unwrap();
     let user_id = make_user(name).await;
-    let session = Arc::new(SessionStore::open(&config).await.unwrap());
+    let session = Arc::new(MemorySessionStore::new(&config));
     let records = Arc::new(records);
-    let jobs = Arc::new(JobStore::open(&config).await.unwrap());
+    let jobs = Arc::new(MemoryJobStore::new());
     let auth = Authority::User {
       id: user_id.clone(),
       token: String::from(""),
+      scopes: Scopes::all(),
+      role: Role::Player,
     };
 
     let ctx = Context::builder()
@@ -253,6 +366,7 @@ pub mod test_helpers {
       .records(records)
       .session(session)
       .jobs(jobs)
+      .http(Arc::new(IsahcClient::new().unwrap()))
       .with_authority(auth)
       .unwrap();
 
@@ -260,16 +374,20 @@ pub mod test_helpers {
   }
 
   pub fn with_auth(auth: Authority) -> Context {
+    with_config(load_config().unwrap(), auth)
+  }
+
+  pub fn with_config(config: Configuration, auth: Authority) -> Context {
     block_on(async {
-      let config = load_config().unwrap();
-      let session = Arc::new(SessionStore::open(&config).await.unwrap());
+      let session = Arc::new(MemorySessionStore::new(&config));
       let records = Arc::new(RecordStore::open(&config).await.unwrap());
-      let jobs = Arc::new(JobStore::open(&config).await.unwrap());
+      let jobs = Arc::new(MemoryJobStore::new());
       Context::builder()
         .configuration(&config)
         .records(records)
         .session(session)
         .jobs(jobs)
+        .http(Arc::new(IsahcClient::new().unwrap()))
         .with_authority(auth)
         .unwrap()
     })
@@ -278,11 +396,149 @@ pub mod test_helpers {
 
 #[cfg(test)]
 mod test {
-  use super::test_helpers::with_auth;
-  use crate::Authority;
+  use super::test_helpers::{cleanup_user, make_user, with_auth};
+  use super::{find_credential, is_mutation, load_auth, load_authorization, Credential};
+  use crate::authority::{Role, Scope, Scopes};
+  use crate::configuration::test_helpers::load_test_config;
+  use crate::records::MemoryRecords;
+  use crate::session::{csrf_token, MemorySessionStore, SessionStore};
+  use crate::{api_tokens, Authority};
+  use async_std::task::block_on;
+  use sqlx::query;
 
   #[test]
   fn test_none_authority() {
     assert_eq!(with_auth(Authority::None).authority(), &Authority::None);
   }
+
+  #[test]
+  fn api_token_authority() {
+    let ctx = with_auth(Authority::None);
+
+    block_on(async {
+      let user_id = make_user("context.api_token_authority").await;
+      let token = api_tokens::generate();
+      let mut conn = ctx.records_connection().await.expect("unable to connect");
+
+      query!(
+        "insert into krumnet.api_tokens (user_id, name, token_hash, scopes) values ($1, 'test', $2, $3)",
+        user_id,
+        api_tokens::hash(&token),
+        &vec![String::from("read")]
+      )
+      .execute(&mut conn)
+      .await
+      .expect("unable to insert");
+
+      let authority = load_authorization(token.clone(), ctx.session(), ctx.records())
+        .await
+        .expect("unable to load authority");
+
+      assert_eq!(
+        authority,
+        Authority::User {
+          id: user_id.clone(),
+          token: token.clone(),
+          scopes: Scopes::from_strings(&[String::from("read")]),
+          role: Role::Player,
+        }
+      );
+
+      if let Authority::User { scopes, .. } = authority {
+        assert!(!scopes.allows(Scope::LobbyPlay));
+      }
+
+      query!(
+        "update krumnet.api_tokens set revoked_at = now() where user_id = $1",
+        user_id
+      )
+      .execute(&mut conn)
+      .await
+      .expect("unable to revoke");
+
+      let revoked = load_authorization(token.clone(), ctx.session(), ctx.records())
+        .await
+        .expect("unable to load authority");
+
+      assert_eq!(revoked, Authority::None);
+
+      query!("delete from krumnet.api_tokens where user_id = $1", user_id)
+        .execute(&mut conn)
+        .await
+        .expect("unable to delete");
+
+      cleanup_user(&user_id).await;
+    });
+  }
+
+  fn head(lines: &[&str]) -> elaine::Head {
+    lines
+      .iter()
+      .fold(elaine::Builder::new(), |builder, line| {
+        builder.insert(String::from(*line)).unwrap()
+      })
+      .collect::<elaine::Head>()
+  }
+
+  #[test]
+  fn bearer_and_raw_authorization() {
+    let config = load_test_config().unwrap();
+    let bearer = head(&["GET / HTTP/1.1", "Authorization: Bearer abc"]);
+    let raw = head(&["GET / HTTP/1.1", "Authorization: abc"]);
+
+    assert_eq!(
+      find_credential(&bearer, &config),
+      Some(Credential::Header(String::from("abc")))
+    );
+    assert_eq!(
+      find_credential(&raw, &config),
+      Some(Credential::Header(String::from("abc")))
+    );
+  }
+
+  #[test]
+  fn session_cookie_credentials() {
+    let mut config = load_test_config().unwrap();
+    let request = head(&["POST / HTTP/1.1", "Cookie: krumnet_session=abc"]);
+    assert_eq!(find_credential(&request, &config), None);
+
+    config.session_store.cookie.enabled = true;
+    assert_eq!(
+      find_credential(&request, &config),
+      Some(Credential::Cookie(String::from("abc")))
+    );
+    assert!(is_mutation(&request));
+    assert!(!is_mutation(&head(&["GET / HTTP/1.1"])));
+  }
+
+  #[test]
+  fn cookie_mutations_require_csrf() {
+    block_on(async {
+      let mut config = load_test_config().unwrap();
+      config.session_store.cookie.enabled = true;
+
+      let session = MemorySessionStore::new(&config);
+      let records = MemoryRecords::new();
+      records.add_user("csrf-user", Role::Player).await;
+
+      let token = session.create("csrf-user").await.unwrap();
+      let cookie = format!("Cookie: krumnet_session={}", token);
+      let csrf = format!("X-CSRF-Token: {}", csrf_token(&config, &token).unwrap());
+
+      let mut other = config.clone();
+      other.session_store.secret = String::from("another secret");
+      let guessed = format!("X-CSRF-Token: {}", csrf_token(&other, &token).unwrap());
+
+      let read = head(&["GET / HTTP/1.1", &cookie]);
+      let forged = head(&["POST / HTTP/1.1", &cookie]);
+      let mismatched = head(&["POST / HTTP/1.1", &cookie, &guessed]);
+      let valid = head(&["POST / HTTP/1.1", &cookie, &csrf]);
+
+      let load = |request| load_auth(request, &config, &session, &records);
+      assert_ne!(load(&read).await.unwrap(), Authority::None);
+      assert_eq!(load(&forged).await.unwrap(), Authority::None);
+      assert_eq!(load(&mismatched).await.unwrap(), Authority::None);
+      assert_ne!(load(&valid).await.unwrap(), Authority::None);
+    });
+  }
 }
@@ -0,0 +1,118 @@
+use crate::configuration::SessionCookieConfiguration;
+use crate::constants::{CSRF_COOKIE_NAME, LINK_COOKIE_NAME, SESSION_COOKIE_NAME};
+
+// Finds the value of a single cookie from the contents of a `Cookie` request header.
+pub fn find(header: &str, name: &str) -> Option<String> {
+  header
+    .split(';')
+    .filter_map(|pair| {
+      let mut parts = pair.trim().splitn(2, '=');
+      match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) if key == name => Some(String::from(value)),
+        _ => None,
+      }
+    })
+    .nth(0)
+}
+
+fn attributes(config: &SessionCookieConfiguration, max_age: Option<u64>) -> String {
+  let domain = config
+    .domain
+    .as_ref()
+    .map(|domain| format!("; Domain={}", domain))
+    .unwrap_or_default();
+
+  let age = max_age
+    .map(|age| format!("; Max-Age={}", age))
+    .unwrap_or_default();
+
+  format!(
+    "Path=/; Secure; SameSite={}{}{}",
+    config.same_site, domain, age
+  )
+}
+
+// The session cookie is never visible to scripts. The csrf cookie is, so that the client can echo
+// it back in the csrf header of mutating requests.
+pub fn session(
+  config: &SessionCookieConfiguration,
+  token: &str,
+  csrf: &str,
+  max_age: Option<u64>,
+) -> Vec<String> {
+  vec![
+    format!(
+      "{}={}; HttpOnly; {}",
+      SESSION_COOKIE_NAME,
+      token,
+      attributes(config, max_age)
+    ),
+    format!(
+      "{}={}; {}",
+      CSRF_COOKIE_NAME,
+      csrf,
+      attributes(config, max_age)
+    ),
+  ]
+}
+
+pub fn clear(config: &SessionCookieConfiguration) -> Vec<String> {
+  vec![
+    format!(
+      "{}=; HttpOnly; {}",
+      SESSION_COOKIE_NAME,
+      attributes(config, Some(0))
+    ),
+    format!("{}=; {}", CSRF_COOKIE_NAME, attributes(config, Some(0))),
+  ]
+}
+
+// Ties an identity link flow to the browser that started it; the callback is only honoured when the
+// browser finishing the flow presents the same value.
+pub fn link(config: &SessionCookieConfiguration, binding: &str, max_age: u64) -> String {
+  format!(
+    "{}={}; HttpOnly; {}",
+    LINK_COOKIE_NAME,
+    binding,
+    attributes(config, Some(max_age))
+  )
+}
+
+pub fn clear_link(config: &SessionCookieConfiguration) -> String {
+  format!(
+    "{}=; HttpOnly; {}",
+    LINK_COOKIE_NAME,
+    attributes(config, Some(0))
+  )
+}
+
+#[cfg(test)]
+mod test {
+  use super::{clear, find, session};
+  use crate::configuration::SessionCookieConfiguration;
+
+  #[test]
+  fn find_cookie() {
+    let header = "theme=dark; krumnet_session=abc.def=; other=1";
+    assert_eq!(
+      find(header, "krumnet_session"),
+      Some(String::from("abc.def="))
+    );
+    assert_eq!(find(header, "missing"), None);
+  }
+
+  #[test]
+  fn session_cookies() {
+    let config = SessionCookieConfiguration {
+      domain: Some(String::from("krumpled.com")),
+      ..SessionCookieConfiguration::default()
+    };
+    let cookies = session(&config, "token", "csrf", Some(60));
+    assert_eq!(
+      cookies[0],
+      "krumnet_session=token; HttpOnly; Path=/; Secure; SameSite=Lax; Domain=krumpled.com; Max-Age=60"
+    );
+    assert!(!cookies[1].contains("HttpOnly"));
+    assert!(clear(&config)[0].contains("Max-Age=0"));
+  }
+}
@@ -0,0 +1,8 @@
+update
+  krumnet.users as users
+set
+  avatar_url = $2
+where
+  users.id = $1
+and
+  users.avatar_url is null;
@@ -0,0 +1,6 @@
+insert into krumnet.identities
+  (user_id, provider, subject, email, email_verified, name)
+values
+  ($1, $2, $3, $4, $5, $6)
+on conflict (provider, subject) do nothing
+returning id;
@@ -1,11 +1,22 @@
-with new_user as (
+with generated as (
+    select uuid_generate_v4()::varchar as id
+), new_user as (
     insert into krumnet.users
-      (default_email, name)
-    values
-      ($1, $2)
-    returning id 
-) insert into krumnet.google_accounts
-    (email, name, google_id, user_id)
+      (id, default_email, name, avatar_url)
+    select
+      generated.id,
+      case
+        when exists (select 1 from krumnet.users as users where users.default_email = $1)
+        then generated.id || '@' || $7
+        else $1
+      end,
+      $2,
+      nullif($6, '')
+    from generated
+    returning id
+) insert into krumnet.identities
+    (user_id, provider, subject, email, email_verified, name)
   select
-    $3, $4, $5, new_user.id
-  from new_user;
+    new_user.id, $3, $4, $1, $5, $2
+  from new_user
+  returning user_id;
@@ -0,0 +1,8 @@
+insert into krumnet.users
+  (default_email, name)
+values
+  ($1, $2)
+on conflict (default_email) do update
+  set name = excluded.name
+returning
+  id as user_id;
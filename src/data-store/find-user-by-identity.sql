@@ -0,0 +1,13 @@
+select
+  users.id as user_id
+from
+  krumnet.users as users
+inner join
+  krumnet.identities as identities
+on
+  identities.user_id = users.id
+where
+  identities.provider = $1
+and
+  identities.subject = $2
+limit 1;
@@ -0,0 +1,11 @@
+select
+  identities.user_id as user_id
+from
+  krumnet.identities as identities
+where
+  identities.email = $1
+and
+  identities.email_verified = true
+order by
+  identities.created_at asc
+limit 1;
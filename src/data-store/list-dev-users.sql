@@ -0,0 +1,10 @@
+select
+  users.id   as user_id,
+  users.name as user_name
+from
+  krumnet.users as users
+where
+  users.default_email like $1
+order by
+  users.created_at desc
+limit 50;
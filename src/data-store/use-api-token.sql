@@ -0,0 +1,20 @@
+update
+  krumnet.api_tokens as tokens
+set
+  last_used_at = now()
+from
+  krumnet.users as users
+where
+  tokens.token_hash = $1
+and
+  tokens.revoked_at is null
+and
+  (tokens.expires_at is null or tokens.expires_at > now())
+and
+  users.id = tokens.user_id
+and
+  users.deleted_at is null
+returning
+  tokens.user_id as user_id,
+  tokens.scopes  as scopes,
+  users.role     as user_role;
@@ -1,9 +1,12 @@
 select
   id            as user_id,
   name          as user_name,
-  default_email as user_email
+  default_email as user_email,
+  role          as user_role
 from
   krumnet.users as users
 where
   users.id = $1
+and
+  users.deleted_at is null
 limit 1
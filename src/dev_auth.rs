@@ -0,0 +1,173 @@
+use async_std::io::Read as AsyncRead;
+use log::{debug, info, warn};
+use sqlx::query_file;
+use std::io::Result;
+use std::marker::Unpin;
+
+use crate::constants::{DEV_AUTH_EMAIL_DOMAIN, DEV_AUTH_MAX_NAME_LENGTH};
+use crate::http::query as qs;
+use crate::oauth::session_response;
+use crate::{errors, read_size_async, Context, Response};
+
+const INVALID_NAME: &'static str = "errors.dev_auth.invalid_name";
+
+// Turns the name provided on the login form into the slug used to build the user's email address.
+// Names are case-insensitive and spaces become dashes; anything other than ascii alphanumerics,
+// spaces and dashes is rejected outright.
+fn slug(name: &str) -> Option<String> {
+  let trimmed = name.trim();
+
+  if trimmed.is_empty() || trimmed.len() > DEV_AUTH_MAX_NAME_LENGTH {
+    return None;
+  }
+
+  let valid = trimmed
+    .chars()
+    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == ' ');
+
+  if !valid {
+    return None;
+  }
+
+  Some(trimmed.to_lowercase().replace(' ', "-"))
+}
+
+fn escape(input: &str) -> String {
+  input
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+async fn find_or_create_user(context: &Context, name: &str) -> Result<String> {
+  let slugged = slug(name).ok_or_else(|| errors::e(format!("invalid dev name '{}'", name)))?;
+  let email = format!("{}@{}", slugged, DEV_AUTH_EMAIL_DOMAIN);
+  let mut conn = context.records_connection().await?;
+
+  query_file!(
+    "src/data-store/find-or-create-dev-user.sql",
+    email,
+    name.trim()
+  )
+  .fetch_all(&mut conn)
+  .await
+  .map_err(errors::humanize_error)?
+  .into_iter()
+  .nth(0)
+  .map(|row| row.user_id)
+  .ok_or_else(|| errors::e(format!("Unable to find or create dev user '{}'", email)))
+}
+
+async fn existing_users(context: &Context) -> Result<Vec<String>> {
+  let mut conn = context.records_connection().await?;
+
+  query_file!(
+    "src/data-store/list-dev-users.sql",
+    format!("%@{}", DEV_AUTH_EMAIL_DOMAIN)
+  )
+  .fetch_all(&mut conn)
+  .await
+  .map_err(errors::humanize_error)
+  .map(|rows| rows.into_iter().map(|row| row.user_name).collect())
+}
+
+fn render_form(names: &[String]) -> String {
+  let existing = names
+    .iter()
+    .map(|name| {
+      format!(
+        "<li><form method=\"post\" action=\"/auth/dev\"><input type=\"hidden\" name=\"name\" value=\"{0}\" /><button type=\"submit\">{0}</button></form></li>",
+        escape(name)
+      )
+    })
+    .collect::<String>();
+
+  format!(
+    "<!doctype html><html><head><title>krumnet dev login</title></head><body>\
+     <h1>krumnet dev login</h1>\
+     <form method=\"post\" action=\"/auth/dev\">\
+     <input type=\"text\" name=\"name\" maxlength=\"{}\" placeholder=\"name\" autofocus />\
+     <button type=\"submit\">sign in</button>\
+     </form><ul>{}</ul></body></html>",
+    DEV_AUTH_MAX_NAME_LENGTH, existing
+  )
+}
+
+// Route
+// GET /auth/dev
+pub async fn form(context: &Context) -> Result<Response> {
+  if !context.config().dev_auth.enabled {
+    return Ok(Response::not_found().cors(context.cors()));
+  }
+
+  let names = existing_users(context).await?;
+  debug!(
+    "rendering dev login form with {} existing users",
+    names.len()
+  );
+  Ok(Response::ok_html(render_form(&names)).cors(context.cors()))
+}
+
+// Route
+// POST /auth/dev
+pub async fn login<R>(context: &Context, reader: &mut R) -> Result<Response>
+where
+  R: AsyncRead + Unpin,
+{
+  if !context.config().dev_auth.enabled {
+    return Ok(Response::not_found().cors(context.cors()));
+  }
+
+  let contents = read_size_async(reader, context.pending()).await?;
+  let name = qs::parse(&contents)
+    .find(|(key, _)| key == "name")
+    .map(|(_, value)| String::from(value))
+    .unwrap_or_default();
+
+  if slug(&name).is_none() {
+    warn!("rejecting dev login for invalid name '{}'", name);
+    return Ok(Response::bad_request(INVALID_NAME).cors(context.cors()));
+  }
+
+  let uid = find_or_create_user(context, &name).await?;
+  let token = context.session().create(&uid).await?;
+  info!("created dev session for user '{}'", uid);
+
+  session_response(context, &token)
+}
+
+#[cfg(test)]
+mod test {
+  use super::{find_or_create_user, slug};
+  use crate::context::test_helpers as context_helpers;
+  use crate::Authority;
+  use async_std::task::block_on;
+
+  #[test]
+  fn slug_valid() {
+    assert_eq!(slug(" Danny Hadley "), Some(String::from("danny-hadley")));
+  }
+
+  #[test]
+  fn slug_invalid() {
+    assert_eq!(slug(""), None);
+    assert_eq!(slug("<script>"), None);
+    assert_eq!(slug(&"a".repeat(33)), None);
+  }
+
+  #[test]
+  fn find_or_create_is_idempotent() {
+    let ctx = context_helpers::with_auth(Authority::None);
+    block_on(async {
+      let first = find_or_create_user(&ctx, "dev auth idempotent")
+        .await
+        .unwrap();
+      let second = find_or_create_user(&ctx, "Dev Auth Idempotent")
+        .await
+        .unwrap();
+      assert_eq!(first, second);
+      context_helpers::cleanup_user(&first).await;
+    });
+  }
+}
@@ -3,17 +3,17 @@ extern crate http;
 use async_std::io::{timeout, Read};
 use async_std::prelude::*;
 use http::header::{
-  HeaderName, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
-  ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_HEADERS, CONTENT_LENGTH, CONTENT_TYPE,
-  LOCATION,
+  HeaderName, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+  ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_HEADERS,
+  CONTENT_LENGTH, CONTENT_TYPE, LOCATION, SET_COOKIE,
 };
 use log::{debug, info};
 use std::io::{Error, ErrorKind, Result};
 use std::marker::Unpin;
 use std::time::Duration;
 
-use crate::constants::MAX_FILE_SIZE;
-pub use http::header::AUTHORIZATION;
+use crate::constants::{CSRF_HEADER_NAME, MAX_FILE_SIZE};
+pub use http::header::{AUTHORIZATION, COOKIE};
 pub use http::{header, Method, Request, StatusCode, Uri};
 pub use url::form_urlencoded as query;
 pub use url::Url;
@@ -100,6 +100,16 @@ impl Response {
     Ok(Response(StatusCode::OK, header_map, Payload::String(vec)))
   }
 
+  pub fn ok_html<S: std::fmt::Display>(html: S) -> Self {
+    let mut header_map = HeaderMap::default();
+    header_map.push((CONTENT_TYPE, "text/html; charset=utf-8".to_string()));
+    Response(
+      StatusCode::OK,
+      header_map,
+      Payload::String(format!("{}", html)),
+    )
+  }
+
   pub fn bad_request<S: std::fmt::Display>(reason: S) -> Self {
     let mut header_map = HeaderMap::default();
     header_map.push((CONTENT_TYPE, "text/plain; charset=utf-8".to_string()));
@@ -126,6 +136,20 @@ impl Response {
     )
   }
 
+  pub fn forbidden() -> Self {
+    Response(StatusCode::FORBIDDEN, HeaderMap::default(), Payload::Empty)
+  }
+
+  pub fn forbidden_reason<S: std::fmt::Display>(reason: S) -> Self {
+    let mut header_map = HeaderMap::default();
+    header_map.push((CONTENT_TYPE, "text/plain; charset=utf-8".to_string()));
+    Response(
+      StatusCode::FORBIDDEN,
+      header_map,
+      Payload::String(format!("{}", reason)),
+    )
+  }
+
   pub fn not_found() -> Self {
     Response(StatusCode::NOT_FOUND, HeaderMap::default(), Payload::Empty)
   }
@@ -136,6 +160,16 @@ impl Response {
     Response(StatusCode::TEMPORARY_REDIRECT, header_map, Payload::Empty)
   }
 
+  pub fn status(&self) -> StatusCode {
+    self.0
+  }
+
+  pub fn cookie(self, value: String) -> Self {
+    let Response(code, mut header_map, body) = self;
+    header_map.push((SET_COOKIE, value));
+    Response(code, header_map, body)
+  }
+
   pub fn cors(self, origin: String) -> Self {
     let Response(code, mut header_map, body) = self;
 
@@ -143,12 +177,13 @@ impl Response {
     header_map.push((ACCESS_CONTROL_ALLOW_ORIGIN, origin));
     header_map.push((
       ACCESS_CONTROL_ALLOW_HEADERS,
-      format!("{}, {}", AUTHORIZATION, CONTENT_TYPE),
+      format!("{}, {}, {}", AUTHORIZATION, CONTENT_TYPE, CSRF_HEADER_NAME),
     ));
+    header_map.push((ACCESS_CONTROL_ALLOW_CREDENTIALS, "true".to_string()));
     header_map.push((ACCESS_CONTROL_REQUEST_HEADERS, CONTENT_TYPE.to_string()));
     header_map.push((
       ACCESS_CONTROL_ALLOW_METHODS,
-      "POST, GET, PUT, DELETE".to_string(),
+      "POST, GET, PUT, PATCH, DELETE".to_string(),
     ));
 
     Response(code, header_map, body)
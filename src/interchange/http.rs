@@ -1,5 +1,4 @@
-use crate::interchange::jobs;
-use crate::interchange::jobs::{Job, QueuedJob};
+use crate::interchange::jobs::{CheckRoundCompletionResult, Job, JobStatus, QueuedJob};
 use chrono::{DateTime, Utc};
 use serde::Serialize;
 pub use sqlx::FromRow;
@@ -23,6 +22,7 @@ pub struct GameRoundEntry {
   pub created: DateTime<Utc>,
   pub user_id: String,
   pub user_name: String,
+  pub user_avatar: Option<String>,
 }
 
 #[derive(Debug, Serialize, FromRow)]
@@ -31,6 +31,7 @@ pub struct GameRoundVote {
   pub id: String,
   pub member_id: String,
   pub user_id: String,
+  pub user_avatar: Option<String>,
   pub entry_id: String,
   #[serde(with = "chrono::serde::ts_milliseconds_option")]
   pub created: Option<DateTime<Utc>>,
@@ -104,6 +105,7 @@ pub struct GameDetailPlacement {
   pub id: String,
   pub user_name: String,
   pub user_id: String,
+  pub user_avatar: Option<String>,
   pub place: i32,
   pub vote_count: i32,
 }
@@ -141,6 +143,7 @@ pub struct GameRoundPlacement {
   pub id: String,
   pub user_name: String,
   pub user_id: String,
+  pub user_avatar: Option<String>,
   pub place: i32,
 }
 
@@ -168,8 +171,37 @@ pub struct LobbyDetails {
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "snake_case", tag = "kind", content = "data")]
 pub enum JobResult {
-  NewLobby { id: String },
-  NewGame { id: String },
+  NewLobby {
+    id: String,
+  },
+  NewGame {
+    id: String,
+  },
+  MergedUser {
+    id: String,
+  },
+  UserExport {
+    archive: serde_json::Value,
+  },
+  RoundFulfillment {
+    round_id: String,
+    remaining: u8,
+    next_round_id: Option<String>,
+  },
+  RoundCompletion {
+    round_id: String,
+    result: CheckRoundCompletionResult,
+  },
+  LobbyMembershipCleanup {
+    lobby_id: String,
+    member_id: String,
+    lobby_closed: bool,
+  },
+  GameMembershipCleanup {
+    game_id: String,
+    member_id: String,
+    round_ids: Vec<String>,
+  },
   Nothing,
 }
 
@@ -187,40 +219,76 @@ pub struct JobHandle {
   pub result: Option<WrappedJobResult>,
 }
 
-fn without_result(id: String) -> JobHandle {
-  JobHandle {
-    id,
-    result: Some(WrappedJobResult::Success(JobResult::Nothing)),
-  }
+// Where a job is in its lifecycle, returned when a client polls for it.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct JobDetails {
+  pub id: String,
+  pub status: JobStatus,
+  pub attempts: u32,
+  pub last_error: Option<String>,
+  pub worker: Option<String>,
+  #[serde(with = "chrono::serde::ts_milliseconds_option")]
+  pub queued: Option<DateTime<Utc>>,
+  #[serde(with = "chrono::serde::ts_milliseconds_option")]
+  pub started: Option<DateTime<Utc>>,
+  #[serde(with = "chrono::serde::ts_milliseconds_option")]
+  pub finished: Option<DateTime<Utc>>,
+  pub result: Option<WrappedJobResult>,
 }
 
-impl From<QueuedJob> for JobHandle {
-  fn from(job: QueuedJob) -> Self {
-    let id = job.id.clone();
-
-    match job.job {
-      Job::CreateGame(jobs::CreateGame { result, .. }) => {
-        let result = result.map(|res| match res {
-          Ok(id) => WrappedJobResult::Success(JobResult::NewGame { id }),
-          Err(e) => WrappedJobResult::Failure(e),
-        });
-        JobHandle { id, result }
-      }
-      Job::CreateLobby(jobs::CreateLobby { creator: _, result }) => {
-        let result = result.map(|res| match res {
-          Ok(id) => WrappedJobResult::Success(JobResult::NewLobby { id }),
-          Err(e) => WrappedJobResult::Failure(e),
-        });
-        JobHandle { id, result }
-      }
-      Job::CheckRoundFulfillment { .. }
-      | Job::CleanupLobbyMembership { .. }
-      | Job::CheckRoundCompletion(_)
-      | Job::CleanupGameMembership { .. } => without_result(id),
+impl JobDetails {
+  // The result is given by the handler registered for the job's kind.
+  pub fn new(job: QueuedJob, result: Option<WrappedJobResult>) -> Self {
+    JobDetails {
+      id: job.id.clone(),
+      status: job.status,
+      attempts: job.attempts,
+      last_error: job.last_error.clone(),
+      worker: job.worker.clone(),
+      queued: job.queued_at.map(DateTime::from),
+      started: job.started_at.map(DateTime::from),
+      finished: job.finished_at.map(DateTime::from),
+      result,
     }
   }
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct JobList {
+  pub jobs: Vec<JobDetails>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ApiToken {
+  pub id: String,
+  pub name: String,
+  pub scopes: Vec<String>,
+  #[serde(with = "chrono::serde::ts_milliseconds_option")]
+  pub created: Option<DateTime<Utc>>,
+  #[serde(with = "chrono::serde::ts_milliseconds_option")]
+  pub expires: Option<DateTime<Utc>>,
+  #[serde(with = "chrono::serde::ts_milliseconds_option")]
+  pub last_used: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ApiTokenList {
+  pub tokens: Vec<ApiToken>,
+}
+
+// Returned only once, when the token is minted. The raw token is never persisted and can not be
+// retrieved again.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct NewApiToken {
+  pub token: String,
+  pub details: ApiToken,
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct SessionUserData {
@@ -229,6 +297,51 @@ pub struct SessionUserData {
   pub name: String,
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct UserProfile {
+  pub id: String,
+  pub name: String,
+  pub display_name: Option<String>,
+  pub avatar_url: Option<String>,
+  pub pronouns: Option<String>,
+  pub timezone: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct AdminUser {
+  pub id: String,
+  pub name: String,
+  pub email: String,
+  pub role: String,
+  pub providers: Vec<String>,
+  #[serde(with = "chrono::serde::ts_milliseconds_option")]
+  pub created: Option<DateTime<Utc>>,
+  #[serde(with = "chrono::serde::ts_milliseconds_option")]
+  pub deleted: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct AdminDeadLetter {
+  pub id: String,
+  pub attempts: u32,
+  pub last_error: Option<String>,
+  pub job: Job,
+}
+
+impl From<QueuedJob> for AdminDeadLetter {
+  fn from(queued: QueuedJob) -> Self {
+    AdminDeadLetter {
+      id: queued.id,
+      attempts: queued.attempts,
+      last_error: queued.last_error,
+      job: queued.job,
+    }
+  }
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct SessionData {
@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 // Queued when a user leaves a lobby or game explicitly, jobs of this kind will attempt to create
 // game round entries for any rounds that do not already have one. On success, the job's result
@@ -21,7 +21,16 @@ pub struct CleanupGameMembership {
 #[serde(rename_all = "snake_case")]
 pub struct CheckRoundFulfillment {
   pub round_id: String,
-  pub result: Option<Result<u8, String>>,
+  pub result: Option<Result<CheckRoundFulfillmentResult, String>>,
+}
+
+// Untagged so that results saved as a bare count of missing entries, before the round id was kept,
+// can still be read back.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum CheckRoundFulfillmentResult {
+  Remaining(u8),
+  Fulfilled { next_round_id: Option<String> },
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
@@ -66,6 +75,37 @@ pub struct CreateLobby {
   pub result: Option<Result<String, String>>,
 }
 
+// Queued when two users have been determined to belong to the same person, jobs of this kind will
+// move every identity, membership and result from the source user onto the target user and then
+// remove the source user. On success, the job's result will be populated with the target user id.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct MergeUsers {
+  pub source: String,
+  pub target: String,
+  pub result: Option<Result<String, String>>,
+}
+
+// Queued when a user requests a download of their data, jobs of this kind will collect the user's
+// lobbies, games, entries, votes and placements into a single json archive that is returned as the
+// job's result.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct ExportUserData {
+  pub user_id: String,
+  pub result: Option<Result<serde_json::Value, String>>,
+}
+
+// Queued when a user requests their account be deleted, jobs of this kind will remove the user from
+// any lobbies they are still in, clear the text of their entries and anonymize the user record.
+// Memberships, votes and placements are kept so that other players' game results remain intact.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct DeleteUser {
+  pub user_id: String,
+  pub result: Option<Result<String, String>>,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "snake_case", tag = "t", content = "c")]
 pub enum Job {
@@ -75,20 +115,86 @@ pub enum Job {
   CleanupLobbyMembership(CleanupLobbyMembership),
   CheckRoundCompletion(CheckRoundCompletion),
   CleanupGameMembership(CleanupGameMembership),
+  MergeUsers(MergeUsers),
+  ExportUserData(ExportUserData),
+  DeleteUser(DeleteUser),
+}
+
+impl Job {
+  // The tag the job is serialized with, used to configure per-kind worker limits.
+  pub fn kind(&self) -> &'static str {
+    match self {
+      Job::CreateLobby(_) => "create_lobby",
+      Job::CheckRoundFulfillment(_) => "check_round_fulfillment",
+      Job::CreateGame(_) => "create_game",
+      Job::CleanupLobbyMembership(_) => "cleanup_lobby_membership",
+      Job::CheckRoundCompletion(_) => "check_round_completion",
+      Job::CleanupGameMembership(_) => "cleanup_game_membership",
+      Job::MergeUsers(_) => "merge_users",
+      Job::ExportUserData(_) => "export_user_data",
+      Job::DeleteUser(_) => "delete_user",
+    }
+  }
 }
 
+// Written when a worker takes a job off the queue and refreshed by the worker while the job runs.
+// A job whose time falls too far behind is assumed to belong to a worker that has gone away.
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 pub struct DequeuedJob {
   pub id: String,
   pub time: SystemTime,
+  #[serde(default)]
+  pub worker: String,
 }
 
 impl DequeuedJob {
-  pub fn new(id: &String) -> Self {
+  pub fn new(id: &str, worker: &str) -> Self {
     DequeuedJob {
-      id: id.clone(),
+      id: String::from(id),
       time: SystemTime::now(),
+      worker: String::from(worker),
+    }
+  }
+
+  pub fn expired(&self, timeout: Duration) -> bool {
+    self
+      .time
+      .elapsed()
+      .map(|elapsed| elapsed >= timeout)
+      .unwrap_or(false)
+  }
+}
+
+// Jobs waiting to be retried are `Queued` again. Failed jobs failed in a way that retrying would
+// not fix, while dead jobs ran out of attempts and sit on the dead-letter list until replayed.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+  #[default]
+  Queued,
+  Running,
+  Succeeded,
+  Failed,
+  Dead,
+}
+
+impl JobStatus {
+  // Finished jobs are only kept around for their results and are expired once those are stale.
+  // Dead jobs are not finished; they are kept until someone replays them.
+  pub fn is_finished(&self) -> bool {
+    match self {
+      JobStatus::Succeeded | JobStatus::Failed => true,
+      JobStatus::Queued | JobStatus::Running | JobStatus::Dead => false,
+    }
+  }
+
+  // Nothing more happens to finished or dead jobs unless someone steps in, so clients waiting on a
+  // job stop waiting once it gets here.
+  pub fn is_settled(&self) -> bool {
+    match self {
+      JobStatus::Succeeded | JobStatus::Failed | JobStatus::Dead => true,
+      JobStatus::Queued | JobStatus::Running => false,
     }
   }
 }
@@ -98,17 +204,55 @@ impl DequeuedJob {
 pub struct QueuedJob {
   pub id: String,
   pub job: Job,
+  // The number of times the job has failed in a way that was worth retrying, along with the reason
+  // given by the most recent failure.
+  #[serde(default)]
+  pub attempts: u32,
+  #[serde(default)]
+  pub last_error: Option<String>,
+  #[serde(default)]
+  pub status: JobStatus,
+  // The worker that most recently took the job off the queue.
+  #[serde(default)]
+  pub worker: Option<String>,
+  #[serde(default)]
+  pub queued_at: Option<SystemTime>,
+  #[serde(default)]
+  pub started_at: Option<SystemTime>,
+  #[serde(default)]
+  pub finished_at: Option<SystemTime>,
 }
 
 impl QueuedJob {
-  pub fn user(&self) -> Option<String> {
-    match &self.job {
-      Job::CreateLobby(CreateLobby { creator, .. }) => Some(creator.clone()),
-      Job::CreateGame(CreateGame { creator, .. }) => Some(creator.clone()),
-      Job::CheckRoundFulfillment { .. }
-      | Job::CleanupLobbyMembership { .. }
-      | Job::CheckRoundCompletion(_)
-      | Job::CleanupGameMembership(_) => None,
+  pub fn new(id: &str, job: Job) -> Self {
+    QueuedJob {
+      id: String::from(id),
+      job,
+      attempts: 0,
+      last_error: None,
+      status: JobStatus::Queued,
+      worker: None,
+      queued_at: Some(SystemTime::now()),
+      started_at: None,
+      finished_at: None,
     }
   }
+
+  // Returns a copy of the job moved to the status, stamping the time it started or finished.
+  pub fn with_status(&self, status: JobStatus) -> Self {
+    let mut job = self.clone();
+    let now = Some(SystemTime::now());
+
+    match status {
+      JobStatus::Queued => job.finished_at = None,
+      JobStatus::Running => {
+        job.started_at = now;
+        job.finished_at = None;
+      }
+      JobStatus::Succeeded | JobStatus::Failed | JobStatus::Dead => job.finished_at = now,
+    }
+
+    job.status = status;
+    job
+  }
 }
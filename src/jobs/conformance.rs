@@ -0,0 +1,581 @@
+// Behavior expected of every job store, run against each implementation.
+use async_std::sync::Arc;
+use async_std::task::{block_on, sleep, spawn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use uuid::Uuid;
+
+use sqlx::postgres::PgPool;
+use sqlx::Connection;
+
+use super::{
+  commit_queued, commit_queued_all, JobStore, MemoryJobStore, PostgresJobStore, RedisJobStore,
+};
+use crate::context::test_helpers::load_config;
+use crate::interchange::jobs::{CheckRoundFulfillment, CreateLobby, Job, JobStatus, QueuedJob};
+
+fn create_lobby(creator: &str) -> Job {
+  Job::CreateLobby(CreateLobby {
+    creator: String::from(creator),
+    result: None,
+  })
+}
+
+fn fulfillment(round_id: &str) -> Job {
+  Job::CheckRoundFulfillment(CheckRoundFulfillment {
+    round_id: String::from(round_id),
+    result: None,
+  })
+}
+
+fn result(queued: &QueuedJob) -> Option<Result<String, String>> {
+  match &queued.job {
+    Job::CreateLobby(details) => details.result.clone(),
+    _ => None,
+  }
+}
+
+fn listed(jobs: Vec<QueuedJob>) -> Vec<String> {
+  jobs.into_iter().map(|job| job.id).collect()
+}
+
+async fn jobs(store: &dyn JobStore) {
+  let first = store.queue(&create_lobby("first")).await.unwrap();
+  let second = store.queue(&create_lobby("second")).await.unwrap();
+  assert_ne!(first, second);
+
+  let found = store.lookup(&first).await.unwrap().unwrap();
+  assert_eq!(found.id, first);
+  assert_eq!(found.job, create_lobby("first"));
+
+  assert_eq!(store.dequeue().await.unwrap().unwrap().id, first);
+  assert_eq!(store.dequeue().await.unwrap().unwrap().id, second);
+  assert!(store.dequeue().await.unwrap().is_none());
+
+  let finished = QueuedJob::new(
+    &first,
+    Job::CreateLobby(CreateLobby {
+      creator: String::from("first"),
+      result: Some(Ok(String::from("lobby"))),
+    }),
+  );
+  assert_eq!(store.update(&first, &finished).await.unwrap(), first);
+  let updated = store.lookup(&first).await.unwrap().unwrap();
+  assert_eq!(result(&updated), Some(Ok(String::from("lobby"))));
+
+  store.ack(&first).await.unwrap();
+
+  // Only the unacknowledged job is put back on the queue once it times out.
+  let patience = Duration::from_secs(60);
+  store.heartbeat(&second).await.unwrap();
+  assert!(store.reap(patience).await.unwrap().is_empty());
+  assert_eq!(
+    store.reap(Duration::from_secs(0)).await.unwrap(),
+    vec![second.clone()]
+  );
+  assert!(store.reap(Duration::from_secs(0)).await.unwrap().is_empty());
+  assert_eq!(store.dequeue().await.unwrap().unwrap().id, second);
+  store.ack(&second).await.unwrap();
+  assert!(store.reap(Duration::from_secs(0)).await.unwrap().is_empty());
+
+  assert_eq!(store.requeue(&first).await.unwrap(), Some(first.clone()));
+  let reset = store.lookup(&first).await.unwrap().unwrap();
+  assert_eq!(result(&reset), None);
+  assert_eq!(store.dequeue().await.unwrap().unwrap().id, first);
+  store.ack(&first).await.unwrap();
+
+  let missing = Uuid::new_v4().to_string();
+  assert!(store.lookup(&missing).await.unwrap().is_none());
+  assert!(store.requeue(&missing).await.unwrap().is_none());
+}
+
+async fn retries(store: &dyn JobStore) {
+  let id = store.queue(&create_lobby("retried")).await.unwrap();
+  let dequeued = store.dequeue().await.unwrap().unwrap();
+  assert_eq!(dequeued.attempts, 0);
+
+  let failed = QueuedJob {
+    attempts: 1,
+    last_error: Some(String::from("unavailable")),
+    ..dequeued
+  };
+
+  // Retried jobs wait out their delay before they are queued again.
+  store.retry(&failed, Duration::from_secs(60)).await.unwrap();
+  assert!(store.promote().await.unwrap().is_empty());
+  assert!(store.reap(Duration::from_secs(0)).await.unwrap().is_empty());
+  assert_eq!(store.requeue(&id).await.unwrap(), Some(id.clone()));
+  assert!(store.promote().await.unwrap().is_empty());
+  let dequeued = store.dequeue().await.unwrap().unwrap();
+  assert_eq!(dequeued.attempts, 0);
+
+  store.retry(&failed, Duration::from_secs(0)).await.unwrap();
+  assert_eq!(store.promote().await.unwrap(), vec![id.clone()]);
+  assert!(store.promote().await.unwrap().is_empty());
+
+  let retried = store.dequeue().await.unwrap().unwrap();
+  assert_eq!(retried.attempts, 1);
+  assert_eq!(retried.last_error, Some(String::from("unavailable")));
+
+  // Buried jobs stay on the dead-letter list until they are replayed.
+  store.bury(&retried).await.unwrap();
+  assert!(store.dequeue().await.unwrap().is_none());
+  assert!(store.reap(Duration::from_secs(0)).await.unwrap().is_empty());
+
+  let dead = store.dead_letters().await.unwrap();
+  assert_eq!(dead.len(), 1);
+  assert_eq!(dead[0].id, id);
+  assert_eq!(dead[0].attempts, 1);
+  assert_eq!(dead[0].status, JobStatus::Dead);
+  assert!(!store
+    .expire(Duration::from_secs(0))
+    .await
+    .unwrap()
+    .contains(&id));
+
+  assert_eq!(store.replay(&id).await.unwrap(), Some(id.clone()));
+  assert!(store.replay(&id).await.unwrap().is_none());
+  assert!(store.dead_letters().await.unwrap().is_empty());
+
+  let replayed = store.dequeue().await.unwrap().unwrap();
+  assert_eq!(replayed.id, id);
+  assert_eq!(replayed.attempts, 0);
+  assert_eq!(replayed.last_error, None);
+  store.ack(&id).await.unwrap();
+}
+
+async fn scheduling(store: &dyn JobStore) {
+  let later = store
+    .queue_in(&create_lobby("later"), Duration::from_secs(60))
+    .await
+    .unwrap();
+  assert!(store.lookup(&later).await.unwrap().is_some());
+  assert!(store.promote().await.unwrap().is_empty());
+  assert!(store.dequeue().await.unwrap().is_none());
+
+  assert!(store.cancel(&later).await.unwrap());
+  assert!(!store.cancel(&later).await.unwrap());
+  assert!(store.lookup(&later).await.unwrap().is_none());
+
+  let past = SystemTime::now() - Duration::from_secs(1);
+  let due = store.queue_at(&create_lobby("due"), past).await.unwrap();
+  assert_eq!(store.promote().await.unwrap(), vec![due.clone()]);
+  assert_eq!(store.dequeue().await.unwrap().unwrap().id, due);
+
+  // Jobs that are already being worked on can no longer be cancelled.
+  assert!(!store.cancel(&due).await.unwrap());
+  store.ack(&due).await.unwrap();
+
+  let queued = store.queue(&create_lobby("queued")).await.unwrap();
+  assert!(store.cancel(&queued).await.unwrap());
+  assert!(store.dequeue().await.unwrap().is_none());
+}
+
+async fn lifecycle(store: &dyn JobStore) {
+  let id = store.queue(&create_lobby("lifecycle")).await.unwrap();
+  let queued = store.lookup(&id).await.unwrap().unwrap();
+  assert_eq!(queued.status, JobStatus::Queued);
+  assert!(queued.queued_at.is_some());
+  assert!(queued.started_at.is_none());
+
+  let dequeued = store.dequeue().await.unwrap().unwrap();
+  let running = store.lookup(&id).await.unwrap().unwrap();
+  assert_eq!(running.status, JobStatus::Running);
+  assert_eq!(running.worker, dequeued.worker);
+  assert!(running.worker.is_some());
+  assert!(running.started_at.is_some());
+
+  // Only finished jobs are expired, once they are older than the retention period.
+  assert!(!store
+    .expire(Duration::from_secs(0))
+    .await
+    .unwrap()
+    .contains(&id));
+
+  let succeeded = dequeued.with_status(JobStatus::Succeeded);
+  assert!(succeeded.finished_at.is_some());
+  store.update(&id, &succeeded).await.unwrap();
+  store.ack(&id).await.unwrap();
+
+  assert!(store
+    .expire(Duration::from_secs(60))
+    .await
+    .unwrap()
+    .is_empty());
+  assert_eq!(
+    store.expire(Duration::from_secs(0)).await.unwrap(),
+    vec![id.clone()]
+  );
+  assert!(store.lookup(&id).await.unwrap().is_none());
+}
+
+async fn coalescing(store: &dyn JobStore) {
+  let round = Uuid::new_v4().to_string();
+  let first = store.queue(&fulfillment(&round)).await.unwrap();
+  assert_eq!(store.queue(&fulfillment(&round)).await.unwrap(), first);
+
+  let other = store.queue(&fulfillment("other")).await.unwrap();
+  assert_ne!(other, first);
+
+  // Asking for a running job again flags it to run once more rather than queueing a duplicate.
+  let running = store.dequeue().await.unwrap().unwrap();
+  assert_eq!(running.id, first);
+  assert_eq!(store.queue(&fulfillment(&round)).await.unwrap(), first);
+  assert_eq!(store.queue(&fulfillment(&round)).await.unwrap(), first);
+  assert_eq!(store.dequeue().await.unwrap().unwrap().id, other);
+  store.ack(&other).await.unwrap();
+  assert!(store.dequeue().await.unwrap().is_none());
+
+  let succeeded = running.with_status(JobStatus::Succeeded);
+  store.update(&first, &succeeded).await.unwrap();
+  store.ack(&first).await.unwrap();
+
+  let rerun = store.dequeue().await.unwrap().unwrap();
+  assert_ne!(rerun.id, first);
+  assert_eq!(rerun.job, fulfillment(&round));
+  store
+    .update(&rerun.id, &rerun.with_status(JobStatus::Succeeded))
+    .await
+    .unwrap();
+  store.ack(&rerun.id).await.unwrap();
+  assert!(store.dequeue().await.unwrap().is_none());
+
+  // Finished jobs are never coalesced into.
+  let fresh = store.queue(&fulfillment(&round)).await.unwrap();
+  assert!(fresh != first && fresh != rerun.id);
+  assert!(store.cancel(&fresh).await.unwrap());
+  let replacement = store.queue(&fulfillment(&round)).await.unwrap();
+  assert_ne!(replacement, fresh);
+  assert!(store.cancel(&replacement).await.unwrap());
+}
+
+// Asking for the same job from several places at once still queues it only once.
+async fn racing(store: Arc<dyn JobStore>) {
+  let round = Uuid::new_v4().to_string();
+
+  let voters = (0..8)
+    .map(|_| {
+      let (voter, round) = (store.clone(), round.clone());
+      spawn(async move { voter.queue(&fulfillment(&round)).await.unwrap() })
+    })
+    .collect::<Vec<_>>();
+
+  let mut ids = Vec::new();
+
+  for voter in voters {
+    ids.push(voter.await);
+  }
+
+  ids.dedup();
+  assert_eq!(ids.len(), 1);
+
+  let waiting = listed(store.waiting().await.unwrap());
+  assert_eq!(
+    waiting.iter().filter(|waiting| **waiting == ids[0]).count(),
+    1
+  );
+  assert!(store.cancel(&ids[0]).await.unwrap());
+}
+
+// Workers saving the jobs they run do not keep coalesced jobs from being queued.
+async fn contending(store: Arc<dyn JobStore>) {
+  let id = store.queue(&create_lobby("busy")).await.unwrap();
+  let running = store.dequeue().await.unwrap().unwrap();
+  let done = Arc::new(AtomicBool::new(false));
+  let (worker, finished) = (store.clone(), done.clone());
+
+  let updates = spawn(async move {
+    while !finished.load(Ordering::SeqCst) {
+      worker.update(&id, &running).await.unwrap();
+      sleep(Duration::from_millis(1)).await;
+    }
+
+    worker.ack(&id).await.unwrap();
+  });
+
+  let voters = (0..20)
+    .map(|_| {
+      let voter = store.clone();
+      spawn(async move {
+        let round = Uuid::new_v4().to_string();
+        voter.queue(&fulfillment(&round)).await
+      })
+    })
+    .collect::<Vec<_>>();
+
+  let mut ids = Vec::new();
+
+  for voter in voters {
+    ids.push(voter.await.unwrap());
+  }
+
+  done.store(true, Ordering::SeqCst);
+  updates.await;
+
+  for id in ids {
+    assert!(store.cancel(&id).await.unwrap());
+  }
+}
+
+async fn batches(store: &dyn JobStore) {
+  let round = Uuid::new_v4().to_string();
+  let jobs = vec![
+    create_lobby("batched"),
+    fulfillment(&round),
+    fulfillment(&round),
+    create_lobby("also batched"),
+  ];
+
+  let ids = store.queue_all(&jobs).await.unwrap();
+  assert_eq!(ids.len(), 4);
+  assert_eq!(ids[1], ids[2]);
+  assert!(store.queue_all(&[]).await.unwrap().is_empty());
+
+  for (index, expected) in vec![0, 1, 3].into_iter().enumerate() {
+    let dequeued = store.dequeue().await.unwrap().unwrap();
+    assert_eq!(dequeued.id, ids[expected], "dequeue {}", index);
+    assert_eq!(dequeued.job, jobs[expected]);
+    store.ack(&dequeued.id).await.unwrap();
+  }
+
+  assert!(store.dequeue().await.unwrap().is_none());
+}
+
+async fn listings(store: &dyn JobStore) {
+  let first = store.queue(&create_lobby("listed")).await.unwrap();
+  let later = store
+    .queue_in(&create_lobby("listed later"), Duration::from_secs(60))
+    .await
+    .unwrap();
+
+  // Scheduled jobs are listed after the jobs already on the queue.
+  let waiting = listed(store.waiting().await.unwrap());
+  let position = |id: &String| waiting.iter().position(|listed| listed == id);
+  assert!(position(&first).unwrap() < position(&later).unwrap());
+  assert!(!listed(store.running().await.unwrap()).contains(&first));
+
+  let running = store.dequeue().await.unwrap().unwrap();
+  assert_eq!(running.id, first);
+  assert!(!listed(store.waiting().await.unwrap()).contains(&first));
+  assert!(listed(store.running().await.unwrap()).contains(&first));
+
+  store
+    .update(&first, &running.with_status(JobStatus::Failed))
+    .await
+    .unwrap();
+  store.ack(&first).await.unwrap();
+  assert!(!listed(store.running().await.unwrap()).contains(&first));
+
+  let finished = store.finished().await.unwrap();
+  let failed = finished.iter().find(|job| job.id == first).unwrap();
+  assert_eq!(failed.status, JobStatus::Failed);
+
+  assert!(store
+    .expire(Duration::from_secs(0))
+    .await
+    .unwrap()
+    .contains(&first));
+  assert!(!listed(store.finished().await.unwrap()).contains(&first));
+  assert!(store.cancel(&later).await.unwrap());
+}
+
+async fn waiting(store: &dyn JobStore) {
+  let id = store.queue(&create_lobby("awaited")).await.unwrap();
+  let missing = Uuid::new_v4().to_string();
+
+  let started = Instant::now();
+  store
+    .wait(&[id.clone(), missing.clone()], Duration::from_millis(300))
+    .await
+    .unwrap();
+  assert!(started.elapsed() >= Duration::from_millis(300));
+
+  let running = store.dequeue().await.unwrap().unwrap();
+  store
+    .update(&id, &running.with_status(JobStatus::Failed))
+    .await
+    .unwrap();
+  store.ack(&id).await.unwrap();
+
+  let started = Instant::now();
+  store
+    .wait(&[id.clone(), missing], Duration::from_secs(10))
+    .await
+    .unwrap();
+  assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+// Requeueing a job that is still waiting leaves it on the queue once, so that it only runs once.
+async fn requeueing(store: &dyn JobStore) {
+  let id = store.queue(&create_lobby("requeued")).await.unwrap();
+  assert_eq!(store.requeue(&id).await.unwrap(), Some(id.clone()));
+
+  let waiting = listed(store.waiting().await.unwrap());
+  assert_eq!(waiting.iter().filter(|waiting| **waiting == id).count(), 1);
+  assert!(store.cancel(&id).await.unwrap());
+}
+
+// Jobs are listed under the user they belong to, however often they are saved, until they are
+// cancelled.
+async fn ownership(store: &dyn JobStore) {
+  let user = Uuid::new_v4().to_string();
+  let id = store.queue(&create_lobby(&user)).await.unwrap();
+  store.queue(&create_lobby("someone else")).await.unwrap();
+
+  assert_eq!(listed(store.owned(&user).await.unwrap()), vec![id.clone()]);
+
+  let saved = QueuedJob::new(&id, create_lobby(&user));
+  store.update(&id, &saved).await.unwrap();
+  assert_eq!(listed(store.owned(&user).await.unwrap()), vec![id.clone()]);
+  assert!(store.cancel(&id).await.unwrap());
+  assert!(store.owned(&user).await.unwrap().is_empty());
+}
+
+// Workers promoting at the same time queue each due job exactly once between them.
+async fn promotions(store: Arc<dyn JobStore>) {
+  let past = SystemTime::now() - Duration::from_secs(1);
+  let mut due = Vec::new();
+
+  for _ in 0..5 {
+    due.push(
+      store
+        .queue_at(&create_lobby("promoted"), past)
+        .await
+        .unwrap(),
+    );
+  }
+
+  let promoters = (0..4)
+    .map(|_| {
+      let promoter = store.clone();
+      spawn(async move { promoter.promote().await.unwrap() })
+    })
+    .collect::<Vec<_>>();
+
+  let mut promoted = Vec::new();
+
+  for promoter in promoters {
+    promoted.extend(promoter.await);
+  }
+
+  promoted.sort();
+  due.sort();
+  assert_eq!(promoted, due);
+
+  let waiting = listed(store.waiting().await.unwrap());
+
+  for id in &due {
+    assert_eq!(waiting.iter().filter(|waiting| *waiting == id).count(), 1);
+    assert!(store.cancel(id).await.unwrap());
+  }
+}
+
+// Jobs queued alongside records being written are only ever seen once the records are committed.
+async fn committing(store: &dyn JobStore) {
+  let config = load_config().unwrap();
+  let pg = PgPool::connect(&config.record_store.postgres_uri)
+    .await
+    .unwrap();
+  let mut conn = pg.acquire().await.unwrap();
+  let job = create_lobby("committed");
+
+  let mut abandoned = conn.begin().await.unwrap();
+  let within = store
+    .queue_within(&mut abandoned, std::slice::from_ref(&job))
+    .await
+    .unwrap();
+  abandoned.rollback().await.unwrap();
+
+  for id in within.unwrap_or_default() {
+    assert!(store.lookup(&id).await.unwrap().is_none());
+  }
+
+  let tx = conn.begin().await.unwrap();
+  let id = commit_queued(store, tx, &job).await.unwrap();
+  assert!(store.lookup(&id).await.unwrap().is_some());
+  assert!(store.cancel(&id).await.unwrap());
+
+  let batch = [create_lobby("committed"), create_lobby("batched")];
+  let tx = conn.begin().await.unwrap();
+  let ids = commit_queued_all(store, tx, &batch).await.unwrap();
+  assert_eq!(ids.len(), 2);
+
+  for id in ids {
+    assert!(store.cancel(&id).await.unwrap());
+  }
+}
+
+// A job settling while it is waited on wakes the waiter well before the timeout.
+async fn wakes(store: Arc<dyn JobStore>) {
+  let id = store.queue(&create_lobby("woken")).await.unwrap();
+  let running = store.dequeue().await.unwrap().unwrap();
+  let (settler, settled) = (store.clone(), id.clone());
+
+  spawn(async move {
+    sleep(Duration::from_millis(200)).await;
+    let succeeded = running.with_status(JobStatus::Succeeded);
+    settler.update(&settled, &succeeded).await.unwrap();
+    settler.ack(&settled).await.unwrap();
+  });
+
+  let started = Instant::now();
+  store
+    .wait(std::slice::from_ref(&id), Duration::from_secs(10))
+    .await
+    .unwrap();
+  assert!(started.elapsed() < Duration::from_secs(5));
+  assert!(store
+    .lookup(&id)
+    .await
+    .unwrap()
+    .unwrap()
+    .status
+    .is_settled());
+}
+
+async fn conforms(store: Arc<dyn JobStore>) {
+  jobs(store.as_ref()).await;
+  lifecycle(store.as_ref()).await;
+  retries(store.as_ref()).await;
+  scheduling(store.as_ref()).await;
+  coalescing(store.as_ref()).await;
+  racing(store.clone()).await;
+  contending(store.clone()).await;
+  batches(store.as_ref()).await;
+  listings(store.as_ref()).await;
+  waiting(store.as_ref()).await;
+  requeueing(store.as_ref()).await;
+  ownership(store.as_ref()).await;
+  committing(store.as_ref()).await;
+  promotions(store.clone()).await;
+  wakes(store.clone()).await;
+  assert!(store.health().await.healthy);
+}
+
+#[test]
+fn memory() {
+  block_on(conforms(Arc::new(MemoryJobStore::new())));
+}
+
+#[test]
+fn redis() {
+  let mut config = load_config().unwrap();
+  let prefix = format!("krumnet_conformance:{}", Uuid::new_v4());
+  config.job_store.queue_key = format!("{}:queue", prefix);
+  config.job_store.map_key = format!("{}:map", prefix);
+  config.job_store.dequeue_key = format!("{}:dequeue", prefix);
+  config.job_store.queue_delay = 1;
+
+  let store = block_on(RedisJobStore::open(&config)).unwrap();
+  block_on(conforms(Arc::new(store)));
+}
+
+#[test]
+fn postgres() {
+  let mut config = load_config().unwrap();
+  config.job_store.queue_key = format!("krumnet_conformance:{}", Uuid::new_v4());
+  config.job_store.queue_delay = 1;
+
+  let store = block_on(PostgresJobStore::open(&config)).unwrap();
+  block_on(conforms(Arc::new(store)));
+}
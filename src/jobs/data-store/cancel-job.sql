@@ -0,0 +1,10 @@
+delete from
+  krumnet.jobs as jobs
+where
+  jobs.id = $1
+and
+  jobs.queue = $2
+and
+  jobs.state in ('waiting', 'scheduled')
+returning
+  jobs.id as id;
@@ -0,0 +1,26 @@
+with next as (
+  select
+    jobs.id
+  from
+    krumnet.jobs as jobs
+  where
+    jobs.queue = $1
+  and
+    jobs.state = 'waiting'
+  order by
+    jobs.run_at, jobs.created_at
+  limit 1
+  for update skip locked
+) update
+  krumnet.jobs as jobs
+set
+  state = 'held',
+  worker = $2,
+  heartbeat_at = now()
+from
+  next
+where
+  jobs.id = next.id
+returning
+  jobs.id      as id,
+  jobs.payload as payload;
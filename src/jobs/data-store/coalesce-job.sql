@@ -0,0 +1,13 @@
+update
+  krumnet.jobs as jobs
+set
+  rerun = jobs.rerun or jobs.state = 'held'
+where
+  jobs.queue = $1
+and
+  jobs.dedupe_key = $2
+and
+  jobs.state in ('waiting', 'scheduled', 'held')
+returning
+  jobs.id    as id,
+  jobs.state as state;
@@ -0,0 +1,8 @@
+select
+  count(*) as "waiting!"
+from
+  krumnet.jobs as jobs
+where
+  jobs.queue = $1
+and
+  jobs.state = 'waiting';
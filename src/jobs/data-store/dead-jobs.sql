@@ -0,0 +1,10 @@
+select
+  jobs.payload as payload
+from
+  krumnet.jobs as jobs
+where
+  jobs.queue = $1
+and
+  jobs.state = 'dead'
+order by
+  jobs.run_at;
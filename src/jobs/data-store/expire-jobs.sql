@@ -0,0 +1,10 @@
+delete from
+  krumnet.jobs as jobs
+where
+  jobs.queue = $1
+and
+  jobs.state <> 'dead'
+and
+  jobs.finished_at <= now() - ($2::bigint * interval '1 millisecond')
+returning
+  jobs.id as id;
@@ -0,0 +1,9 @@
+select
+  jobs.payload as payload,
+  jobs.state   as state
+from
+  krumnet.jobs as jobs
+where
+  jobs.id = $1
+and
+  jobs.queue = $2;
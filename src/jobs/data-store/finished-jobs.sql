@@ -0,0 +1,12 @@
+select
+  jobs.payload as payload
+from
+  krumnet.jobs as jobs
+where
+  jobs.queue = $1
+and
+  jobs.state <> 'dead'
+and
+  jobs.finished_at is not null
+order by
+  jobs.finished_at;
@@ -0,0 +1,10 @@
+update
+  krumnet.jobs as jobs
+set
+  heartbeat_at = now()
+where
+  jobs.id = $1
+and
+  jobs.queue = $2
+and
+  jobs.state = 'held';
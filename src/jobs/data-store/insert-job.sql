@@ -0,0 +1,7 @@
+insert into
+  krumnet.jobs
+  (id, queue, payload, state, run_at, dedupe_key, user_id)
+values
+  ($1, $2, $3, $4, $5, $6, $7)
+returning
+  jobs.id as id;
@@ -0,0 +1,11 @@
+select
+  jobs.payload as payload
+from
+  krumnet.jobs as jobs
+where
+  jobs.queue = $1
+and
+  jobs.state = any($2)
+order by
+  jobs.run_at,
+  jobs.created_at;
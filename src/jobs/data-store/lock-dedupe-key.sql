@@ -0,0 +1,4 @@
+select
+  true as "locked!"
+from
+  pg_advisory_xact_lock(hashtext($1));
@@ -0,0 +1,15 @@
+update
+  krumnet.jobs as jobs
+set
+  payload = $3,
+  state = $4,
+  run_at = $5,
+  worker = null,
+  heartbeat_at = null,
+  rerun = false
+where
+  jobs.id = $1
+and
+  jobs.queue = $2
+returning
+  jobs.id as id;
@@ -0,0 +1,12 @@
+update
+  krumnet.jobs as jobs
+set
+  state = 'waiting'
+where
+  jobs.queue = $1
+and
+  jobs.state = 'scheduled'
+and
+  jobs.run_at <= now()
+returning
+  jobs.id as id;
@@ -0,0 +1,27 @@
+with released as (
+  select
+    jobs.id,
+    jobs.rerun
+  from
+    krumnet.jobs as jobs
+  where
+    jobs.id = $1
+  and
+    jobs.queue = $2
+  and
+    jobs.state = 'held'
+  for update
+) update
+  krumnet.jobs as jobs
+set
+  state = 'idle',
+  worker = null,
+  heartbeat_at = null,
+  rerun = false
+from
+  released
+where
+  jobs.id = released.id
+returning
+  released.rerun as rerun,
+  jobs.payload   as payload;
@@ -0,0 +1,12 @@
+insert into
+  krumnet.jobs
+  (id, queue, payload, state, finished_at, user_id)
+values
+  ($1, $2, $3, 'idle', $4, $5)
+on conflict (id) do update set
+  queue = excluded.queue,
+  payload = excluded.payload,
+  finished_at = excluded.finished_at,
+  user_id = excluded.user_id
+returning
+  jobs.id as id;
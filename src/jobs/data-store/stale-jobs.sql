@@ -0,0 +1,13 @@
+select
+  jobs.id      as id,
+  jobs.payload as payload,
+  jobs.worker  as worker
+from
+  krumnet.jobs as jobs
+where
+  jobs.queue = $1
+and
+  jobs.state = 'held'
+and
+  jobs.heartbeat_at <= now() - ($2::bigint * interval '1 millisecond')
+for update skip locked;
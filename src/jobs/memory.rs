@@ -0,0 +1,400 @@
+use async_std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Result;
+use std::time::{Duration, Instant, SystemTime};
+use uuid::Uuid;
+
+use super::JobStore;
+use crate::bg::registry::Registry;
+use crate::interchange::jobs::{DequeuedJob, Job, JobStatus, QueuedJob};
+use crate::redis::Health;
+
+const WORKER: &'static str = "memory";
+
+#[derive(Default)]
+struct State {
+  queue: VecDeque<String>,
+  jobs: HashMap<String, QueuedJob>,
+  dequeued: HashMap<String, DequeuedJob>,
+  scheduled: HashMap<String, Instant>,
+  dead: Vec<String>,
+  dedupe: HashMap<String, String>,
+  rerun: HashSet<String>,
+  owned: HashMap<String, HashSet<String>>,
+}
+
+impl State {
+  fn push(&mut self, registry: &Registry, job: &Job) -> String {
+    let id = Uuid::new_v4().to_string();
+
+    if let Some(key) = registry.dedupe_key(job) {
+      self.dedupe.insert(key, id.clone());
+    }
+
+    self.insert(registry, &id, job);
+    self.queue.push_back(id.clone());
+    id
+  }
+
+  // Saves a new job, indexing it under the user it belongs to.
+  fn insert(&mut self, registry: &Registry, id: &str, job: &Job) {
+    if let Some(user) = registry.user(job) {
+      self.owned.entry(user).or_default().insert(String::from(id));
+    }
+
+    self
+      .jobs
+      .insert(String::from(id), QueuedJob::new(id, job.clone()));
+  }
+
+  fn forget(&mut self, registry: &Registry, id: &str) {
+    let user = match self.jobs.remove(id) {
+      Some(job) => registry.user(&job.job),
+      None => return,
+    };
+
+    if let Some(user) = user {
+      if let Some(ids) = self.owned.get_mut(&user) {
+        ids.remove(id);
+
+        if ids.is_empty() {
+          self.owned.remove(&user);
+        }
+      }
+    }
+  }
+
+  // Returns the id of a pending job the job can be coalesced into, flagging it to run again if it
+  // is already running.
+  fn coalesce(&mut self, registry: &Registry, job: &Job) -> Option<String> {
+    let id = registry
+      .dedupe_key(job)
+      .and_then(|key| self.dedupe.get(&key).cloned())?;
+
+    match self.jobs.get(&id).map(|existing| existing.status) {
+      Some(JobStatus::Queued) => Some(id),
+      Some(JobStatus::Running) => {
+        self.rerun.insert(id.clone());
+        Some(id)
+      }
+      _ => None,
+    }
+  }
+
+  // Lets go of the job's dedupe key once it is no longer pending, queueing it again if it was
+  // flagged while it ran. Jobs waiting to be retried keep their key.
+  fn release(&mut self, registry: &Registry, id: &str) {
+    let rerun = self.rerun.remove(id);
+    let job = match self.jobs.get(id) {
+      Some(job) if job.status != JobStatus::Queued => job.clone(),
+      _ => return,
+    };
+
+    if let Some(key) = registry.dedupe_key(&job.job) {
+      if self.dedupe.get(&key).map(String::as_str) == Some(id) {
+        self.dedupe.remove(&key);
+      }
+    }
+
+    if rerun && job.status.is_finished() {
+      self.push(registry, &registry.reset(&job.job));
+    }
+  }
+}
+
+// Keeps the queue in process memory. Unlike the redis store, dequeuing never waits for a job to be
+// queued; an empty queue returns `None` right away.
+#[derive(Default)]
+pub struct MemoryJobStore {
+  _state: Mutex<State>,
+  _registry: Arc<Registry>,
+}
+
+impl MemoryJobStore {
+  pub fn new() -> Self {
+    MemoryJobStore::default()
+  }
+
+  // Handlers of the registry decide which jobs are coalesced and how jobs are reset.
+  pub fn with_registry(self, registry: Arc<Registry>) -> Self {
+    MemoryJobStore {
+      _registry: registry,
+      ..self
+    }
+  }
+
+  // The number of jobs waiting to be dequeued, useful for asserting on what a handler has queued.
+  pub async fn pending(&self) -> usize {
+    self._state.lock().await.queue.len()
+  }
+}
+
+#[async_trait]
+impl JobStore for MemoryJobStore {
+  async fn lookup(&self, id: &str) -> Result<Option<QueuedJob>> {
+    Ok(self._state.lock().await.jobs.get(id).cloned())
+  }
+
+  async fn update(&self, id: &str, job: &QueuedJob) -> Result<String> {
+    let mut state = self._state.lock().await;
+    state.jobs.insert(String::from(id), job.clone());
+    Ok(String::from(id))
+  }
+
+  async fn dequeue(&self) -> Result<Option<QueuedJob>> {
+    let mut state = self._state.lock().await;
+
+    let id = match state.queue.pop_front() {
+      Some(id) => id,
+      None => return Ok(None),
+    };
+
+    state
+      .dequeued
+      .insert(id.clone(), DequeuedJob::new(&id, WORKER));
+
+    let running = match state.jobs.get(&id) {
+      Some(job) => QueuedJob {
+        worker: Some(String::from(WORKER)),
+        ..job.with_status(JobStatus::Running)
+      },
+      None => return Ok(None),
+    };
+
+    state.jobs.insert(id, running.clone());
+    Ok(Some(running))
+  }
+
+  async fn ack(&self, id: &str) -> Result<()> {
+    let mut state = self._state.lock().await;
+    state.dequeued.remove(id);
+    state.release(&self._registry, id);
+    Ok(())
+  }
+
+  async fn heartbeat(&self, id: &str) -> Result<()> {
+    let mut state = self._state.lock().await;
+    state
+      .dequeued
+      .insert(String::from(id), DequeuedJob::new(id, WORKER));
+    Ok(())
+  }
+
+  async fn reap(&self, timeout: Duration) -> Result<Vec<String>> {
+    let mut state = self._state.lock().await;
+
+    let expired = state
+      .dequeued
+      .values()
+      .filter(|entry| entry.expired(timeout))
+      .map(|entry| entry.id.clone())
+      .collect::<Vec<String>>();
+
+    for id in &expired {
+      state.dequeued.remove(id);
+      state.queue.push_back(id.clone());
+
+      if let Some(job) = state
+        .jobs
+        .get(id)
+        .map(|job| job.with_status(JobStatus::Queued))
+      {
+        state.jobs.insert(id.clone(), job);
+      }
+    }
+
+    Ok(expired)
+  }
+
+  async fn queue(&self, job: &Job) -> Result<String> {
+    let mut state = self._state.lock().await;
+
+    match state.coalesce(&self._registry, job) {
+      Some(id) => Ok(id),
+      None => Ok(state.push(&self._registry, job)),
+    }
+  }
+
+  async fn queue_at(&self, job: &Job, when: SystemTime) -> Result<String> {
+    let id = Uuid::new_v4().to_string();
+    let delay = when
+      .duration_since(SystemTime::now())
+      .unwrap_or_else(|_| Duration::from_secs(0));
+
+    let mut state = self._state.lock().await;
+    state.insert(&self._registry, &id, job);
+    state.scheduled.insert(id.clone(), Instant::now() + delay);
+    Ok(id)
+  }
+
+  async fn cancel(&self, id: &str) -> Result<bool> {
+    let mut state = self._state.lock().await;
+    let queued = state.queue.len();
+    state.queue.retain(|queued| queued != id);
+
+    if state.scheduled.remove(id).is_none() && state.queue.len() == queued {
+      return Ok(false);
+    }
+
+    state.dedupe.retain(|_, pending| pending != id);
+    state.forget(&self._registry, id);
+    Ok(true)
+  }
+
+  async fn requeue(&self, id: &str) -> Result<Option<String>> {
+    let mut state = self._state.lock().await;
+
+    let reset = match state.jobs.get(id) {
+      Some(existing) => QueuedJob::new(id, self._registry.reset(&existing.job)),
+      None => return Ok(None),
+    };
+
+    state.jobs.insert(String::from(id), reset);
+    state.dequeued.remove(id);
+    state.scheduled.remove(id);
+    state.dead.retain(|dead| dead != id);
+    state.queue.retain(|queued| queued != id);
+    state.queue.push_back(String::from(id));
+    Ok(Some(String::from(id)))
+  }
+
+  async fn retry(&self, job: &QueuedJob, delay: Duration) -> Result<()> {
+    let mut state = self._state.lock().await;
+    state
+      .jobs
+      .insert(job.id.clone(), job.with_status(JobStatus::Queued));
+    state.dequeued.remove(&job.id);
+    state.release(&self._registry, &job.id);
+    state
+      .scheduled
+      .insert(job.id.clone(), Instant::now() + delay);
+    Ok(())
+  }
+
+  async fn promote(&self) -> Result<Vec<String>> {
+    let mut state = self._state.lock().await;
+    let now = Instant::now();
+
+    let due = state
+      .scheduled
+      .iter()
+      .filter(|(_, at)| **at <= now)
+      .map(|(id, _)| id.clone())
+      .collect::<Vec<String>>();
+
+    for id in &due {
+      state.scheduled.remove(id);
+      state.queue.push_back(id.clone());
+    }
+
+    Ok(due)
+  }
+
+  async fn bury(&self, job: &QueuedJob) -> Result<()> {
+    let mut state = self._state.lock().await;
+    state
+      .jobs
+      .insert(job.id.clone(), job.with_status(JobStatus::Dead));
+    state.dequeued.remove(&job.id);
+    state.release(&self._registry, &job.id);
+    state.dead.retain(|dead| dead != &job.id);
+    state.dead.push(job.id.clone());
+    Ok(())
+  }
+
+  async fn dead_letters(&self) -> Result<Vec<QueuedJob>> {
+    let state = self._state.lock().await;
+    let jobs = state
+      .dead
+      .iter()
+      .filter_map(|id| state.jobs.get(id).cloned())
+      .collect();
+    Ok(jobs)
+  }
+
+  async fn waiting(&self) -> Result<Vec<QueuedJob>> {
+    let state = self._state.lock().await;
+    let mut scheduled = state.scheduled.iter().collect::<Vec<_>>();
+    scheduled.sort_by_key(|(_, when)| **when);
+
+    let jobs = state
+      .queue
+      .iter()
+      .chain(scheduled.into_iter().map(|(id, _)| id))
+      .filter_map(|id| state.jobs.get(id).cloned())
+      .collect();
+    Ok(jobs)
+  }
+
+  async fn running(&self) -> Result<Vec<QueuedJob>> {
+    let state = self._state.lock().await;
+    let jobs = state
+      .dequeued
+      .keys()
+      .filter_map(|id| state.jobs.get(id).cloned())
+      .collect();
+    Ok(jobs)
+  }
+
+  async fn finished(&self) -> Result<Vec<QueuedJob>> {
+    let state = self._state.lock().await;
+    let mut jobs = state
+      .jobs
+      .values()
+      .filter(|job| job.status.is_finished())
+      .cloned()
+      .collect::<Vec<_>>();
+    jobs.sort_by_key(|job| job.finished_at);
+    Ok(jobs)
+  }
+
+  async fn owned(&self, user: &str) -> Result<Vec<QueuedJob>> {
+    let state = self._state.lock().await;
+    let mut jobs = state
+      .owned
+      .get(user)
+      .into_iter()
+      .flatten()
+      .filter_map(|id| state.jobs.get(id).cloned())
+      .collect::<Vec<_>>();
+    jobs.sort_by_key(|job| job.queued_at);
+    Ok(jobs)
+  }
+
+  async fn replay(&self, id: &str) -> Result<Option<String>> {
+    if !self._state.lock().await.dead.iter().any(|dead| dead == id) {
+      return Ok(None);
+    }
+
+    self.requeue(id).await
+  }
+
+  async fn expire(&self, retention: Duration) -> Result<Vec<String>> {
+    let mut state = self._state.lock().await;
+
+    let expired = state
+      .jobs
+      .values()
+      .filter(|job| job.status.is_finished())
+      .filter(|job| {
+        job
+          .finished_at
+          .and_then(|at| at.elapsed().ok())
+          .map(|elapsed| elapsed >= retention)
+          .unwrap_or(false)
+      })
+      .map(|job| job.id.clone())
+      .collect::<Vec<String>>();
+
+    for id in &expired {
+      state.forget(&self._registry, id);
+    }
+
+    Ok(expired)
+  }
+
+  async fn health(&self) -> Health {
+    Health::default()
+  }
+}
@@ -0,0 +1,212 @@
+use async_std::sync::Arc;
+use async_std::task::sleep;
+use async_trait::async_trait;
+use sqlx::postgres::{PgConnection, Postgres};
+use sqlx::Transaction;
+use std::io::Result;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::bg::registry::Registry;
+use crate::configuration::JobBackend;
+use crate::constants::JOB_WAIT_POLL_INTERVAL;
+use crate::interchange::jobs::{Job, QueuedJob};
+use crate::redis::Health;
+use crate::{errors, Configuration};
+
+pub mod memory;
+pub mod postgres;
+pub mod redis;
+
+#[cfg(test)]
+mod conformance;
+
+pub use memory::MemoryJobStore;
+pub use postgres::PostgresJobStore;
+pub use redis::RedisJobStore;
+
+// Jobs are queued by the web process and worked off by `kruwk`. Each job is stored under its id
+// so that clients can poll for its result after it has been taken off the queue.
+//
+// Dequeued jobs are held by the store until they are acknowledged. A worker that dies part way
+// through a job stops sending heartbeats for it, and `reap` puts the job back on the queue.
+//
+// Jobs that fail in a way worth retrying are scheduled to be queued again after a delay. Once a job
+// has used up its attempts it is moved to a dead-letter list, where it stays until it is replayed.
+#[async_trait]
+pub trait JobStore: Send + Sync {
+  async fn lookup(&self, id: &str) -> Result<Option<QueuedJob>>;
+
+  // Saves the job as given. Jobs saved with a finished status are remembered so that they can be
+  // expired once they are older than the retention period.
+  async fn update(&self, id: &str, job: &QueuedJob) -> Result<String>;
+
+  async fn dequeue(&self) -> Result<Option<QueuedJob>>;
+
+  // Marks a dequeued job as finished, releasing it from the worker that took it. A finished job
+  // flagged to run again is queued afresh under a new id.
+  async fn ack(&self, id: &str) -> Result<()>;
+
+  // Lets the store know the job is still being worked on.
+  async fn heartbeat(&self, id: &str) -> Result<()>;
+
+  // Requeues dequeued jobs that have not been acknowledged or heard from within the timeout,
+  // returning their ids.
+  async fn reap(&self, timeout: Duration) -> Result<Vec<String>>;
+
+  // Jobs with a dedupe key are coalesced into a pending job with the same key, whose id is returned
+  // instead. When that job is already running it is flagged to run once more after it finishes, so
+  // that whatever changed in the meantime is still looked at.
+  async fn queue(&self, job: &Job) -> Result<String>;
+
+  // Queues several jobs at once, returning their ids in the same order. Where the store allows it,
+  // either all of the jobs are queued or none of them are.
+  async fn queue_all(&self, jobs: &[Job]) -> Result<Vec<String>> {
+    let mut ids = Vec::with_capacity(jobs.len());
+
+    for job in jobs {
+      ids.push(self.queue(job).await?);
+    }
+
+    Ok(ids)
+  }
+
+  // Queues the jobs using a connection to the record store, so that they are only queued if the
+  // transaction the connection is part of commits. Stores that keep their jobs elsewhere return
+  // `None`, leaving the jobs to be queued once the transaction has committed.
+  async fn queue_within(
+    &self,
+    _conn: &mut PgConnection,
+    _jobs: &[Job],
+  ) -> Result<Option<Vec<String>>> {
+    Ok(None)
+  }
+
+  // Stores the job without queueing it; it is moved onto the queue by `promote` once the time has
+  // come.
+  async fn queue_at(&self, job: &Job, when: SystemTime) -> Result<String>;
+
+  async fn queue_in(&self, job: &Job, delay: Duration) -> Result<String> {
+    self.queue_at(job, SystemTime::now() + delay).await
+  }
+
+  // Removes a job that has not been dequeued yet, returning whether there was one to remove.
+  async fn cancel(&self, id: &str) -> Result<bool>;
+
+  // Clears the result of a previously queued job and pushes it back onto the end of the queue,
+  // returning `None` when no job with the id exists.
+  async fn requeue(&self, id: &str) -> Result<Option<String>>;
+
+  // Saves the job and acknowledges it, scheduling it to be put back on the queue once the delay
+  // has passed.
+  async fn retry(&self, job: &QueuedJob, delay: Duration) -> Result<()>;
+
+  // Moves scheduled jobs that are due onto the queue, returning their ids.
+  async fn promote(&self) -> Result<Vec<String>>;
+
+  // Saves the job and acknowledges it, moving it to the dead-letter list.
+  async fn bury(&self, job: &QueuedJob) -> Result<()>;
+
+  async fn dead_letters(&self) -> Result<Vec<QueuedJob>>;
+
+  // Jobs waiting on the queue, in the order they will be dequeued, followed by jobs scheduled for
+  // later or waiting to be retried.
+  async fn waiting(&self) -> Result<Vec<QueuedJob>>;
+
+  // Jobs taken off the queue by a worker that have not been acknowledged yet.
+  async fn running(&self) -> Result<Vec<QueuedJob>>;
+
+  // Jobs that succeeded or failed and are waiting to be expired, oldest first.
+  async fn finished(&self) -> Result<Vec<QueuedJob>>;
+
+  // Jobs the store still knows about that belong to the user, as decided by the job's handler,
+  // oldest first. Stores keep an index of jobs by user so that this never looks at anyone else's.
+  async fn owned(&self, user: &str) -> Result<Vec<QueuedJob>>;
+
+  // Takes a job off the dead-letter list and requeues it with its attempts cleared, returning
+  // `None` when the job is not on the list.
+  async fn replay(&self, id: &str) -> Result<Option<String>>;
+
+  // Forgets finished jobs that finished longer ago than the retention period, returning their ids.
+  async fn expire(&self, retention: Duration) -> Result<Vec<String>>;
+
+  // Waits until every one of the jobs has finished or died, or the timeout has passed. Jobs that do
+  // not exist are not waited on. Stores that cannot be told when a job settles look every so often.
+  async fn wait(&self, ids: &[String], timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let interval = Duration::from_millis(JOB_WAIT_POLL_INTERVAL);
+
+    loop {
+      if settled(self, ids).await? {
+        return Ok(());
+      }
+
+      let now = Instant::now();
+
+      if now >= deadline {
+        return Ok(());
+      }
+
+      sleep(interval.min(deadline - now)).await;
+    }
+  }
+
+  async fn health(&self) -> Health;
+}
+
+// Commits the records written in the transaction along with the job that looks at them. The job is
+// queued inside the transaction where the store allows it, and otherwise right after it commits so
+// that a worker never picks up the job before the records are there to be seen.
+pub async fn commit_queued<S: JobStore + ?Sized>(
+  store: &S,
+  tx: Transaction<'_, Postgres>,
+  job: &Job,
+) -> Result<String> {
+  let mut ids = commit_queued_all(store, tx, std::slice::from_ref(job)).await?;
+  ids.pop().ok_or_else(|| errors::e("job was not queued"))
+}
+
+pub async fn commit_queued_all<S: JobStore + ?Sized>(
+  store: &S,
+  mut tx: Transaction<'_, Postgres>,
+  jobs: &[Job],
+) -> Result<Vec<String>> {
+  let queued = store.queue_within(&mut tx, jobs).await?;
+  tx.commit().await.map_err(errors::humanize_error)?;
+
+  match queued {
+    Some(ids) => Ok(ids),
+    None => store.queue_all(jobs).await,
+  }
+}
+
+// Whether each of the jobs that still exist has settled.
+pub async fn settled<S: JobStore + ?Sized>(store: &S, ids: &[String]) -> Result<bool> {
+  for id in ids {
+    if let Some(job) = store.lookup(id).await? {
+      if !job.status.is_settled() {
+        return Ok(false);
+      }
+    }
+  }
+
+  Ok(true)
+}
+
+// Opens the job store backend selected by the configuration.
+pub async fn open(
+  configuration: &Configuration,
+  registry: Arc<Registry>,
+) -> Result<Arc<dyn JobStore>> {
+  match configuration.job_store.backend {
+    JobBackend::Redis => Ok(Arc::new(
+      RedisJobStore::open(configuration)
+        .await?
+        .with_registry(registry),
+    )),
+    JobBackend::Postgres => Ok(Arc::new(
+      PostgresJobStore::open(configuration)
+        .await?
+        .with_registry(registry),
+    )),
+  }
+}
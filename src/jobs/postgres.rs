@@ -0,0 +1,614 @@
+use async_std::future::timeout;
+use async_std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use serde_json::{from_str as deserialize, to_string as serialize};
+use sqlx::postgres::{PgConnection, PgListener, PgPool};
+use sqlx::{query_file, Connection};
+use std::io::{Error, Result};
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+use super::JobStore;
+use crate::bg::registry::Registry;
+use crate::interchange::jobs::{Job, JobStatus, QueuedJob};
+use crate::redis::Health;
+use crate::{errors, Configuration};
+
+// Rows move between these states; only `waiting` rows are dequeued. Inserting or updating a row
+// into `waiting` notifies this channel with the name of its queue.
+const WAITING: &'static str = "waiting";
+const SCHEDULED: &'static str = "scheduled";
+const HELD: &'static str = "held";
+const DEAD: &'static str = "dead";
+const CHANNEL: &'static str = "krumnet_jobs";
+
+const DEFAULT_QUEUE: &'static str = "krumnet:jobs";
+
+fn warn_and_return<E: std::error::Error>(error: E) -> Error {
+  warn!("job store failure - {}", error);
+  errors::humanize_error(error)
+}
+
+fn millis(duration: Duration) -> i64 {
+  duration.as_millis() as i64
+}
+
+// Keeps jobs in the `krumnet.jobs` table alongside the records, for deployments that would rather
+// not run redis. Rows are namespaced by the configured `queue_key` so that several queues can share
+// the table.
+pub struct PostgresJobStore {
+  _pg: PgPool,
+  _listener: Mutex<PgListener>,
+  _queue: String,
+  _queue_delay: u64,
+  _worker: String,
+  _registry: Arc<Registry>,
+}
+
+// Rows are saved along with the user the job belongs to, so that a user's jobs can be listed
+// without looking at anyone else's.
+async fn insert(
+  conn: &mut PgConnection,
+  queue: &str,
+  registry: &Registry,
+  job: &Job,
+  state: &str,
+  when: SystemTime,
+  dedupe_key: Option<&str>,
+) -> Result<String> {
+  let uid = Uuid::new_v4().to_string();
+  let serialized = serialize(&QueuedJob::new(&uid, job.clone()))?;
+  let run_at = DateTime::<Utc>::from(when);
+  let user = registry.user(job);
+
+  query_file!(
+    "src/jobs/data-store/insert-job.sql",
+    uid,
+    queue,
+    serialized,
+    state,
+    run_at,
+    dedupe_key,
+    user
+  )
+  .fetch_one(conn)
+  .await
+  .map_err(warn_and_return)?;
+
+  debug!("job '{}' inserted as {}", uid, state);
+  Ok(uid)
+}
+
+// Queues the job using the given connection, so that it is only queued if the transaction the
+// connection is part of commits.
+pub async fn enqueue(
+  conn: &mut PgConnection,
+  queue: &str,
+  registry: &Registry,
+  job: &Job,
+) -> Result<String> {
+  let key = match registry.dedupe_key(job) {
+    Some(key) => key,
+    None => return insert(conn, queue, registry, job, WAITING, SystemTime::now(), None).await,
+  };
+
+  // The lock on the key is held until the transaction ends, so that jobs queued with the same key
+  // at the same time are coalesced as well.
+  let mut tx = conn.begin().await.map_err(warn_and_return)?;
+  let lock = format!("{}:{}", queue, key);
+  query_file!("src/jobs/data-store/lock-dedupe-key.sql", lock)
+    .fetch_one(&mut tx)
+    .await
+    .map_err(warn_and_return)?;
+
+  let pending = query_file!("src/jobs/data-store/coalesce-job.sql", queue, key)
+    .fetch_all(&mut tx)
+    .await
+    .map_err(warn_and_return)?;
+
+  let id = match pending.into_iter().next() {
+    Some(row) => {
+      debug!("job coalesced into '{}' ({}, {})", row.id, key, row.state);
+      row.id
+    }
+    None => {
+      let now = SystemTime::now();
+      insert(&mut tx, queue, registry, job, WAITING, now, Some(&key)).await?
+    }
+  };
+
+  tx.commit().await.map_err(warn_and_return)?;
+  Ok(id)
+}
+
+// As with `enqueue`, but the job is left for `promote` to queue once the time has come.
+pub async fn enqueue_at(
+  conn: &mut PgConnection,
+  queue: &str,
+  registry: &Registry,
+  job: &Job,
+  when: SystemTime,
+) -> Result<String> {
+  insert(conn, queue, registry, job, SCHEDULED, when, None).await
+}
+
+impl PostgresJobStore {
+  pub async fn open(configuration: &Configuration) -> Result<Self> {
+    let uri = &configuration.record_store.postgres_uri;
+    let pg = PgPool::connect(uri).await.map_err(errors::humanize_error)?;
+    PostgresJobStore::with_pool(configuration, pg).await
+  }
+
+  // Creates a job store on top of an existing pool, allowing it to be shared with the record store.
+  pub async fn with_pool(configuration: &Configuration, pg: PgPool) -> Result<Self> {
+    let mut listener = PgListener::connect_with(&pg)
+      .await
+      .map_err(warn_and_return)?;
+    listener.listen(CHANNEL).await.map_err(warn_and_return)?;
+
+    let queue = match configuration.job_store.queue_key.as_str() {
+      "" => String::from(DEFAULT_QUEUE),
+      key => String::from(key),
+    };
+
+    let delay = if configuration.job_store.queue_delay > 0 {
+      configuration.job_store.queue_delay
+    } else {
+      10
+    };
+
+    info!("postgres job store ready, queue[{}]", queue);
+
+    Ok(PostgresJobStore {
+      _pg: pg,
+      _listener: Mutex::new(listener),
+      _queue: queue,
+      _queue_delay: delay,
+      _worker: Uuid::new_v4().to_string(),
+      _registry: Arc::new(Registry::default()),
+    })
+  }
+
+  // Handlers of the registry decide which jobs are coalesced and how jobs are reset.
+  pub fn with_registry(self, registry: Arc<Registry>) -> Self {
+    PostgresJobStore {
+      _registry: registry,
+      ..self
+    }
+  }
+
+  pub fn queue_key(&self) -> &str {
+    self._queue.as_str()
+  }
+
+  // Takes the oldest waiting job, skipping rows other workers have locked, and marks it as running
+  // in the same transaction.
+  async fn claim(&self) -> Result<Option<QueuedJob>> {
+    let mut conn = self._pg.acquire().await.map_err(warn_and_return)?;
+    let mut tx = conn.begin().await.map_err(warn_and_return)?;
+
+    let claimed = query_file!(
+      "src/jobs/data-store/claim-job.sql",
+      self._queue,
+      self._worker
+    )
+    .fetch_optional(&mut tx)
+    .await
+    .map_err(warn_and_return)?;
+
+    let row = match claimed {
+      Some(row) => row,
+      None => return Ok(None),
+    };
+
+    info!("found queue entry - '{}'", row.id);
+    let job = deserialize::<QueuedJob>(&row.payload)?;
+    let running = QueuedJob {
+      worker: Some(self._worker.clone()),
+      ..job.with_status(JobStatus::Running)
+    };
+    let serialized = serialize(&running)?;
+
+    query_file!(
+      "src/jobs/data-store/save-job.sql",
+      row.id,
+      self._queue,
+      serialized,
+      None::<DateTime<Utc>>,
+      self._registry.user(&running.job)
+    )
+    .fetch_one(&mut tx)
+    .await
+    .map_err(warn_and_return)?;
+
+    tx.commit().await.map_err(warn_and_return)?;
+    Ok(Some(running))
+  }
+
+  // Rewrites the job and moves it into the given state, clearing whichever worker held it.
+  async fn shift(&self, job: &QueuedJob, state: &str, when: SystemTime) -> Result<bool> {
+    let serialized = serialize(job)?;
+    let run_at = DateTime::<Utc>::from(when);
+
+    let moved = query_file!(
+      "src/jobs/data-store/move-job.sql",
+      job.id,
+      self._queue,
+      serialized,
+      state,
+      run_at
+    )
+    .fetch_optional(&self._pg)
+    .await
+    .map_err(warn_and_return)?;
+
+    Ok(moved.is_some())
+  }
+
+  async fn list(&self, states: &[&str]) -> Result<Vec<QueuedJob>> {
+    let states = states
+      .iter()
+      .map(|state| state.to_string())
+      .collect::<Vec<_>>();
+    let rows = query_file!("src/jobs/data-store/list-jobs.sql", self._queue, &states)
+      .fetch_all(&self._pg)
+      .await
+      .map_err(warn_and_return)?;
+
+    let mut jobs = Vec::new();
+
+    for row in rows {
+      jobs.push(deserialize::<QueuedJob>(&row.payload)?);
+    }
+
+    Ok(jobs)
+  }
+
+  async fn find(&self, id: &str) -> Result<Option<(QueuedJob, String)>> {
+    let row = query_file!("src/jobs/data-store/find-job.sql", id, self._queue)
+      .fetch_optional(&self._pg)
+      .await
+      .map_err(warn_and_return)?;
+
+    match row {
+      Some(row) => Ok(Some((deserialize::<QueuedJob>(&row.payload)?, row.state))),
+      None => Ok(None),
+    }
+  }
+}
+
+#[async_trait]
+impl JobStore for PostgresJobStore {
+  async fn lookup(&self, id: &str) -> Result<Option<QueuedJob>> {
+    self.find(id).await.map(|found| found.map(|(job, _)| job))
+  }
+
+  // Only the job is saved, along with the queue and user it belongs to; where the job sits in the
+  // queue is left as it was.
+  async fn update(&self, id: &str, job: &QueuedJob) -> Result<String> {
+    let serialized = serialize(job)?;
+    let finished_at = match job.status.is_finished() {
+      true => job.finished_at.map(DateTime::<Utc>::from),
+      false => None,
+    };
+
+    query_file!(
+      "src/jobs/data-store/save-job.sql",
+      id,
+      self._queue,
+      serialized,
+      finished_at,
+      self._registry.user(&job.job)
+    )
+    .fetch_one(&self._pg)
+    .await
+    .map_err(warn_and_return)?;
+
+    Ok(String::from(id))
+  }
+
+  // Idle workers wait to be notified of newly waiting jobs rather than polling, giving up once the
+  // queue delay has passed. Every wait is preceded by another claim, so that jobs queued while a
+  // different task held the listener are not missed.
+  async fn dequeue(&self) -> Result<Option<QueuedJob>> {
+    if let Some(job) = self.claim().await? {
+      return Ok(Some(job));
+    }
+
+    let wait = Duration::from_secs(self._queue_delay);
+    let attempt = timeout(wait, async {
+      let mut listener = self._listener.lock().await;
+
+      loop {
+        if let Some(job) = self.claim().await? {
+          return Ok(Some(job));
+        }
+
+        let notification = listener.recv().await.map_err(warn_and_return)?;
+        debug!("woken by job waiting on '{}'", notification.payload());
+      }
+    });
+
+    match attempt.await {
+      Ok(result) => result,
+      Err(_) => Ok(None),
+    }
+  }
+
+  async fn ack(&self, id: &str) -> Result<()> {
+    let released = query_file!("src/jobs/data-store/release-job.sql", id, self._queue)
+      .fetch_optional(&self._pg)
+      .await
+      .map_err(warn_and_return)?;
+
+    if let Some(row) = released.filter(|row| row.rerun) {
+      let job = deserialize::<QueuedJob>(&row.payload)?;
+      let again = self.queue(&self._registry.reset(&job.job)).await?;
+      info!(
+        "job '{}' asked for again while running, queued '{}'",
+        id, again
+      );
+    }
+
+    debug!("job '{}' acknowledged", id);
+    Ok(())
+  }
+
+  async fn heartbeat(&self, id: &str) -> Result<()> {
+    query_file!("src/jobs/data-store/heartbeat-job.sql", id, self._queue)
+      .execute(&self._pg)
+      .await
+      .map(|_| ())
+      .map_err(warn_and_return)
+  }
+
+  // Stale rows are locked while they are requeued, so that reapers running at the same time skip
+  // the jobs the other has already found.
+  async fn reap(&self, timeout: Duration) -> Result<Vec<String>> {
+    let mut conn = self._pg.acquire().await.map_err(warn_and_return)?;
+    let mut tx = conn.begin().await.map_err(warn_and_return)?;
+
+    let stale = query_file!(
+      "src/jobs/data-store/stale-jobs.sql",
+      self._queue,
+      millis(timeout)
+    )
+    .fetch_all(&mut tx)
+    .await
+    .map_err(warn_and_return)?;
+
+    let mut requeued = Vec::new();
+
+    for row in stale {
+      warn!(
+        "job '{}' from worker '{:?}' timed out, requeueing",
+        row.id, row.worker
+      );
+
+      let job = deserialize::<QueuedJob>(&row.payload)?.with_status(JobStatus::Queued);
+      let serialized = serialize(&job)?;
+
+      query_file!(
+        "src/jobs/data-store/move-job.sql",
+        row.id,
+        self._queue,
+        serialized,
+        WAITING,
+        Utc::now()
+      )
+      .fetch_optional(&mut tx)
+      .await
+      .map_err(warn_and_return)?;
+
+      requeued.push(row.id);
+    }
+
+    tx.commit().await.map_err(warn_and_return)?;
+    Ok(requeued)
+  }
+
+  async fn queue(&self, job: &Job) -> Result<String> {
+    let mut conn = self._pg.acquire().await.map_err(warn_and_return)?;
+    enqueue(&mut conn, &self._queue, &self._registry, job).await
+  }
+
+  async fn queue_within(
+    &self,
+    conn: &mut PgConnection,
+    jobs: &[Job],
+  ) -> Result<Option<Vec<String>>> {
+    let mut ids = Vec::with_capacity(jobs.len());
+
+    for job in jobs {
+      ids.push(enqueue(conn, &self._queue, &self._registry, job).await?);
+    }
+
+    Ok(Some(ids))
+  }
+
+  async fn queue_all(&self, jobs: &[Job]) -> Result<Vec<String>> {
+    let mut conn = self._pg.acquire().await.map_err(warn_and_return)?;
+    let mut tx = conn.begin().await.map_err(warn_and_return)?;
+    let mut ids = Vec::with_capacity(jobs.len());
+
+    for job in jobs {
+      ids.push(enqueue(&mut tx, &self._queue, &self._registry, job).await?);
+    }
+
+    tx.commit().await.map_err(warn_and_return)?;
+    Ok(ids)
+  }
+
+  async fn queue_at(&self, job: &Job, when: SystemTime) -> Result<String> {
+    let mut conn = self._pg.acquire().await.map_err(warn_and_return)?;
+    let uid = enqueue_at(&mut conn, &self._queue, &self._registry, job, when).await?;
+
+    debug!("job '{}' scheduled for {:?}", uid, when);
+    Ok(uid)
+  }
+
+  async fn cancel(&self, id: &str) -> Result<bool> {
+    let removed = query_file!("src/jobs/data-store/cancel-job.sql", id, self._queue)
+      .fetch_optional(&self._pg)
+      .await
+      .map_err(warn_and_return)?;
+
+    if removed.is_some() {
+      info!("job '{}' cancelled", id);
+    }
+
+    Ok(removed.is_some())
+  }
+
+  async fn requeue(&self, id: &str) -> Result<Option<String>> {
+    let existing = match self.lookup(id).await? {
+      Some(existing) => existing,
+      None => return Ok(None),
+    };
+
+    let reset = QueuedJob::new(id, self._registry.reset(&existing.job));
+
+    if !self.shift(&reset, WAITING, SystemTime::now()).await? {
+      return Ok(None);
+    }
+
+    info!("job '{}' requeued", id);
+    Ok(Some(String::from(id)))
+  }
+
+  async fn retry(&self, job: &QueuedJob, delay: Duration) -> Result<()> {
+    let queued = job.with_status(JobStatus::Queued);
+    self
+      .shift(&queued, SCHEDULED, SystemTime::now() + delay)
+      .await?;
+
+    info!(
+      "job '{}' scheduled for retry in {:?} (attempt {})",
+      job.id, delay, job.attempts
+    );
+    Ok(())
+  }
+
+  async fn promote(&self) -> Result<Vec<String>> {
+    let promoted = query_file!("src/jobs/data-store/promote-jobs.sql", self._queue)
+      .fetch_all(&self._pg)
+      .await
+      .map_err(warn_and_return)?;
+
+    Ok(promoted.into_iter().map(|row| row.id).collect())
+  }
+
+  async fn bury(&self, job: &QueuedJob) -> Result<()> {
+    let dead = job.with_status(JobStatus::Dead);
+    self.shift(&dead, DEAD, SystemTime::now()).await?;
+
+    warn!(
+      "job '{}' moved to dead letters after {} attempts",
+      job.id, job.attempts
+    );
+    Ok(())
+  }
+
+  async fn dead_letters(&self) -> Result<Vec<QueuedJob>> {
+    let rows = query_file!("src/jobs/data-store/dead-jobs.sql", self._queue)
+      .fetch_all(&self._pg)
+      .await
+      .map_err(warn_and_return)?;
+
+    let mut jobs = Vec::new();
+
+    for row in rows {
+      jobs.push(deserialize::<QueuedJob>(&row.payload)?);
+    }
+
+    Ok(jobs)
+  }
+
+  async fn waiting(&self) -> Result<Vec<QueuedJob>> {
+    self.list(&[WAITING, SCHEDULED]).await
+  }
+
+  async fn running(&self) -> Result<Vec<QueuedJob>> {
+    self.list(&[HELD]).await
+  }
+
+  async fn owned(&self, user: &str) -> Result<Vec<QueuedJob>> {
+    let rows = query_file!("src/jobs/data-store/owned-jobs.sql", self._queue, user)
+      .fetch_all(&self._pg)
+      .await
+      .map_err(warn_and_return)?;
+
+    let mut jobs = Vec::new();
+
+    for row in rows {
+      jobs.push(deserialize::<QueuedJob>(&row.payload)?);
+    }
+
+    Ok(jobs)
+  }
+
+  async fn finished(&self) -> Result<Vec<QueuedJob>> {
+    let rows = query_file!("src/jobs/data-store/finished-jobs.sql", self._queue)
+      .fetch_all(&self._pg)
+      .await
+      .map_err(warn_and_return)?;
+
+    let mut jobs = Vec::new();
+
+    for row in rows {
+      jobs.push(deserialize::<QueuedJob>(&row.payload)?);
+    }
+
+    Ok(jobs)
+  }
+
+  async fn replay(&self, id: &str) -> Result<Option<String>> {
+    match self.find(id).await? {
+      Some((_, state)) if state == DEAD => self.requeue(id).await,
+      _ => Ok(None),
+    }
+  }
+
+  // Dead jobs are kept until they are replayed, regardless of when they finished.
+  async fn expire(&self, retention: Duration) -> Result<Vec<String>> {
+    let expired = query_file!(
+      "src/jobs/data-store/expire-jobs.sql",
+      self._queue,
+      millis(retention)
+    )
+    .fetch_all(&self._pg)
+    .await
+    .map_err(warn_and_return)?;
+
+    Ok(expired.into_iter().map(|row| row.id).collect())
+  }
+
+  async fn health(&self) -> Health {
+    let size = self._pg.size() as usize;
+    let waiting = query_file!("src/jobs/data-store/count-waiting-jobs.sql", self._queue)
+      .fetch_one(&self._pg)
+      .await;
+
+    match waiting {
+      Ok(row) => {
+        debug!("{} jobs waiting on '{}'", row.waiting, self._queue);
+        Health {
+          healthy: true,
+          size,
+          connected: size,
+          failures: 0,
+          last_error: None,
+        }
+      }
+      Err(e) => Health {
+        healthy: false,
+        size,
+        connected: 0,
+        failures: 1,
+        last_error: Some(format!("{}", e)),
+      },
+    }
+  }
+}
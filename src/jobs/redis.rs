@@ -0,0 +1,1295 @@
+use async_std::channel::{unbounded, Sender};
+use async_std::io::timeout;
+use async_std::sync::{Arc, Mutex};
+use async_std::task::spawn;
+use async_trait::async_trait;
+use kramer::{Arity, Command, HashCommand, Insertion, Response, ResponseValue};
+use log::{debug, info, warn};
+use serde_json::{from_str as deserialize, to_string as serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::io::{ErrorKind, Result};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use super::JobStore;
+use crate::bg::registry::Registry;
+use crate::interchange::jobs::{DequeuedJob, Job, JobStatus, QueuedJob};
+use crate::redis::{Health, Pinned, Raw, Redis, Subscription};
+use crate::{errors, Configuration};
+
+// How many times a transaction over watched keys is tried again after others changed them first.
+const WATCH_ATTEMPTS: usize = 10;
+
+// Each store takes jobs off the shared queue into a processing list of its own, named after a worker
+// id generated when the store is created.
+pub struct RedisJobStore {
+  _redis: Arc<Redis>,
+  _keys: (String, String, String),
+  _queue_delay: u64,
+  _worker: String,
+  _registry: Arc<Registry>,
+  _settlements: Arc<Settlements>,
+}
+
+// Everyone waiting on jobs shares the one subscription to the settled channel, which is opened by
+// the first wait and forwards each settled id to whoever registered for it. When the subscription
+// fails, every waiter is let go and the next wait subscribes again.
+#[derive(Default)]
+struct Settlements {
+  _listening: Mutex<bool>,
+  _waiters: Mutex<HashMap<String, Vec<Sender<String>>>>,
+}
+
+impl Settlements {
+  async fn listen(settlements: &Arc<Self>, redis: &Redis, channel: &str) -> Result<()> {
+    let mut listening = settlements._listening.lock().await;
+
+    if *listening {
+      return Ok(());
+    }
+
+    let subscription = redis.subscribe(channel).await?;
+    spawn(Settlements::forward(settlements.clone(), subscription));
+    *listening = true;
+    Ok(())
+  }
+
+  async fn forward(settlements: Arc<Self>, mut subscription: Subscription) {
+    loop {
+      let id = match subscription.next().await {
+        Ok(id) => id,
+        Err(e) => {
+          warn!("settled subscription failed - {}", e);
+          let mut listening = settlements._listening.lock().await;
+          settlements._waiters.lock().await.clear();
+          *listening = false;
+          return;
+        }
+      };
+
+      let waiters = settlements._waiters.lock().await.remove(&id);
+
+      for waiter in waiters.unwrap_or_default() {
+        waiter.send(id.clone()).await.ok();
+      }
+    }
+  }
+
+  async fn register(&self, ids: &[String], waiter: &Sender<String>) {
+    let mut waiters = self._waiters.lock().await;
+
+    for id in ids {
+      waiters.entry(id.clone()).or_default().push(waiter.clone());
+    }
+  }
+
+  // Drops the senders of waiters that have given up, so ids that never settle are not held onto.
+  async fn release(&self, ids: &[String]) {
+    let mut waiters = self._waiters.lock().await;
+
+    for id in ids {
+      if let Some(senders) = waiters.get_mut(id) {
+        senders.retain(|sender| !sender.is_closed());
+
+        if senders.is_empty() {
+          waiters.remove(id);
+        }
+      }
+    }
+  }
+}
+
+fn dequeue_cmd(queue_key: &str, processing_key: &str, delay: u64) -> Raw {
+  Raw::new("BLMOVE")
+    .arg(queue_key)
+    .arg(processing_key)
+    .arg("LEFT")
+    .arg("RIGHT")
+    .arg(delay)
+}
+
+fn processing_key(queue_key: &str, worker: &str) -> String {
+  format!("{}:processing:{}", queue_key, worker)
+}
+
+// When each worker last went to take a job off the queue, in milliseconds. Reapers use this to tell
+// an id that a worker has only just moved onto its processing list from one left behind by a
+// worker that died before noting when it dequeued the job.
+fn workers_key(queue_key: &str) -> String {
+  format!("{}:workers", queue_key)
+}
+
+// Jobs waiting to be retried are kept in a sorted set scored by the time, in milliseconds, they are
+// due to be queued again.
+fn scheduled_key(queue_key: &str) -> String {
+  format!("{}:scheduled", queue_key)
+}
+
+fn dead_key(queue_key: &str) -> String {
+  format!("{}:dead", queue_key)
+}
+
+// The id of the latest job queued under a dedupe key, and the ids of running jobs that were asked
+// for again while they ran. Each dedupe key is kept apart so that it can be watched on its own.
+fn dedupe_key(queue_key: &str, key: &str) -> String {
+  format!("{}:dedupe:{}", queue_key, key)
+}
+
+fn rerun_key(queue_key: &str) -> String {
+  format!("{}:rerun", queue_key)
+}
+
+// Finished jobs are kept in a sorted set scored by the time, in milliseconds, they finished.
+fn finished_key(map_key: &str) -> String {
+  format!("{}:finished", map_key)
+}
+
+// The ids of the jobs belonging to each user, kept until the jobs are cancelled or expired.
+fn owned_key(map_key: &str, user: &str) -> String {
+  format!("{}:user:{}", map_key, user)
+}
+
+// The ids of jobs that have finished or died are published here, for anyone waiting on them.
+fn settled_channel(map_key: &str) -> String {
+  format!("{}:settled", map_key)
+}
+
+fn millis(time: SystemTime) -> u128 {
+  time
+    .duration_since(UNIX_EPOCH)
+    .map(|since| since.as_millis())
+    .unwrap_or(0)
+}
+
+fn ids(response: Response) -> Vec<String> {
+  match response {
+    Response::Array(values) => values
+      .into_iter()
+      .filter_map(|value| match value {
+        ResponseValue::String(id) => Some(id),
+        _ => None,
+      })
+      .collect(),
+    _ => vec![],
+  }
+}
+
+fn counted(response: Response) -> bool {
+  match response {
+    Response::Item(ResponseValue::Integer(count)) => count > 0,
+    _ => false,
+  }
+}
+
+impl RedisJobStore {
+  pub async fn open<C>(configuration: C) -> Result<Self>
+  where
+    C: std::ops::Deref<Target = Configuration>,
+  {
+    let redis = Redis::open(
+      configuration.job_store.redis_uri.as_str(),
+      &configuration.redis,
+    )
+    .await?;
+
+    Ok(RedisJobStore::with_redis(&configuration, Arc::new(redis)))
+  }
+
+  // Creates a job store on top of an existing redis pool, allowing it to be shared.
+  pub fn with_redis(configuration: &Configuration, redis: Arc<Redis>) -> Self {
+    let (queue, map, dequeue) = (
+      &configuration.job_store.queue_key,
+      &configuration.job_store.map_key,
+      &configuration.job_store.dequeue_key,
+    );
+
+    let delay = if configuration.job_store.queue_delay > 0 {
+      configuration.job_store.queue_delay
+    } else {
+      10
+    };
+
+    info!("job store ready, queue[{}] map[{}]", queue, map);
+
+    RedisJobStore {
+      _queue_delay: delay,
+      _redis: redis,
+      _keys: (queue.clone(), map.clone(), dequeue.clone()),
+      _worker: Uuid::new_v4().to_string(),
+      _registry: Arc::new(Registry::default()),
+      _settlements: Arc::new(Settlements::default()),
+    }
+  }
+
+  // Handlers of the registry decide which jobs are coalesced and how jobs are reset.
+  pub fn with_registry(self, registry: Arc<Registry>) -> Self {
+    RedisJobStore {
+      _registry: registry,
+      ..self
+    }
+  }
+
+  async fn command<K: Display, V: Display>(&self, cmd: &Command<K, V>) -> Result<Response> {
+    self._redis.execute(cmd).await
+  }
+
+  // Moves the next id from the queue onto this worker's processing list in a single command, so
+  // that a job is never held only in the memory of a worker that might crash.
+  async fn dequeue_next_id(&self) -> Result<Option<String>> {
+    let (queue_key, _, _) = &self._keys;
+    self.command_raw(&self.seen_command()).await?;
+
+    let processing = processing_key(queue_key, &self._worker);
+    let cmd = dequeue_cmd(queue_key, &processing, self._queue_delay);
+    let wait = Duration::from_secs(self._queue_delay);
+    let res = self._redis.execute_blocking(&cmd, wait).await?;
+
+    match res {
+      Response::Item(ResponseValue::String(id)) => {
+        info!("found queue entry - '{}'", id);
+        Ok(Some(id))
+      }
+      Response::Item(ResponseValue::Empty) => Ok(None),
+      other => {
+        info!("strange value popped from provisioning queue - {:?}", other);
+        Ok(None)
+      }
+    }
+  }
+
+  fn seen_command(&self) -> Raw {
+    let (queue_key, _, _) = &self._keys;
+    Raw::new("HSET")
+      .arg(workers_key(queue_key))
+      .arg(&self._worker)
+      .arg(millis(SystemTime::now()))
+  }
+
+  // Every worker that has dequeued a job, along with when it was last seen.
+  async fn workers(&self) -> Result<HashMap<String, u128>> {
+    let (queue_key, _, _) = &self._keys;
+    let all = Raw::new("HGETALL").arg(workers_key(queue_key));
+    let values = ids(self.command_raw(&all).await?);
+
+    let workers = values
+      .chunks(2)
+      .filter_map(|pair| match pair {
+        [worker, seen] => seen.parse().ok().map(|seen| (worker.clone(), seen)),
+        _ => None,
+      })
+      .collect();
+
+    Ok(workers)
+  }
+
+  // Whether a worker last seen at the given time is long gone, i.e it has not come back for another
+  // job within the timeout, on top of the time it may spend blocked waiting on the queue.
+  fn gone(&self, seen: Option<u128>, timeout: Duration) -> bool {
+    let grace = timeout + Duration::from_secs(self._queue_delay);
+
+    match seen {
+      Some(seen) => millis(SystemTime::now()).saturating_sub(seen) >= grace.as_millis(),
+      None => true,
+    }
+  }
+
+  // Puts the id back on the queue if it is still on the worker's processing list and has either
+  // timed out or, when it has no dequeue entry at all, was left there by a worker that is gone. The
+  // list and the dequeue entries are watched while deciding, so that a job acknowledged or
+  // heartbeat in the meantime is left alone.
+  async fn reclaim(&self, worker: &str, id: &str, timeout: Duration, gone: bool) -> Result<bool> {
+    let (queue_key, map_key, dequeue_key) = &self._keys;
+    let processing = processing_key(queue_key, worker);
+    let mut pinned = self._redis.pinned().await?;
+
+    for _ in 0..WATCH_ATTEMPTS {
+      pinned.watch(&[&processing, dequeue_key]).await?;
+
+      let range = Raw::new("LRANGE").arg(&processing).arg(0).arg(-1);
+      let listed = ids(pinned.execute(&range).await?)
+        .iter()
+        .any(|held| held == id);
+
+      let entry = match pinned
+        .execute(Raw::new("HGET").arg(dequeue_key).arg(id))
+        .await?
+      {
+        Response::Item(ResponseValue::String(serialized)) => {
+          Some(deserialize::<DequeuedJob>(&serialized)?)
+        }
+        _ => None,
+      };
+
+      let expired = match &entry {
+        Some(entry) => entry.expired(timeout),
+        None => gone,
+      };
+
+      if !expired || !(listed || entry.is_some()) {
+        pinned.unwatch().await?;
+        return Ok(false);
+      }
+
+      // Entries left behind for jobs no longer on their processing list are simply forgotten.
+      let mut commands = vec![Raw::new("HDEL").arg(dequeue_key).arg(id)];
+
+      if listed {
+        if let Response::Item(ResponseValue::String(serialized)) = pinned
+          .execute(Raw::new("HGET").arg(map_key).arg(id))
+          .await?
+        {
+          let job = deserialize::<QueuedJob>(&serialized)?;
+          commands.extend(self.save_commands(id, &job.with_status(JobStatus::Queued))?);
+        }
+
+        commands.push(Raw::new("LREM").arg(&processing).arg(0).arg(id));
+        commands.push(Raw::new("RPUSH").arg(queue_key).arg(id));
+      }
+
+      if pinned.transaction(&commands).await?.is_some() {
+        return Ok(listed);
+      }
+    }
+
+    warn!(
+      "job '{}' from worker '{}' kept changing, leaving it",
+      id, worker
+    );
+    Ok(false)
+  }
+
+  // Forgets a worker that is gone once nothing is left on its processing list.
+  async fn forget_worker(&self, worker: &str, timeout: Duration) -> Result<()> {
+    let (queue_key, _, _) = &self._keys;
+    let (workers, processing) = (workers_key(queue_key), processing_key(queue_key, worker));
+    let mut pinned = self._redis.pinned().await?;
+    pinned.watch(&[&workers, &processing]).await?;
+
+    let seen = match pinned
+      .execute(Raw::new("HGET").arg(&workers).arg(worker))
+      .await?
+    {
+      Response::Item(ResponseValue::String(seen)) => seen.parse().ok(),
+      _ => None,
+    };
+    let held = pinned.execute(Raw::new("LLEN").arg(&processing)).await?;
+
+    if !self.gone(seen, timeout) || counted(held) {
+      return pinned.unwatch().await;
+    }
+
+    let removal = [Raw::new("HDEL").arg(&workers).arg(worker)];
+
+    if pinned.transaction(&removal).await?.is_some() {
+      info!("forgot worker '{}'", worker);
+    }
+
+    Ok(())
+  }
+
+  async fn dequeued_entry(&self, id: &str) -> Result<Option<DequeuedJob>> {
+    let (_, _, dequeue_key) = &self._keys;
+    let lookup =
+      Command::Hashes::<_, &str>(HashCommand::Get(dequeue_key.as_str(), Some(Arity::One(id))));
+
+    match self.command(&lookup).await? {
+      Response::Item(ResponseValue::String(serialized)) => {
+        Ok(Some(deserialize::<DequeuedJob>(serialized.as_str())?))
+      }
+      _ => Ok(None),
+    }
+  }
+
+  async fn write_dequeued(&self, entry: &DequeuedJob) -> Result<()> {
+    let (_, _, dequeue_key) = &self._keys;
+    let serialized = serialize(entry)?;
+    let cmd = Command::Hashes(HashCommand::Set(
+      dequeue_key.as_str(),
+      Arity::One((entry.id.as_str(), serialized)),
+      Insertion::Always,
+    ));
+    self.command(&cmd).await.map(|_| ())
+  }
+
+  // The commands removing the job from the processing list it was moved onto and forgetting when
+  // it was dequeued. The first replies with whether the job was still being processed.
+  fn release_commands(&self, entry: &DequeuedJob) -> Vec<Raw> {
+    let (queue_key, _, dequeue_key) = &self._keys;
+    let processing = processing_key(queue_key, &entry.worker);
+
+    vec![
+      Raw::new("LREM").arg(processing).arg(0).arg(&entry.id),
+      Raw::new("HDEL").arg(dequeue_key).arg(&entry.id),
+    ]
+  }
+
+  async fn release(&self, entry: &DequeuedJob) -> Result<bool> {
+    let replies = self
+      ._redis
+      .transaction(&self.release_commands(entry))
+      .await?;
+
+    match replies.first() {
+      Some(ResponseValue::Integer(count)) => Ok(*count > 0),
+      _ => Ok(false),
+    }
+  }
+
+  // The commands saving the job, along with when it finished if it has.
+  fn save_commands(&self, id: &str, job: &QueuedJob) -> Result<Vec<Raw>> {
+    let (_, map_key, _) = &self._keys;
+    let serialized = serialize(job)?;
+
+    let finished = match (job.status.is_finished(), job.finished_at) {
+      (true, Some(at)) => Raw::new("ZADD")
+        .arg(finished_key(map_key))
+        .arg(millis(at))
+        .arg(id),
+      _ => Raw::new("ZREM").arg(finished_key(map_key)).arg(id),
+    };
+
+    let mut commands = vec![
+      Raw::new("HSET").arg(map_key).arg(id).arg(serialized),
+      finished,
+    ];
+
+    if job.status.is_settled() {
+      commands.push(Raw::new("PUBLISH").arg(settled_channel(map_key)).arg(id));
+    }
+
+    Ok(commands)
+  }
+
+  // The command indexing a new job under the user it belongs to, if it belongs to anyone.
+  fn own_command(&self, id: &str, job: &Job) -> Option<Raw> {
+    let (_, map_key, _) = &self._keys;
+    self
+      ._registry
+      .user(job)
+      .map(|user| Raw::new("SADD").arg(owned_key(map_key, &user)).arg(id))
+  }
+
+  // The commands removing the job along with its place in the index of its user's jobs.
+  fn forget_commands(&self, id: &str, job: Option<&QueuedJob>) -> Vec<Raw> {
+    let (_, map_key, _) = &self._keys;
+    let mut commands = vec![Raw::new("HDEL").arg(map_key).arg(id)];
+
+    if let Some(user) = job.and_then(|job| self._registry.user(&job.job)) {
+      commands.push(Raw::new("SREM").arg(owned_key(map_key, &user)).arg(id));
+    }
+
+    commands
+  }
+
+  // Applies the commands along with those releasing the job from its worker, then lets go of its
+  // dedupe key if it is done with.
+  async fn settle(&self, id: &str, mut commands: Vec<Raw>) -> Result<()> {
+    let entry = self
+      .dequeued_entry(id)
+      .await?
+      .unwrap_or_else(|| DequeuedJob::new(id, &self._worker));
+
+    commands.extend(self.release_commands(&entry));
+    self._redis.transaction(&commands).await?;
+    self.release_key(id).await
+  }
+
+  async fn dequeued_entries(&self) -> Result<Vec<DequeuedJob>> {
+    let (_, _, dequeue_key) = &self._keys;
+    let all = Command::Hashes::<_, &str>(HashCommand::Get(dequeue_key.as_str(), None));
+
+    let values = match self.command(&all).await? {
+      Response::Array(values) => values,
+      _ => return Ok(vec![]),
+    };
+
+    // The response alternates between field and value; only the values are needed.
+    let entries = values
+      .iter()
+      .skip(1)
+      .step_by(2)
+      .filter_map(|value| match value {
+        ResponseValue::String(serialized) => deserialize::<DequeuedJob>(serialized).ok(),
+        _ => None,
+      })
+      .collect();
+
+    Ok(entries)
+  }
+
+  async fn command_raw(&self, cmd: &Raw) -> Result<Response> {
+    self._redis.execute(cmd).await
+  }
+
+  // Removes the id from the dead-letter list, returning whether it was there.
+  async fn exhume(&self, id: &str) -> Result<bool> {
+    let (queue_key, _, _) = &self._keys;
+    let removal = Raw::new("LREM").arg(dead_key(queue_key)).arg(0).arg(id);
+
+    match self.command_raw(&removal).await? {
+      Response::Item(ResponseValue::Integer(count)) => Ok(count > 0),
+      _ => Ok(false),
+    }
+  }
+
+  fn schedule_command(&self, id: &str, when: SystemTime) -> Raw {
+    let (queue_key, _, _) = &self._keys;
+    Raw::new("ZADD")
+      .arg(scheduled_key(queue_key))
+      .arg(millis(when))
+      .arg(id)
+  }
+
+  // Removes the id from the scheduled set, returning whether it was there.
+  async fn unschedule(&self, id: &str) -> Result<bool> {
+    let (queue_key, _, _) = &self._keys;
+    let removal = Raw::new("ZREM").arg(scheduled_key(queue_key)).arg(id);
+
+    match self.command_raw(&removal).await? {
+      Response::Item(ResponseValue::Integer(count)) => Ok(count > 0),
+      _ => Ok(false),
+    }
+  }
+
+  // Moves the id from the scheduled set onto the queue if it is still due, watching the set so that
+  // an id promoted, cancelled or rescheduled by someone else in the meantime is left alone.
+  async fn promote_due(&self, id: &str, now: u128) -> Result<bool> {
+    let (queue_key, _, _) = &self._keys;
+    let scheduled = scheduled_key(queue_key);
+    let mut pinned = self._redis.pinned().await?;
+
+    for _ in 0..WATCH_ATTEMPTS {
+      pinned.watch(&[&scheduled]).await?;
+      let score = Raw::new("ZSCORE").arg(&scheduled).arg(id);
+
+      let due = match pinned.execute(&score).await? {
+        Response::Item(ResponseValue::String(score)) => score
+          .parse::<f64>()
+          .map(|at| at <= now as f64)
+          .unwrap_or(false),
+        _ => false,
+      };
+
+      if !due {
+        pinned.unwatch().await?;
+        return Ok(false);
+      }
+
+      let commands = [
+        Raw::new("ZREM").arg(&scheduled).arg(id),
+        Raw::new("RPUSH").arg(queue_key).arg(id),
+      ];
+
+      if pinned.transaction(&commands).await?.is_some() {
+        return Ok(true);
+      }
+    }
+
+    warn!("scheduled job '{}' kept changing, leaving it for later", id);
+    Ok(false)
+  }
+
+  // Returns the id of a pending job the job can be coalesced into, adding the command flagging it to
+  // run again if it is already running. Reads go through the pinned connection watching the dedupe
+  // keys. A pending job lets go of its key once it is no longer queued or running, so the commands
+  // are only applied while the job they coalesce into is still pending.
+  async fn coalesce(
+    &self,
+    pinned: &mut Pinned<'_>,
+    key: &str,
+    commands: &mut Vec<Raw>,
+  ) -> Result<Option<String>> {
+    let (queue_key, map_key, _) = &self._keys;
+    let entry = dedupe_key(queue_key, key);
+    pinned.watch(&[&entry]).await?;
+
+    let id = match pinned.execute(Raw::new("GET").arg(&entry)).await? {
+      Response::Item(ResponseValue::String(id)) => id,
+      _ => return Ok(None),
+    };
+
+    let status = match pinned
+      .execute(Raw::new("HGET").arg(map_key).arg(&id))
+      .await?
+    {
+      Response::Item(ResponseValue::String(serialized)) => {
+        deserialize::<QueuedJob>(&serialized)?.status
+      }
+      _ => return Ok(None),
+    };
+
+    match status {
+      JobStatus::Queued => Ok(Some(id)),
+      JobStatus::Running => {
+        commands.push(Raw::new("SADD").arg(rerun_key(queue_key)).arg(&id));
+        debug!("job '{}' is running, flagging it to run again", id);
+        Ok(Some(id))
+      }
+      _ => Ok(None),
+    }
+  }
+
+  // Lets go of the job's dedupe key once it is no longer pending, queueing it again if it was
+  // flagged while it ran. Jobs waiting to be retried keep their key. The flag is read and cleared
+  // while watching the keys `queue_all` writes when coalescing, so a flag set in between is seen.
+  async fn release_key(&self, id: &str) -> Result<()> {
+    let (queue_key, _, _) = &self._keys;
+    let rerun = rerun_key(queue_key);
+    let unflag = Raw::new("SREM").arg(&rerun).arg(id);
+
+    let job = self.lookup(id).await?;
+    let key = job
+      .as_ref()
+      .filter(|job| job.status != JobStatus::Queued)
+      .and_then(|job| self._registry.dedupe_key(&job.job));
+
+    let (job, key) = match (job, key) {
+      (Some(job), Some(key)) => (job, key),
+      _ => return self.command_raw(&unflag).await.map(|_| ()),
+    };
+
+    let dedupe = dedupe_key(queue_key, &key);
+    let mut pinned = self._redis.pinned().await?;
+
+    for _ in 0..WATCH_ATTEMPTS {
+      pinned.watch(&[&dedupe, &rerun]).await?;
+      let flagged = counted(
+        pinned
+          .execute(Raw::new("SISMEMBER").arg(&rerun).arg(id))
+          .await?,
+      );
+      let latest = pinned.execute(Raw::new("GET").arg(&dedupe)).await?;
+
+      let mut commands = vec![unflag.clone()];
+
+      match latest {
+        Response::Item(ResponseValue::String(latest)) if latest == id => {
+          commands.push(Raw::new("DEL").arg(&dedupe))
+        }
+        _ => (),
+      }
+
+      if pinned.transaction(&commands).await?.is_none() {
+        continue;
+      }
+
+      drop(pinned);
+
+      if flagged && job.status.is_finished() {
+        let again = self.queue(&self._registry.reset(&job.job)).await?;
+        info!(
+          "job '{}' asked for again while running, queued '{}'",
+          id, again
+        );
+      }
+
+      return Ok(());
+    }
+
+    Err(errors::e(format!("dedupe key '{}' kept changing", key)))
+  }
+
+  // The key is only removed while it still points at the job, so that a job queued under it in the
+  // meantime keeps it.
+  async fn forget_key(&self, key: &str, id: &str) -> Result<()> {
+    let (queue_key, _, _) = &self._keys;
+    let dedupe = dedupe_key(queue_key, key);
+    let mut pinned = self._redis.pinned().await?;
+
+    for _ in 0..WATCH_ATTEMPTS {
+      pinned.watch(&[&dedupe]).await?;
+
+      match pinned.execute(Raw::new("GET").arg(&dedupe)).await? {
+        Response::Item(ResponseValue::String(latest)) if latest == id => (),
+        _ => return pinned.unwatch().await,
+      }
+
+      let removal = [Raw::new("DEL").arg(&dedupe)];
+
+      if pinned.transaction(&removal).await?.is_some() {
+        return Ok(());
+      }
+    }
+
+    Err(errors::e(format!("dedupe key '{}' kept changing", key)))
+  }
+
+  // Jobs that have been forgotten since their ids were read are skipped.
+  async fn lookup_all(&self, ids: &[String]) -> Result<Vec<QueuedJob>> {
+    let mut jobs = Vec::new();
+
+    for id in ids {
+      if let Some(job) = self.deserialize_entry(id).await? {
+        jobs.push(job);
+      }
+    }
+
+    Ok(jobs)
+  }
+
+  async fn deserialize_entry(&self, id: &str) -> Result<Option<QueuedJob>> {
+    let (_, map_key, _) = &self._keys;
+    let lookup =
+      Command::Hashes::<_, &str>(HashCommand::Get(map_key.as_str(), Some(Arity::One(id))));
+    let res = self.command(&lookup).await?;
+
+    if let Response::Item(ResponseValue::String(serialized)) = res {
+      info!("pulled provisioning map entry - {:?}", serialized);
+      let attempt = deserialize::<QueuedJob>(serialized.as_str())?;
+      return Ok(Some(attempt));
+    }
+
+    info!(
+      "strange response from provisioning map for '{}' - {:?}",
+      id, res
+    );
+    Ok(None)
+  }
+}
+
+#[async_trait]
+impl JobStore for RedisJobStore {
+  async fn lookup(&self, id: &str) -> Result<Option<QueuedJob>> {
+    self.deserialize_entry(id).await
+  }
+
+  async fn update(&self, id: &str, job: &QueuedJob) -> Result<String> {
+    self
+      ._redis
+      .transaction(&self.save_commands(id, job)?)
+      .await?;
+    Ok(String::from(id))
+  }
+
+  async fn dequeue(&self) -> Result<Option<QueuedJob>> {
+    let next = self.dequeue_next_id().await?;
+    match next {
+      Some(id) => {
+        debug!("moved id '{}' to processing, writing dequeue job", id);
+        let (_, _, dequeue_key) = &self._keys;
+        let entry = DequeuedJob::new(&id, &self._worker);
+
+        let job = match self.deserialize_entry(&id).await? {
+          Some(job) => job,
+          None => {
+            warn!("dequeued id '{}' has no job, dropping", id);
+            self.release(&entry).await?;
+            return Ok(None);
+          }
+        };
+
+        let running = QueuedJob {
+          worker: Some(self._worker.clone()),
+          ..job.with_status(JobStatus::Running)
+        };
+
+        let mut commands = self.save_commands(&id, &running)?;
+        commands.push(
+          Raw::new("HSET")
+            .arg(dequeue_key)
+            .arg(&id)
+            .arg(serialize(&entry)?),
+        );
+        commands.push(self.seen_command());
+        self._redis.transaction(&commands).await?;
+        Ok(Some(running))
+      }
+      None => Ok(None),
+    }
+  }
+
+  async fn ack(&self, id: &str) -> Result<()> {
+    self.settle(id, vec![]).await?;
+    debug!("job '{}' acknowledged", id);
+    Ok(())
+  }
+
+  async fn heartbeat(&self, id: &str) -> Result<()> {
+    let worker = self
+      .dequeued_entry(id)
+      .await?
+      .map(|entry| entry.worker)
+      .unwrap_or_else(|| self._worker.clone());
+
+    self.write_dequeued(&DequeuedJob::new(id, &worker)).await
+  }
+
+  // Both the dequeue entries and the processing list of every known worker are walked, so that a
+  // job moved onto a processing list by a worker that died before writing its entry is not lost.
+  // Only ids still found on their processing list are requeued. Anything else has either been
+  // acknowledged or was reaped by someone else in the meantime.
+  async fn reap(&self, timeout: Duration) -> Result<Vec<String>> {
+    let (queue_key, _, _) = &self._keys;
+    let workers = self.workers().await?;
+    let mut held = self
+      .dequeued_entries()
+      .await?
+      .into_iter()
+      .map(|entry| (entry.worker, entry.id))
+      .collect::<Vec<_>>();
+
+    for worker in workers.keys() {
+      let range = Raw::new("LRANGE")
+        .arg(processing_key(queue_key, worker))
+        .arg(0)
+        .arg(-1);
+
+      for id in ids(self.command_raw(&range).await?) {
+        if !held.contains(&(worker.clone(), id.clone())) {
+          held.push((worker.clone(), id));
+        }
+      }
+    }
+
+    let mut requeued = Vec::new();
+
+    for (worker, id) in held {
+      let gone = self.gone(workers.get(&worker).copied(), timeout);
+
+      if !self.reclaim(&worker, &id, timeout, gone).await? {
+        continue;
+      }
+
+      warn!("job '{}' from worker '{}' timed out, requeued", id, worker);
+      requeued.push(id);
+    }
+
+    for (worker, seen) in &workers {
+      if self.gone(Some(*seen), timeout) {
+        self.forget_worker(worker, timeout).await?;
+      }
+    }
+
+    Ok(requeued)
+  }
+
+  async fn queue(&self, job: &Job) -> Result<String> {
+    let mut ids = self.queue_all(std::slice::from_ref(job)).await?;
+    ids.pop().ok_or_else(|| errors::e("job was not queued"))
+  }
+
+  // Jobs that can be coalesced are looked up first. Everything else is saved and pushed onto the
+  // queue in a single transaction, so a worker never pops an id whose job has not been written.
+  // The dedupe key of each job that can be coalesced is watched while it is looked up, starting
+  // over if one changes before the transaction is applied. Only those keys are watched; the job map
+  // is written by every worker and would keep the transaction from applying.
+  async fn queue_all(&self, jobs: &[Job]) -> Result<Vec<String>> {
+    let (queue_key, map_key, _) = &self._keys;
+    let coalescing = jobs
+      .iter()
+      .any(|job| self._registry.dedupe_key(job).is_some());
+    let mut pinned = self._redis.pinned().await?;
+
+    for _ in 0..WATCH_ATTEMPTS {
+      let mut ids = Vec::with_capacity(jobs.len());
+      let mut claimed: HashMap<String, String> = HashMap::new();
+      let mut commands = Vec::new();
+
+      for job in jobs {
+        let key = self._registry.dedupe_key(job);
+
+        if let Some(key) = &key {
+          let pending = match claimed.get(key) {
+            Some(id) => Some(id.clone()),
+            None => self.coalesce(&mut pinned, key, &mut commands).await?,
+          };
+
+          if let Some(id) = pending {
+            debug!("job coalesced into '{}' ({})", id, key);
+            ids.push(id);
+            continue;
+          }
+        }
+
+        let uid = Uuid::new_v4().to_string();
+        let serialized = serialize(&QueuedJob::new(&uid, job.clone()))?;
+        debug!("serialized job '{}' - '{}'", uid, serialized);
+
+        commands.push(Raw::new("HSET").arg(map_key).arg(&uid).arg(serialized));
+        commands.extend(self.own_command(&uid, job));
+
+        if let Some(key) = key {
+          commands.push(Raw::new("SET").arg(dedupe_key(queue_key, &key)).arg(&uid));
+          claimed.insert(key, uid.clone());
+        }
+
+        commands.push(Raw::new("RPUSH").arg(queue_key).arg(&uid));
+        ids.push(uid);
+      }
+
+      if commands.is_empty() {
+        if coalescing {
+          pinned.unwatch().await?;
+        }
+
+        return Ok(ids);
+      }
+
+      if pinned.transaction(&commands).await?.is_some() {
+        debug!("jobs {:?} inserted", ids);
+        return Ok(ids);
+      }
+
+      debug!("coalesced jobs changed while queueing, trying again");
+    }
+
+    Err(errors::e("coalesced jobs kept changing while queueing"))
+  }
+
+  async fn queue_at(&self, job: &Job, when: SystemTime) -> Result<String> {
+    let uid = Uuid::new_v4().to_string();
+    let mut commands = self.save_commands(&uid, &QueuedJob::new(&uid, job.clone()))?;
+    commands.extend(self.own_command(&uid, job));
+    commands.push(self.schedule_command(&uid, when));
+    self._redis.transaction(&commands).await?;
+
+    debug!("job '{}' scheduled for {:?}", uid, when);
+    Ok(uid)
+  }
+
+  // Jobs are only cancelled while they are waiting on the scheduled set or the queue, so that a job
+  // already being worked on is never pulled out from under its worker.
+  async fn cancel(&self, id: &str) -> Result<bool> {
+    let (queue_key, _, _) = &self._keys;
+    let removal = Raw::new("LREM").arg(queue_key).arg(0).arg(id);
+
+    let queued = match self.command_raw(&removal).await? {
+      Response::Item(ResponseValue::Integer(count)) => count > 0,
+      _ => false,
+    };
+
+    if !self.unschedule(id).await? && !queued {
+      return Ok(false);
+    }
+
+    let job = self.lookup(id).await?;
+
+    if let Some(key) = job
+      .as_ref()
+      .and_then(|job| self._registry.dedupe_key(&job.job))
+    {
+      self.forget_key(&key, id).await?;
+    }
+
+    self
+      ._redis
+      .transaction(&self.forget_commands(id, job.as_ref()))
+      .await?;
+
+    info!("job '{}' cancelled", id);
+    Ok(true)
+  }
+
+  async fn requeue(&self, id: &str) -> Result<Option<String>> {
+    let existing = match self.lookup(id).await? {
+      Some(existing) => existing,
+      None => return Ok(None),
+    };
+
+    let (queue_key, _, _) = &self._keys;
+    let reset = QueuedJob::new(id, self._registry.reset(&existing.job));
+    let mut commands = self.save_commands(id, &reset)?;
+
+    if let Some(entry) = self.dequeued_entry(id).await? {
+      commands.extend(self.release_commands(&entry));
+    }
+
+    commands.push(Raw::new("ZREM").arg(scheduled_key(queue_key)).arg(id));
+    commands.push(Raw::new("LREM").arg(dead_key(queue_key)).arg(0).arg(id));
+    commands.push(Raw::new("LREM").arg(queue_key).arg(0).arg(id));
+    commands.push(Raw::new("RPUSH").arg(queue_key).arg(id));
+    self._redis.transaction(&commands).await?;
+
+    info!("job '{}' requeued", id);
+    Ok(Some(String::from(id)))
+  }
+
+  async fn retry(&self, job: &QueuedJob, delay: Duration) -> Result<()> {
+    let mut commands = self.save_commands(&job.id, &job.with_status(JobStatus::Queued))?;
+    commands.push(self.schedule_command(&job.id, SystemTime::now() + delay));
+    self.settle(&job.id, commands).await?;
+
+    info!(
+      "job '{}' scheduled for retry in {:?} (attempt {})",
+      job.id, delay, job.attempts
+    );
+    Ok(())
+  }
+
+  // Each due id is removed from the scheduled set and pushed onto the queue in one transaction, so
+  // that workers promoting at the same time do not queue a job twice, nor lose it in between.
+  async fn promote(&self) -> Result<Vec<String>> {
+    let (queue_key, _, _) = &self._keys;
+    let now = millis(SystemTime::now());
+    let due = Raw::new("ZRANGEBYSCORE")
+      .arg(scheduled_key(queue_key))
+      .arg("-inf")
+      .arg(now);
+
+    let mut promoted = Vec::new();
+
+    for id in ids(self.command_raw(&due).await?) {
+      if !self.promote_due(&id, now).await? {
+        continue;
+      }
+
+      debug!("scheduled job '{}' is due, queued", id);
+      promoted.push(id);
+    }
+
+    Ok(promoted)
+  }
+
+  async fn bury(&self, job: &QueuedJob) -> Result<()> {
+    let (queue_key, _, _) = &self._keys;
+    let mut commands = self.save_commands(&job.id, &job.with_status(JobStatus::Dead))?;
+    commands.push(
+      Raw::new("LREM")
+        .arg(dead_key(queue_key))
+        .arg(0)
+        .arg(&job.id),
+    );
+    commands.push(Raw::new("RPUSH").arg(dead_key(queue_key)).arg(&job.id));
+    self.settle(&job.id, commands).await?;
+
+    warn!(
+      "job '{}' moved to dead letters after {} attempts",
+      job.id, job.attempts
+    );
+    Ok(())
+  }
+
+  async fn dead_letters(&self) -> Result<Vec<QueuedJob>> {
+    let (queue_key, _, _) = &self._keys;
+    let range = Raw::new("LRANGE").arg(dead_key(queue_key)).arg(0).arg(-1);
+    self.lookup_all(&ids(self.command_raw(&range).await?)).await
+  }
+
+  async fn waiting(&self) -> Result<Vec<QueuedJob>> {
+    let (queue_key, _, _) = &self._keys;
+    let queued = Raw::new("LRANGE").arg(queue_key).arg(0).arg(-1);
+    let scheduled = Raw::new("ZRANGE")
+      .arg(scheduled_key(queue_key))
+      .arg(0)
+      .arg(-1);
+
+    let mut pending = ids(self.command_raw(&queued).await?);
+    pending.extend(ids(self.command_raw(&scheduled).await?));
+    self.lookup_all(&pending).await
+  }
+
+  async fn running(&self) -> Result<Vec<QueuedJob>> {
+    let ids = self
+      .dequeued_entries()
+      .await?
+      .into_iter()
+      .map(|entry| entry.id)
+      .collect::<Vec<_>>();
+    self.lookup_all(&ids).await
+  }
+
+  async fn finished(&self) -> Result<Vec<QueuedJob>> {
+    let (_, map_key, _) = &self._keys;
+    let range = Raw::new("ZRANGE").arg(finished_key(map_key)).arg(0).arg(-1);
+    self.lookup_all(&ids(self.command_raw(&range).await?)).await
+  }
+
+  async fn owned(&self, user: &str) -> Result<Vec<QueuedJob>> {
+    let (_, map_key, _) = &self._keys;
+    let members = Raw::new("SMEMBERS").arg(owned_key(map_key, user));
+    let mut jobs = self
+      .lookup_all(&ids(self.command_raw(&members).await?))
+      .await?;
+    jobs.sort_by_key(|job| job.queued_at);
+    Ok(jobs)
+  }
+
+  async fn replay(&self, id: &str) -> Result<Option<String>> {
+    if !self.exhume(id).await? {
+      return Ok(None);
+    }
+
+    self.requeue(id).await
+  }
+
+  // As with promotion, only whoever removes an id from the finished set goes on to forget the job.
+  async fn expire(&self, retention: Duration) -> Result<Vec<String>> {
+    let (_, map_key, _) = &self._keys;
+    let cutoff = SystemTime::now()
+      .checked_sub(retention)
+      .unwrap_or(UNIX_EPOCH);
+    let stale = Raw::new("ZRANGEBYSCORE")
+      .arg(finished_key(map_key))
+      .arg("-inf")
+      .arg(millis(cutoff));
+
+    let mut expired = Vec::new();
+
+    for id in ids(self.command_raw(&stale).await?) {
+      let removal = Raw::new("ZREM").arg(finished_key(map_key)).arg(&id);
+
+      match self.command_raw(&removal).await? {
+        Response::Item(ResponseValue::Integer(count)) if count > 0 => (),
+        _ => continue,
+      }
+
+      let job = self.lookup(&id).await?;
+      self
+        ._redis
+        .transaction(&self.forget_commands(&id, job.as_ref()))
+        .await?;
+      expired.push(id);
+    }
+
+    Ok(expired)
+  }
+
+  // Registers with the shared subscription before looking at the jobs, so that a job settling in
+  // between is not missed.
+  async fn wait(&self, ids: &[String], limit: Duration) -> Result<()> {
+    let (_, map_key, _) = &self._keys;
+    let (sender, receiver) = unbounded();
+    self._settlements.register(ids, &sender).await;
+    drop(sender);
+
+    let waited: Result<()> = async {
+      Settlements::listen(&self._settlements, &self._redis, &settled_channel(map_key)).await?;
+      let mut pending = HashSet::new();
+
+      for id in ids {
+        match self.lookup(id).await? {
+          Some(job) if !job.status.is_settled() => pending.insert(id.clone()),
+          _ => continue,
+        };
+      }
+
+      let deadline = Instant::now() + limit;
+
+      while !pending.is_empty() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        match timeout(remaining, async { Ok(receiver.recv().await) }).await {
+          Ok(Ok(id)) => pending.remove(&id),
+          Ok(Err(_)) => break,
+          Err(e) if e.kind() == ErrorKind::TimedOut => break,
+          Err(e) => return Err(e),
+        };
+      }
+
+      Ok(())
+    }
+    .await;
+
+    drop(receiver);
+    self._settlements.release(ids).await;
+    waited
+  }
+
+  async fn health(&self) -> Health {
+    self._redis.health().await
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{processing_key, workers_key, RedisJobStore};
+  use crate::context::test_helpers::load_config;
+  use crate::interchange::jobs::{CreateLobby, Job, JobStatus};
+  use crate::jobs::JobStore;
+  use crate::redis::Raw;
+  use async_std::sync::Arc;
+  use async_std::task::{block_on, sleep, spawn};
+  use std::time::{Duration, Instant};
+  use uuid::Uuid;
+
+  fn open(name: &str) -> RedisJobStore {
+    let mut config = load_config().unwrap();
+    let prefix = format!("{}:{}", name, Uuid::new_v4());
+    config.job_store.queue_key = format!("{}:queue", prefix);
+    config.job_store.map_key = format!("{}:map", prefix);
+    config.job_store.dequeue_key = format!("{}:dequeue", prefix);
+    config.job_store.queue_delay = 1;
+    block_on(RedisJobStore::open(&config)).unwrap()
+  }
+
+  // Waits running at the same time are all woken by the store's one subscription, and leave nothing
+  // registered behind once they return.
+  #[test]
+  fn waits_share_one_subscription() {
+    let store = Arc::new(open("krumnet_wait_test"));
+
+    block_on(async {
+      let job = Job::CreateLobby(CreateLobby {
+        creator: String::from("waited"),
+        result: None,
+      });
+      let id = store.queue(&job).await.unwrap();
+      let running = store.dequeue().await.unwrap().unwrap();
+
+      let waiters = (0..8)
+        .map(|_| {
+          let (waiter, ids) = (store.clone(), vec![id.clone()]);
+          spawn(async move { waiter.wait(&ids, Duration::from_secs(10)).await.unwrap() })
+        })
+        .collect::<Vec<_>>();
+
+      sleep(Duration::from_millis(200)).await;
+      let started = Instant::now();
+      let succeeded = running.with_status(JobStatus::Succeeded);
+      store.update(&id, &succeeded).await.unwrap();
+      store.ack(&id).await.unwrap();
+
+      for waiter in waiters {
+        waiter.await;
+      }
+
+      assert!(started.elapsed() < Duration::from_secs(5));
+      assert!(*store._settlements._listening.lock().await);
+      assert!(store._settlements._waiters.lock().await.is_empty());
+    });
+  }
+
+  // A worker that dies after moving an id onto its processing list, but before writing down when it
+  // dequeued the job, leaves nothing in the dequeue entries for the reaper to find.
+  #[test]
+  fn reaps_ids_without_entries() {
+    let mut config = load_config().unwrap();
+    let prefix = format!("krumnet_reap_test:{}", Uuid::new_v4());
+    config.job_store.queue_key = format!("{}:queue", prefix);
+    config.job_store.map_key = format!("{}:map", prefix);
+    config.job_store.dequeue_key = format!("{}:dequeue", prefix);
+    config.job_store.queue_delay = 1;
+
+    block_on(async {
+      let store = RedisJobStore::open(&config).await.unwrap();
+      let queue_key = &config.job_store.queue_key;
+      let job = Job::CreateLobby(CreateLobby {
+        creator: String::from("reaped"),
+        result: None,
+      });
+      let id = store.queue(&job).await.unwrap();
+
+      let crashed = processing_key(queue_key, "crashed");
+      let commands = [
+        Raw::new("HSET")
+          .arg(workers_key(queue_key))
+          .arg("crashed")
+          .arg(0),
+        Raw::new("LMOVE")
+          .arg(queue_key)
+          .arg(&crashed)
+          .arg("LEFT")
+          .arg("RIGHT"),
+      ];
+      store._redis.transaction(&commands).await.unwrap();
+
+      let patience = Duration::from_secs(60);
+      assert_eq!(store.reap(patience).await.unwrap(), vec![id.clone()]);
+      assert!(store.reap(patience).await.unwrap().is_empty());
+      assert!(!store.workers().await.unwrap().contains_key("crashed"));
+      assert_eq!(store.dequeue().await.unwrap().unwrap().id, id);
+      store.ack(&id).await.unwrap();
+    });
+  }
+}
@@ -15,44 +15,44 @@ use elaine::{recognize, Head, RequestMethod};
 use log::{debug, error as fatal, info, warn};
 use serde::Serialize;
 
+pub mod api_tokens;
 pub mod authority;
 pub mod bg;
 pub mod configuration;
 pub mod constants;
 pub mod context;
+pub mod cookies;
+pub mod dev_auth;
 pub mod errors;
 pub mod http;
 pub mod interchange;
 pub mod jobs;
 pub mod names;
 pub mod oauth;
+pub mod oidc;
+pub mod outbound;
+pub mod policy;
 pub mod records;
+pub mod redis;
 pub mod routes;
 pub mod session;
 pub mod version;
 
 pub use crate::authority::Authority;
-pub use crate::configuration::{Configuration, GoogleCredentials};
+pub use crate::configuration::{Configuration, GoogleCredentials, JobBackend};
 pub use crate::context::{Context, ContextBuilder};
 pub use crate::http::{read_size_async, Response, Uri};
-pub use crate::jobs::JobStore;
+pub use crate::jobs::{JobStore, PostgresJobStore, RedisJobStore};
 pub use crate::records::{Connection as RecordConnection, RecordStore};
-pub use crate::session::Session as SessionStore;
+pub use crate::session::{RedisSessionStore, SessionStore};
 
 #[derive(Serialize)]
 struct HealthCheckData {
   #[serde(with = "chrono::serde::ts_milliseconds")]
   time: DateTime<Utc>,
   version: String,
-}
-
-impl Default for HealthCheckData {
-  fn default() -> Self {
-    HealthCheckData {
-      time: Utc::now(),
-      version: version::version(),
-    }
-  }
+  session_store: redis::Health,
+  job_store: redis::Health,
 }
 
 fn extract_parts(head: &Head) -> Result<(RequestMethod, String)> {
@@ -63,7 +63,13 @@ fn extract_parts(head: &Head) -> Result<(RequestMethod, String)> {
 
 async fn health_check(context: &Context) -> Result<Response> {
   info!("health check against context - '{:?}'", context);
-  Response::ok_json(HealthCheckData::default()).map(|r| r.cors(context.cors()))
+  let data = HealthCheckData {
+    time: Utc::now(),
+    version: version::version(),
+    session_store: context.session().health().await,
+    job_store: context.jobs().health().await,
+  };
+  Response::ok_json(data).map(|r| r.cors(context.cors()))
 }
 
 // Called for each new connection to the server, this is where requests are routed.
@@ -87,20 +93,61 @@ where
     // Authentication routing
     (RequestMethod::GET, "/auth/redirect") => {
       debug!("initiating oauth flow");
-      oauth::redirect(&ctx)
+      oauth::redirect(&ctx).await
     }
+    (RequestMethod::GET, "/auth/link") => oauth::link(&ctx).await,
+    (RequestMethod::POST, "/auth/link/confirm") => oauth::confirm_link(&ctx, &mut connection).await,
     (RequestMethod::GET, "/auth/identify") => routes::identify(&ctx).await,
-    (RequestMethod::GET, "/auth/destroy") => routes::destroy(&ctx, &uri).await,
+    (RequestMethod::POST, "/auth/destroy") => routes::destroy(&ctx, &uri).await,
     (RequestMethod::GET, "/auth/callback") => {
       debug!("oauth callback");
-      oauth::callback(&ctx, &uri).await
+      oauth::callback(&ctx, &head, &uri).await
     }
+    (RequestMethod::GET, "/auth/dev") => dev_auth::form(&ctx).await,
+    (RequestMethod::POST, "/auth/dev") => dev_auth::login(&ctx, &mut connection).await,
     // Basic health check for sanity
     (RequestMethod::GET, "/health-check") => {
       info!("health-check - '{}'", path);
       health_check(&ctx).await
     }
 
+    // Personal api tokens
+    (RequestMethod::GET, "/api-tokens") => routes::api_tokens::find(&ctx).await,
+    (RequestMethod::POST, "/api-tokens") => routes::api_tokens::create(&ctx, &mut connection).await,
+    (RequestMethod::DELETE, "/api-tokens") => {
+      routes::api_tokens::destroy(&ctx, &mut connection).await
+    }
+
+    // User profiles
+    (RequestMethod::PATCH, "/users/me") => routes::users::update(&ctx, &mut connection).await,
+    (RequestMethod::DELETE, "/users/me") => routes::users::destroy(&ctx).await,
+    (RequestMethod::GET, "/users/me/export") => routes::users::export(&ctx).await,
+    (RequestMethod::GET, path) if path.starts_with("/users/") => {
+      routes::users::find(&ctx, path.trim_start_matches("/users/")).await
+    }
+
+    // Moderation + operations
+    (RequestMethod::GET, "/admin/users") => routes::admin::find_user(&ctx, &uri).await,
+    (RequestMethod::POST, "/admin/users/role") => {
+      routes::admin::set_role(&ctx, &mut connection).await
+    }
+    (RequestMethod::POST, "/admin/users/merge") => {
+      routes::admin::merge_users(&ctx, &mut connection).await
+    }
+    (RequestMethod::POST, "/admin/lobbies/close") => {
+      routes::admin::close_lobby(&ctx, &mut connection).await
+    }
+    (RequestMethod::POST, "/admin/games/end") => {
+      routes::admin::end_game(&ctx, &mut connection).await
+    }
+    (RequestMethod::POST, "/admin/jobs/requeue") => {
+      routes::admin::requeue_job(&ctx, &mut connection).await
+    }
+    (RequestMethod::GET, "/admin/jobs/dead") => routes::admin::dead_letters(&ctx).await,
+    (RequestMethod::POST, "/admin/jobs/dead/replay") => {
+      routes::admin::replay_job(&ctx, &mut connection).await
+    }
+
     // Jobs
     (RequestMethod::GET, "/jobs") => routes::jobs::find(&ctx, &uri).await,
 
@@ -148,15 +195,47 @@ pub async fn serve(configuration: Configuration) -> Result<()> {
   let listener = TcpListener::bind(&configuration.addr).await?;
   let mut incoming = listener.incoming();
 
-  info!("opening session store");
-  let session = Arc::new(SessionStore::open(&configuration).await?);
+  info!("opening redis connection pool");
+  let session_redis = Arc::new(
+    redis::Redis::open(&configuration.session_store.redis_uri, &configuration.redis).await?,
+  );
 
-  info!("opening job store");
-  let jobs = Arc::new(JobStore::open(&configuration).await?);
+  info!("opening session store");
+  let session = Arc::new(RedisSessionStore::with_redis(
+    &configuration,
+    session_redis.clone(),
+  ));
 
   info!("opening record store");
   let records = Arc::new(RecordStore::open(&configuration).await?);
 
+  let registry = Arc::new(bg::registry::Registry::default());
+
+  info!("opening job store");
+  let jobs: Arc<dyn JobStore> = match configuration.job_store.backend {
+    // The stores share a single pool unless they have been pointed at different servers.
+    JobBackend::Redis => {
+      let job_redis = if configuration.job_store.redis_uri == configuration.session_store.redis_uri
+      {
+        session_redis
+      } else {
+        Arc::new(
+          redis::Redis::open(&configuration.job_store.redis_uri, &configuration.redis).await?,
+        )
+      };
+
+      Arc::new(RedisJobStore::with_redis(&configuration, job_redis).with_registry(registry.clone()))
+    }
+    JobBackend::Postgres => {
+      let store = PostgresJobStore::with_pool(&configuration, records.pool()).await?;
+      Arc::new(store.with_registry(registry.clone()))
+    }
+  };
+
+  info!("creating outbound http client");
+  let http: Arc<dyn outbound::HttpClient> = Arc::new(outbound::IsahcClient::new()?);
+  let keys = Arc::new(oidc::KeyCache::default());
+
   info!("accepting incoming tcp streams");
   while let Some(stream) = incoming.next().await {
     match stream {
@@ -165,7 +244,10 @@ pub async fn serve(configuration: Configuration) -> Result<()> {
           .configuration(&configuration)
           .jobs(jobs.clone())
           .session(session.clone())
-          .records(records.clone());
+          .records(records.clone())
+          .http(http.clone())
+          .keys(keys.clone())
+          .registry(registry.clone());
 
         task::spawn(async move {
           let result = route(&mut connection, builder).await;
@@ -1,40 +1,92 @@
-use isahc::HttpClient;
+use async_std::io::Read as AsyncRead;
+use elaine::Head;
 use log::{debug, info, warn};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::from_slice as deserialize;
 use sqlx::query_file;
 use std::io::{Error, ErrorKind, Result};
+use std::marker::Unpin;
+use std::time::Duration;
+use uuid::Uuid;
 
+use crate::authority::Scope;
 use crate::configuration::GoogleCredentials;
 use crate::constants::{
-  google_auth_url, google_info_url, google_token_url, GOOGLE_AUTH_CLIENT_ID_KEY,
-  GOOGLE_AUTH_REDIRECT_URI_KEY, GOOGLE_AUTH_RESPONSE_TYPE_KEY, GOOGLE_AUTH_RESPONSE_TYPE_VALUE,
-  GOOGLE_AUTH_SCOPE_KEY, GOOGLE_AUTH_SCOPE_VALUE,
+  GOOGLE_AUTH_CLIENT_ID_KEY, GOOGLE_AUTH_NONCE_KEY, GOOGLE_AUTH_REDIRECT_URI_KEY,
+  GOOGLE_AUTH_RESPONSE_TYPE_KEY, GOOGLE_AUTH_RESPONSE_TYPE_VALUE, GOOGLE_AUTH_SCOPE_KEY,
+  GOOGLE_AUTH_SCOPE_VALUE, GOOGLE_AUTH_STATE_KEY, GOOGLE_IDENTITY_PROVIDER, LINK_CONFLICT_KEY,
+  LINK_COOKIE_NAME, OAUTH_STATE_TTL, OAUTH_TAKEN_EMAIL_DOMAIN,
 };
-use crate::http::{header, query as qs, Method, Request, Response, Uri, Url};
-use crate::{errors, Context};
+use crate::http::{header, query as qs, Response, Uri, Url, COOKIE};
+use crate::interchange::{
+  self,
+  jobs::{Job, MergeUsers},
+};
+use crate::outbound::OutboundRequest;
+use crate::session;
+use crate::{cookies, errors, oidc, read_size_async, Authority, Context};
 
 // A TokenExchangePayload represents the response received from google oauth that contains the
 // authentication token that will be used in subsequent requests on behalf of this user.
 #[derive(Debug, PartialEq, Deserialize)]
 struct TokenExchangePayload {
   access_token: String,
+  id_token: String,
 }
 
 // The UserInfoPayload represents the data received from the google profile api.
 #[derive(Debug, Clone, Deserialize, Default)]
 struct UserInfoPayload {
+  #[serde(default)]
   name: String,
   sub: String,
+  #[serde(default)]
   email: String,
+  #[serde(default)]
+  email_verified: bool,
+  #[serde(default)]
   picture: String,
 }
 
+// Everything the callback needs to know about the attempt that started the flow, stashed in the
+// session store under the random state sent to google. When a user is present, the identity is
+// being linked to that user rather than used to sign in, and the browser finishing the flow must
+// present the binding in its link cookie.
+#[derive(Debug, Serialize, Deserialize)]
+struct OAuthState {
+  nonce: String,
+  #[serde(default)]
+  user: Option<String>,
+  #[serde(default)]
+  binding: Option<String>,
+}
+
+// An identity the user tried to link that already belongs to another user. Nothing is merged until
+// the user confirms it, see `confirm_link`.
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingMerge {
+  source: String,
+  target: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConfirmPayload {
+  id: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Linked {
+  Attached,
+  Conflict(String),
+}
+
 async fn exchange_code(code: &str, context: &Context) -> Result<TokenExchangePayload> {
-  let client = HttpClient::new().map_err(errors::humanize_error)?;
   let GoogleCredentials {
     client_id,
     client_secret,
     redirect_uri,
+    token_url,
+    ..
   } = &context.config().google;
 
   let encoded = qs::Serializer::new(String::new())
@@ -45,23 +97,19 @@ async fn exchange_code(code: &str, context: &Context) -> Result<TokenExchangePay
     .append_pair("grant_type", "authorization_code")
     .finish();
 
-  match client.post(google_token_url(), encoded) {
-    Ok(mut response) if response.status().is_success() => {
-      let body = response.body_mut();
-      let payload = match serde_json::from_reader(body) {
-        Ok(p) => p,
-        Err(e) => {
-          return Err(Error::new(
-            ErrorKind::Other,
-            format!("unable to parse response body: {:?}", e),
-          ));
-        }
-      };
-      Ok(payload)
-    }
+  // Authorization codes are single use; the exchange is never retried.
+  let request = OutboundRequest::post_form(token_url.as_str(), encoded);
+
+  match context.http().send(request).await {
+    Ok(response) if response.is_success() => response.json().map_err(|e| {
+      Error::new(
+        ErrorKind::Other,
+        format!("unable to parse response body: {:?}", e),
+      )
+    }),
     Ok(response) => Err(Error::new(
       ErrorKind::Other,
-      format!("bad response from google sso: {:?}", response.status()),
+      format!("bad response from google sso: {:?}", response.status),
     )),
     Err(e) => Err(Error::new(
       ErrorKind::Other,
@@ -72,37 +120,63 @@ async fn exchange_code(code: &str, context: &Context) -> Result<TokenExchangePay
 
 // Given the token returned from an oauth code exchange, load the user's information from the
 // google api.
-async fn fetch_info(info: TokenExchangePayload) -> Result<UserInfoPayload> {
-  let client = HttpClient::new().map_err(errors::humanize_error)?;
+async fn fetch_info(info: &TokenExchangePayload, context: &Context) -> Result<UserInfoPayload> {
   let bearer = format!("Bearer {}", info.access_token);
+  let request = OutboundRequest::get(context.config().google.info_url.as_str())
+    .header(header::AUTHORIZATION.as_str(), bearer.as_str());
 
-  let request = Request::builder()
-    .method(Method::GET)
-    .uri(google_info_url())
-    .header(header::AUTHORIZATION, bearer.as_str())
-    .body(())
-    .map_err(errors::humanize_error)?;
-
-  match client.send(request) {
-    Ok(mut response) if response.status() == 200 => {
-      serde_json::from_reader(response.body_mut()).map_err(errors::humanize_error)
-    }
+  match context.http().send(request).await {
+    Ok(response) if response.status == 200 => response.json(),
     Ok(response) => Err(Error::new(
       ErrorKind::Other,
-      format!("bad response satus from google sso: {}", response.status()),
+      format!("bad response satus from google sso: {}", response.status),
     )),
     Err(e) => Err(Error::new(ErrorKind::Other, format!("{}", e))),
   }
 }
 
+// Identity fields always come from the verified id token. The userinfo endpoint is only consulted
+// when the token is missing profile fields, and is only trusted for those.
+async fn load_profile(
+  payload: &TokenExchangePayload,
+  nonce: &str,
+  context: &Context,
+) -> Result<UserInfoPayload> {
+  let claims = oidc::verify(context, &payload.id_token, nonce).await?;
+
+  let (name, picture) = match (claims.name, claims.picture) {
+    (Some(name), Some(picture)) => (name, picture),
+    (name, picture) => {
+      let info = fetch_info(payload, context).await?;
+
+      if info.sub != claims.sub {
+        return Err(errors::e("userinfo subject does not match id token"));
+      }
+
+      (name.unwrap_or(info.name), picture.unwrap_or(info.picture))
+    }
+  };
+
+  Ok(UserInfoPayload {
+    name,
+    picture,
+    sub: claims.sub,
+    email: claims.email,
+    email_verified: claims.email_verified,
+  })
+}
+
 // Given user information loaded from the api, attempt to save the information into the persistence
-// engine, returning the newly created system id if successful.
+// engine, returning the newly created system id if successful. The email becomes the user's default
+// unless another user already has it (e.g an unverified account), in which case the new user is
+// given a placeholder address instead.
 async fn make_user(details: &UserInfoPayload, context: &Context) -> Result<String> {
   let UserInfoPayload {
     email,
     name,
     sub,
-    picture: _,
+    email_verified,
+    picture,
   } = details;
 
   let mut conn = context.records_connection().await?;
@@ -111,30 +185,99 @@ async fn make_user(details: &UserInfoPayload, context: &Context) -> Result<Strin
     "src/data-store/create-user.sql",
     email,
     name,
-    email,
-    name,
-    sub
+    GOOGLE_IDENTITY_PROVIDER,
+    sub,
+    email_verified,
+    picture,
+    OAUTH_TAKEN_EMAIL_DOMAIN
+  )
+  .fetch_all(&mut conn)
+  .await
+  .map_err(errors::humanize_error)?
+  .into_iter()
+  .nth(0)
+  .map(|row| row.user_id)
+  .ok_or_else(|| errors::e("Unable to find recently created user"))
+}
+
+async fn find_identity_owner(
+  profile: &UserInfoPayload,
+  context: &Context,
+) -> Result<Option<String>> {
+  let mut conn = context.records_connection().await?;
+
+  query_file!(
+    "src/data-store/find-user-by-identity.sql",
+    GOOGLE_IDENTITY_PROVIDER,
+    profile.sub
+  )
+  .fetch_all(&mut conn)
+  .await
+  .map_err(errors::humanize_error)
+  .map(|rows| rows.into_iter().nth(0).map(|row| row.user_id))
+}
+
+// Attaches the identity described by the profile to an existing user.
+async fn attach_identity(uid: &str, profile: &UserInfoPayload, context: &Context) -> Result<()> {
+  let mut conn = context.records_connection().await?;
+
+  query_file!(
+    "src/data-store/create-identity.sql",
+    uid,
+    GOOGLE_IDENTITY_PROVIDER,
+    profile.sub,
+    profile.email,
+    profile.email_verified,
+    profile.name
+  )
+  .fetch_all(&mut conn)
+  .await
+  .map_err(errors::humanize_error)?;
+
+  info!(
+    "attached '{}' identity to user '{}'",
+    GOOGLE_IDENTITY_PROVIDER, uid
+  );
+  Ok(())
+}
+
+// Users created before profiles existed have no avatar; seed it from the provider's picture the next
+// time they sign in without overwriting one they may have chosen since.
+async fn backfill_avatar(uid: &str, profile: &UserInfoPayload, context: &Context) -> Result<()> {
+  if profile.picture.is_empty() {
+    return Ok(());
+  }
+
+  let mut conn = context.records_connection().await?;
+  query_file!(
+    "src/data-store/backfill-user-avatar.sql",
+    uid,
+    profile.picture
   )
   .execute(&mut conn)
   .await
   .map_err(errors::humanize_error)?;
 
-  query_file!("src/data-store/find-user-by-google-id.sql", sub)
-    .fetch_all(&mut conn)
-    .await
-    .map_err(errors::humanize_error)?
-    .into_iter()
-    .nth(0)
-    .map(|row| row.user_id)
-    .ok_or_else(|| errors::e("Unable to find recently created user"))
+  Ok(())
 }
 
-// Attempt to find a user based on the google account id returned. If none is found, attempt to
-// find by the email address and make sure to backfill the google account. If there is still no
-// matching user information, attempt to create a new user and google account.
+// Attempt to find a user based on the identity returned. If none is found and the provider has
+// verified the email address, attempt to find by the email address and make sure to backfill the
+// identity. If there is still no matching user information, create a new user and identity.
 async fn find_or_create_user(profile: &UserInfoPayload, context: &Context) -> Result<String> {
-  let mut conn = context.records_connection().await?;
-  let id = query_file!("src/data-store/find-user-by-google-id.sql", profile.sub)
+  info!("loaded user info: {:?}", profile);
+
+  if let Some(id) = find_identity_owner(profile, context).await? {
+    backfill_avatar(&id, profile, context).await?;
+    return Ok(id);
+  }
+
+  if profile.email_verified {
+    let mut conn = context.records_connection().await?;
+    let existing = query_file!(
+      "src/data-store/find-user-by-verified-email.sql",
+      profile.email
+    )
     .fetch_all(&mut conn)
     .await
     .map_err(errors::humanize_error)?
@@ -142,18 +285,78 @@ async fn find_or_create_user(profile: &UserInfoPayload, context: &Context) -> Re
     .nth(0)
     .map(|row| row.user_id);
 
-  info!("loaded user info: {:?}", profile);
+    if let Some(id) = existing {
+      info!(
+        "matched user '{}' by verified email, backfilling identity",
+        id
+      );
+      attach_identity(&id, profile, context).await?;
+      backfill_avatar(&id, profile, context).await?;
+      return Ok(id);
+    }
+  }
 
-  match id {
-    Some(id) => Ok(id),
-    None => {
-      info!("no matching user, creating");
-      make_user(&profile, context).await
+  info!("no matching user, creating");
+  make_user(profile, context).await
+}
+
+// Attaches the identity to the user that started the link flow. If the identity already belongs
+// to some other user, the owner is returned so the user can be asked whether to merge the two.
+async fn link_identity(uid: &str, profile: &UserInfoPayload, context: &Context) -> Result<Linked> {
+  match find_identity_owner(profile, context).await? {
+    Some(owner) if owner == uid => {
+      debug!("identity already linked to user '{}'", uid);
+      Ok(Linked::Attached)
     }
+    Some(owner) => Ok(Linked::Conflict(owner)),
+    None => attach_identity(uid, profile, context)
+      .await
+      .map(|_| Linked::Attached),
   }
 }
 
-fn build_krumi_callback(context: &Context, token: &String) -> Result<String> {
+// Is the callback being finished by the browser that started the link flow?
+fn bound(state: &OAuthState, head: &Head) -> bool {
+  let presented = head
+    .find_header(COOKIE)
+    .and_then(|header| cookies::find(&header, LINK_COOKIE_NAME));
+
+  match (&state.binding, presented) {
+    (Some(binding), Some(presented)) => !binding.is_empty() && binding == &presented,
+    _ => false,
+  }
+}
+
+fn pending_merge_key(id: &str) -> String {
+  format!("link-conflict:{}", id)
+}
+
+// Stashes the conflict and sends the user back to krumi to confirm the merge with their existing
+// session.
+async fn conflict_response(context: &Context, merge: &PendingMerge) -> Result<Response> {
+  let id = Uuid::new_v4().to_string();
+  let value = serde_json::to_string(merge)?;
+
+  context
+    .session()
+    .stash(
+      &pending_merge_key(&id),
+      &value,
+      Duration::from_secs(OAUTH_STATE_TTL),
+    )
+    .await?;
+
+  let mut url = Url::parse(&context.config().krumi.auth_uri).map_err(errors::humanize_error)?;
+  url.query_pairs_mut().append_pair(LINK_CONFLICT_KEY, &id);
+
+  info!(
+    "identity owned by '{}' awaiting merge confirmation from '{}'",
+    merge.source, merge.target
+  );
+  Ok(Response::redirect(&url))
+}
+
+pub(crate) fn build_krumi_callback(context: &Context, token: &str) -> Result<String> {
   let mut parsed_callback =
     Url::parse(&context.config().krumi.auth_uri).map_err(errors::humanize_error)?;
 
@@ -164,7 +367,29 @@ fn build_krumi_callback(context: &Context, token: &String) -> Result<String> {
   Ok(parsed_callback.into_string())
 }
 
-pub async fn callback(context: &Context, uri: &Uri) -> Result<Response> {
+// Hands a newly created session to krumi. In cookie mode the token never appears in a url; the
+// browser is sent back to krumi carrying the session and csrf cookies instead.
+pub(crate) fn session_response(context: &Context, token: &str) -> Result<Response> {
+  let cookie = &context.config().session_store.cookie;
+
+  if !cookie.enabled {
+    return build_krumi_callback(context, token).map(|redir| Response::redirect(&redir));
+  }
+
+  let csrf = session::csrf_token(context.config(), token)?;
+  let max_age = context.config().session_store.expiration_timeout;
+
+  Ok(
+    cookies::session(cookie, token, &csrf, max_age)
+      .into_iter()
+      .fold(
+        Response::redirect(&context.config().krumi.auth_uri),
+        |response, value| response.cookie(value),
+      ),
+  )
+}
+
+pub async fn callback(context: &Context, head: &Head, uri: &Uri) -> Result<Response> {
   let query = uri.query().unwrap_or_default().as_bytes();
 
   let code = match qs::parse(query).find(|(key, _)| key == "code") {
@@ -172,6 +397,30 @@ pub async fn callback(context: &Context, uri: &Uri) -> Result<Response> {
     None => return Ok(Response::not_found()),
   };
 
+  // The state must still be waiting in the session store; this ties the callback to a flow that
+  // was started here and carries the nonce the id token must have been issued for.
+  let stashed = match qs::parse(query).find(|(key, _)| key == GOOGLE_AUTH_STATE_KEY) {
+    Some((_, state)) => context.session().claim(&state).await?,
+    None => None,
+  };
+
+  let state = match stashed.map(|value| serde_json::from_str::<OAuthState>(&value)) {
+    Some(Ok(state)) => state,
+    Some(Err(e)) => {
+      warn!("[warning] unable to parse oauth state: {}", e);
+      return Ok(Response::not_found());
+    }
+    None => {
+      warn!("[warning] oauth callback with missing, unknown or expired state");
+      return Ok(Response::not_found());
+    }
+  };
+
+  if state.user.is_some() && !bound(&state, head) {
+    warn!("[warning] identity link finished by a browser that did not start it");
+    return Ok(Response::not_found());
+  }
+
   let payload = match exchange_code(&code, context).await {
     Ok(payload) => payload,
     Err(e) => {
@@ -180,33 +429,57 @@ pub async fn callback(context: &Context, uri: &Uri) -> Result<Response> {
     }
   };
 
-  let profile = match fetch_info(payload).await {
+  let profile = match load_profile(&payload, &state.nonce, context).await {
     Ok(info) => info,
     Err(e) => {
-      warn!("[warning] unable to fetch user info: {}", e);
+      warn!("[warning] unable to verify user identity: {}", e);
       return Ok(Response::not_found());
     }
   };
 
   info!("received oauth callback - {:?}", profile.sub);
 
-  let uid = match find_or_create_user(&profile, context).await {
-    Ok(id) => id,
-    Err(e) => {
-      info!("[warning] unable to create/find user: {:?}", e);
-      return Ok(Response::not_found());
+  let uid = match state.user {
+    Some(uid) => {
+      let clear = cookies::clear_link(&context.config().session_store.cookie);
+
+      match link_identity(&uid, &profile, context).await {
+        Ok(Linked::Attached) => uid,
+        Ok(Linked::Conflict(source)) => {
+          let merge = PendingMerge {
+            source,
+            target: uid,
+          };
+          return conflict_response(context, &merge)
+            .await
+            .map(|response| response.cookie(clear));
+        }
+        Err(e) => {
+          warn!("[warning] unable to link identity to '{}': {:?}", uid, e);
+          return Ok(Response::not_found().cookie(clear));
+        }
+      }
     }
+    None => match find_or_create_user(&profile, context).await {
+      Ok(id) => id,
+      Err(e) => {
+        info!("[warning] unable to create/find user: {:?}", e);
+        return Ok(Response::not_found());
+      }
+    },
   };
 
   let token = context.session().create(&uid).await?;
   info!("created session for token '{}'", token);
 
-  build_krumi_callback(context, &token).map(|redir| Response::redirect(&redir))
+  session_response(context, &token)
 }
 
-pub fn redirect(context: &Context) -> Result<Response> {
+fn authorization_url(context: &Context, state: &str, nonce: &str) -> Result<Url> {
   let configuration = context.config();
-  let mut url = google_auth_url()
+  let mut url = configuration
+    .google
+    .auth_url
     .parse::<Url>()
     .map_err(errors::humanize_error)?;
 
@@ -222,9 +495,305 @@ pub fn redirect(context: &Context) -> Result<Response> {
       GOOGLE_AUTH_REDIRECT_URI_KEY,
       &configuration.google.redirect_uri,
     )
-    .append_pair(GOOGLE_AUTH_SCOPE_KEY, GOOGLE_AUTH_SCOPE_VALUE);
+    .append_pair(GOOGLE_AUTH_SCOPE_KEY, GOOGLE_AUTH_SCOPE_VALUE)
+    .append_pair(GOOGLE_AUTH_STATE_KEY, state)
+    .append_pair(GOOGLE_AUTH_NONCE_KEY, nonce);
 
-  debug!("oauth flow redirect to {:?}", url);
+  Ok(url)
+}
 
+// Stashes a fresh state and nonce for the flow, returning the url to send the user to.
+async fn begin(context: &Context, user: Option<String>, binding: Option<String>) -> Result<Url> {
+  let state = Uuid::new_v4().to_string();
+  let stashed = OAuthState {
+    nonce: Uuid::new_v4().to_string(),
+    user,
+    binding,
+  };
+  let value = serde_json::to_string(&stashed)?;
+
+  context
+    .session()
+    .stash(&state, &value, Duration::from_secs(OAUTH_STATE_TTL))
+    .await?;
+
+  authorization_url(context, &state, &stashed.nonce)
+}
+
+pub async fn redirect(context: &Context) -> Result<Response> {
+  let url = begin(context, None, None).await?;
+  debug!("oauth flow redirect to {:?}", url);
   Ok(Response::redirect(&url))
 }
+
+// Route
+// GET /auth/link
+pub async fn link(context: &Context) -> Result<Response> {
+  let uid = match context.authority() {
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::Account) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
+    Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
+  };
+
+  let binding = Uuid::new_v4().to_string();
+  let url = begin(context, Some(uid.clone()), Some(binding.clone())).await?;
+  let cookie = cookies::link(
+    &context.config().session_store.cookie,
+    &binding,
+    OAUTH_STATE_TTL,
+  );
+
+  debug!("identity link redirect for user '{}'", uid);
+  Ok(Response::redirect(&url).cookie(cookie))
+}
+
+// Route
+// POST /auth/link/confirm
+//
+// Merges the user owning an identity that a link flow found into the user that started the flow.
+// Only the user that started the flow may confirm it.
+pub async fn confirm_link<R>(context: &Context, reader: &mut R) -> Result<Response>
+where
+  R: AsyncRead + Unpin,
+{
+  let uid = match context.authority() {
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::Account) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
+    Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
+  };
+
+  let contents = read_size_async(reader, context.pending()).await?;
+  let payload = deserialize::<ConfirmPayload>(&contents)?;
+  let stashed = context
+    .session()
+    .claim(&pending_merge_key(&payload.id))
+    .await?;
+
+  let merge = match stashed.map(|value| serde_json::from_str::<PendingMerge>(&value)) {
+    Some(Ok(merge)) if &merge.target == uid => merge,
+    Some(Ok(merge)) => {
+      warn!(
+        "user '{}' attempted to confirm merge started by '{}'",
+        uid, merge.target
+      );
+      return Ok(Response::not_found().cors(context.cors()));
+    }
+    Some(Err(e)) => {
+      warn!("[warning] unable to parse pending merge: {}", e);
+      return Ok(Response::not_found().cors(context.cors()));
+    }
+    None => return Ok(Response::not_found().cors(context.cors())),
+  };
+
+  let job = Job::MergeUsers(MergeUsers {
+    source: merge.source.clone(),
+    target: merge.target.clone(),
+    result: None,
+  });
+  let id = context.jobs().queue(&job).await?;
+  info!(
+    "queued merge of user '{}' into '{}' (job '{}')",
+    merge.source, merge.target, id
+  );
+
+  Response::ok_json(interchange::http::JobHandle { id, result: None })
+    .map(|r| r.cors(context.cors()))
+}
+
+#[cfg(test)]
+mod test {
+  use super::{
+    bound, exchange_code, fetch_info, find_or_create_user, load_profile, make_user, OAuthState,
+    TokenExchangePayload, UserInfoPayload,
+  };
+  use crate::context::test_helpers::{cleanup_user, load_config};
+  use crate::jobs::MemoryJobStore;
+  use crate::oidc::test_helpers::{claims, sign, JWKS_URL};
+  use crate::outbound::{HttpClient, OutboundRequest, OutboundResponse};
+  use crate::session::MemorySessionStore;
+  use crate::{Authority, Context, RecordStore};
+  use async_std::sync::{Arc, Mutex};
+  use async_std::task::block_on;
+  use async_trait::async_trait;
+  use sqlx::query;
+  use std::io::Result;
+
+  struct StubClient {
+    requests: Mutex<Vec<OutboundRequest>>,
+    body: &'static str,
+  }
+
+  #[async_trait]
+  impl HttpClient for StubClient {
+    async fn send(&self, request: OutboundRequest) -> Result<OutboundResponse> {
+      self.requests.lock().await.push(request);
+      Ok(OutboundResponse {
+        status: 200,
+        body: self.body.as_bytes().to_vec(),
+      })
+    }
+  }
+
+  async fn stubbed(body: &'static str) -> (Context, Arc<StubClient>) {
+    let mut config = load_config().unwrap();
+    config.google.token_url = String::from("http://stub.local/token");
+    config.google.info_url = String::from("http://stub.local/info");
+    config.google.jwks_url = String::from(JWKS_URL);
+
+    let stub = Arc::new(StubClient {
+      requests: Mutex::new(Vec::new()),
+      body,
+    });
+
+    let context = Context::builder()
+      .configuration(&config)
+      .session(Arc::new(MemorySessionStore::new(&config)))
+      .records(Arc::new(RecordStore::open(&config).await.unwrap()))
+      .jobs(Arc::new(MemoryJobStore::new()))
+      .http(stub.clone())
+      .with_authority(Authority::None)
+      .unwrap();
+
+    (context, stub)
+  }
+
+  #[test]
+  fn exchange_uses_configured_url() {
+    block_on(async {
+      let (context, stub) = stubbed(r#"{"access_token":"abc","id_token":"jwt"}"#).await;
+      let payload = exchange_code("code", &context).await.unwrap();
+      assert_eq!(payload.access_token, "abc");
+
+      let requests = stub.requests.lock().await;
+      assert_eq!(requests[0].url, "http://stub.local/token");
+      assert_eq!(requests[0].retries, 0);
+    });
+  }
+
+  #[test]
+  fn fetch_info_sends_bearer() {
+    block_on(async {
+      let body = r#"{"name":"n","sub":"s","email":"e","picture":""}"#;
+      let (context, stub) = stubbed(body).await;
+      let token = TokenExchangePayload {
+        access_token: String::from("abc"),
+        id_token: String::from("jwt"),
+      };
+      let info = fetch_info(&token, &context).await.unwrap();
+      assert_eq!(info.sub, "s");
+
+      let requests = stub.requests.lock().await;
+      assert_eq!(requests[0].url, "http://stub.local/info");
+      assert!(requests[0]
+        .headers
+        .contains(&(String::from("authorization"), String::from("Bearer abc"))));
+    });
+  }
+
+  #[test]
+  fn profile_prefers_id_token() {
+    block_on(async {
+      let (context, stub) = stubbed("{}").await;
+      let mut token_claims = claims(&context.config().google.client_id, "n0nce");
+      token_claims["name"] = serde_json::json!("from token");
+      token_claims["picture"] = serde_json::json!("https://example.com/me.png");
+      let payload = TokenExchangePayload {
+        access_token: String::from("abc"),
+        id_token: sign(token_claims),
+      };
+
+      let profile = load_profile(&payload, "n0nce", &context).await.unwrap();
+      assert_eq!(profile.name, "from token");
+      assert!(profile.email_verified);
+      assert!(stub.requests.lock().await.is_empty());
+      assert!(load_profile(&payload, "other", &context).await.is_err());
+    });
+  }
+
+  #[test]
+  fn profile_falls_back_to_userinfo() {
+    block_on(async {
+      let body = r#"{"name":"from info","sub":"oidc-test","email":"ignored","picture":""}"#;
+      let (context, stub) = stubbed(body).await;
+      let payload = TokenExchangePayload {
+        access_token: String::from("abc"),
+        id_token: sign(claims(&context.config().google.client_id, "n0nce")),
+      };
+
+      let profile = load_profile(&payload, "n0nce", &context).await.unwrap();
+      assert_eq!(profile.name, "from info");
+      assert_eq!(profile.email, "oidc-test@krumnet.local");
+      assert_eq!(stub.requests.lock().await.len(), 1);
+    });
+  }
+
+  fn head(lines: &[&str]) -> elaine::Head {
+    lines
+      .iter()
+      .fold(elaine::Builder::new(), |builder, line| {
+        builder.insert(String::from(*line)).unwrap()
+      })
+      .collect::<elaine::Head>()
+  }
+
+  #[test]
+  fn link_callbacks_are_bound() {
+    let state = OAuthState {
+      nonce: String::from("n0nce"),
+      user: Some(String::from("user")),
+      binding: Some(String::from("b1nd")),
+    };
+    let legacy = OAuthState {
+      nonce: String::from("n0nce"),
+      user: Some(String::from("user")),
+      binding: None,
+    };
+
+    let starter = head(&["GET /auth/callback HTTP/1.1", "Cookie: krumnet_link=b1nd"]);
+    let other = head(&["GET /auth/callback HTTP/1.1", "Cookie: krumnet_link=other"]);
+    let none = head(&["GET /auth/callback HTTP/1.1"]);
+
+    assert!(bound(&state, &starter));
+    assert!(!bound(&state, &other));
+    assert!(!bound(&state, &none));
+    assert!(!bound(&legacy, &starter));
+  }
+
+  async fn cleanup_identities(context: &Context, id: &String) {
+    let mut conn = context.records_connection().await.unwrap();
+    query!("delete from krumnet.identities where user_id = $1", id)
+      .execute(&mut conn)
+      .await
+      .expect("unable to delete identities");
+    cleanup_user(id).await;
+  }
+
+  #[test]
+  fn unverified_emails_are_not_matched() {
+    block_on(async {
+      let (context, _stub) = stubbed("{}").await;
+      let email = String::from("oauth.unverified_emails_are_not_matched@krumnet.local");
+      let unverified = UserInfoPayload {
+        name: String::from("claimant"),
+        sub: String::from("oauth.unverified_emails_are_not_matched.claimant"),
+        email: email.clone(),
+        email_verified: false,
+        picture: String::new(),
+      };
+      let claimant = make_user(&unverified, &context).await.unwrap();
+
+      let verified = UserInfoPayload {
+        name: String::from("owner"),
+        sub: String::from("oauth.unverified_emails_are_not_matched.owner"),
+        email_verified: true,
+        ..unverified.clone()
+      };
+      let owner = find_or_create_user(&verified, &context).await.unwrap();
+
+      cleanup_identities(&context, &claimant).await;
+      cleanup_identities(&context, &owner).await;
+
+      assert_ne!(owner, claimant);
+    });
+  }
+}
@@ -0,0 +1,258 @@
+use async_std::sync::RwLock;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::io::Result;
+use std::time::{Duration, Instant};
+
+use crate::configuration::GoogleCredentials;
+use crate::constants::{JWKS_CACHE_TTL, JWKS_MIN_REFRESH};
+use crate::outbound::OutboundRequest;
+use crate::{errors, Context};
+
+const FILE_SCHEME: &'static str = "file://";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Jwk {
+  pub kid: String,
+  pub kty: String,
+  pub n: String,
+  pub e: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Jwks {
+  pub keys: Vec<Jwk>,
+}
+
+impl Jwks {
+  fn find(&self, kid: &str) -> Option<Jwk> {
+    self.keys.iter().find(|key| key.kid == kid).cloned()
+  }
+}
+
+// The claims we rely on from a verified id token. Audience and expiration are checked while
+// decoding; profile fields are optional and may need to be filled in from the userinfo endpoint.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdTokenClaims {
+  pub sub: String,
+  pub iss: String,
+  #[serde(default)]
+  pub email: String,
+  #[serde(default)]
+  pub email_verified: bool,
+  pub name: Option<String>,
+  pub picture: Option<String>,
+  pub nonce: Option<String>,
+}
+
+struct CachedKeys {
+  fetched: Instant,
+  keys: Jwks,
+}
+
+// Signing keys are cached for a while to avoid a round trip per login. A token signed by a key we
+// have not seen means the provider has most likely rotated its keys, so the set is reloaded early;
+// those reloads are rate limited so garbage tokens cannot be used to hammer the provider.
+#[derive(Default)]
+pub struct KeyCache {
+  state: RwLock<Option<CachedKeys>>,
+}
+
+async fn load_keys(context: &Context) -> Result<Jwks> {
+  let url = &context.config().google.jwks_url;
+  debug!("loading signing keys from '{}'", url);
+
+  if let Some(path) = url.strip_prefix(FILE_SCHEME) {
+    let contents = async_std::fs::read(path).await?;
+    return serde_json::from_slice(&contents).map_err(errors::humanize_error);
+  }
+
+  let response = context
+    .http()
+    .send(OutboundRequest::get(url.as_str()))
+    .await?;
+
+  if !response.is_success() {
+    return Err(errors::e(format!(
+      "bad response status loading signing keys: {}",
+      response.status
+    )));
+  }
+
+  response.json()
+}
+
+impl KeyCache {
+  pub async fn find(&self, context: &Context, kid: &str) -> Result<Option<Jwk>> {
+    if let Some(cached) = self.state.read().await.as_ref() {
+      let age = cached.fetched.elapsed();
+
+      if age < Duration::from_secs(JWKS_CACHE_TTL) {
+        if let Some(key) = cached.keys.find(kid) {
+          return Ok(Some(key));
+        }
+
+        if age < Duration::from_secs(JWKS_MIN_REFRESH) {
+          warn!("unknown signing key '{}', keys recently refreshed", kid);
+          return Ok(None);
+        }
+      }
+    }
+
+    let keys = load_keys(context).await?;
+    info!("loaded {} signing keys", keys.keys.len());
+    let key = keys.find(kid);
+
+    *self.state.write().await = Some(CachedKeys {
+      fetched: Instant::now(),
+      keys,
+    });
+
+    Ok(key)
+  }
+}
+
+fn decode_claims(
+  token: &str,
+  key: &Jwk,
+  credentials: &GoogleCredentials,
+  nonce: &str,
+) -> Result<IdTokenClaims> {
+  if key.kty != "RSA" {
+    return Err(errors::e(format!("unsupported key type '{}'", key.kty)));
+  }
+
+  let mut validation = Validation::new(Algorithm::RS256);
+  validation.set_audience(&[credentials.client_id.as_str()]);
+
+  let claims = decode::<IdTokenClaims>(
+    token,
+    &DecodingKey::from_rsa_components(&key.n, &key.e),
+    &validation,
+  )
+  .map_err(errors::humanize_error)?
+  .claims;
+
+  if !credentials.issuers.contains(&claims.iss) {
+    return Err(errors::e(format!("unexpected issuer '{}'", claims.iss)));
+  }
+
+  if claims.nonce.as_deref() != Some(nonce) {
+    return Err(errors::e("id token nonce mismatch"));
+  }
+
+  Ok(claims)
+}
+
+// Verifies the signature and claims of an id token returned from the code exchange, returning the
+// claims if the token was issued by the provider, for us, recently and for this login attempt.
+pub async fn verify(context: &Context, token: &str, nonce: &str) -> Result<IdTokenClaims> {
+  let header = decode_header(token).map_err(errors::humanize_error)?;
+
+  if header.alg != Algorithm::RS256 {
+    return Err(errors::e(format!(
+      "unsupported algorithm '{:?}'",
+      header.alg
+    )));
+  }
+
+  let kid = header
+    .kid
+    .ok_or_else(|| errors::e("id token missing key id"))?;
+
+  let key = context
+    .keys()
+    .find(context, &kid)
+    .await?
+    .ok_or_else(|| errors::e(format!("unknown signing key '{}'", kid)))?;
+
+  decode_claims(token, &key, &context.config().google, nonce)
+}
+
+#[cfg(test)]
+pub mod test_helpers {
+  use chrono::Utc;
+  use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+  use serde_json::json;
+
+  pub const JWKS_URL: &'static str = "file://ci/fixtures/oidc-jwks.json";
+  pub const KEY_ID: &'static str = "krumnet-test";
+  const SIGNING_KEY: &'static [u8] = include_bytes!("../ci/fixtures/oidc-signing-key.pem");
+
+  pub fn sign(claims: serde_json::Value) -> String {
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(String::from(KEY_ID));
+    let key = EncodingKey::from_rsa_pem(SIGNING_KEY).expect("invalid signing key");
+    encode(&header, &claims, &key).expect("unable to sign")
+  }
+
+  pub fn claims(audience: &str, nonce: &str) -> serde_json::Value {
+    json!({
+      "iss": "https://accounts.google.com",
+      "aud": audience,
+      "sub": "oidc-test",
+      "email": "oidc-test@krumnet.local",
+      "email_verified": true,
+      "exp": Utc::now().timestamp() + 60,
+      "nonce": nonce,
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::test_helpers::{claims, sign, JWKS_URL};
+  use super::{decode_claims, verify, Jwks};
+  use crate::context::test_helpers::{load_config, with_config};
+  use crate::{Authority, GoogleCredentials};
+  use async_std::task::block_on;
+
+  fn fixture() -> (Jwks, GoogleCredentials) {
+    let keys = std::fs::read("ci/fixtures/oidc-jwks.json").unwrap();
+    let mut credentials = GoogleCredentials::default();
+    credentials.client_id = String::from("krumnet-test-client");
+    (serde_json::from_slice(&keys).unwrap(), credentials)
+  }
+
+  #[test]
+  fn accepts_valid_token() {
+    let (keys, credentials) = fixture();
+    let token = sign(claims("krumnet-test-client", "n0nce"));
+    let decoded = decode_claims(&token, &keys.keys[0], &credentials, "n0nce").unwrap();
+    assert_eq!(decoded.sub, "oidc-test");
+    assert!(decoded.email_verified);
+  }
+
+  #[test]
+  fn rejects_bad_claims() {
+    let (keys, credentials) = fixture();
+    let key = &keys.keys[0];
+
+    let wrong_audience = sign(claims("someone-else", "n0nce"));
+    assert!(decode_claims(&wrong_audience, key, &credentials, "n0nce").is_err());
+
+    let wrong_nonce = sign(claims("krumnet-test-client", "n0nce"));
+    assert!(decode_claims(&wrong_nonce, key, &credentials, "other").is_err());
+
+    let mut expired = claims("krumnet-test-client", "n0nce");
+    expired["exp"] = serde_json::json!(1);
+    assert!(decode_claims(&sign(expired), key, &credentials, "n0nce").is_err());
+
+    let mut issuer = claims("krumnet-test-client", "n0nce");
+    issuer["iss"] = serde_json::json!("https://evil.example.com");
+    assert!(decode_claims(&sign(issuer), key, &credentials, "n0nce").is_err());
+  }
+
+  #[test]
+  fn verify_loads_keys_from_file() {
+    let mut config = load_config().unwrap();
+    config.google.jwks_url = String::from(JWKS_URL);
+    let audience = config.google.client_id.clone();
+    let context = with_config(config, Authority::None);
+
+    let token = sign(claims(&audience, "n0nce"));
+    let decoded = block_on(verify(&context, &token, "n0nce")).unwrap();
+    assert_eq!(decoded.sub, "oidc-test");
+  }
+}
@@ -0,0 +1,228 @@
+use async_std::io::ReadExt;
+use async_std::task::sleep;
+use async_trait::async_trait;
+use isahc::config::Configurable;
+use log::{debug, warn};
+use std::io::{Error, Result};
+use std::time::Duration;
+
+use crate::constants::{
+  OUTBOUND_REQUEST_RETRIES, OUTBOUND_REQUEST_TIMEOUT, OUTBOUND_RETRY_BACKOFF,
+};
+use crate::errors;
+use crate::http::{header, Method, Request};
+
+// An OutboundRequest describes a call made by the server to some other service, e.g the google
+// oauth apis. Only idempotent requests are retried.
+#[derive(Debug, Clone)]
+pub struct OutboundRequest {
+  pub method: Method,
+  pub url: String,
+  pub headers: Vec<(String, String)>,
+  pub body: Option<String>,
+  pub timeout: Duration,
+  pub retries: u8,
+}
+
+impl OutboundRequest {
+  pub fn get<S: Into<String>>(url: S) -> Self {
+    OutboundRequest {
+      method: Method::GET,
+      url: url.into(),
+      headers: Vec::new(),
+      body: None,
+      timeout: Duration::from_millis(OUTBOUND_REQUEST_TIMEOUT),
+      retries: OUTBOUND_REQUEST_RETRIES,
+    }
+  }
+
+  pub fn post_form<S: Into<String>>(url: S, body: String) -> Self {
+    OutboundRequest {
+      method: Method::POST,
+      body: Some(body),
+      retries: 0,
+      ..OutboundRequest::get(url)
+    }
+    .header(
+      header::CONTENT_TYPE.as_str(),
+      "application/x-www-form-urlencoded",
+    )
+  }
+
+  pub fn header(mut self, name: &str, value: &str) -> Self {
+    self.headers.push((String::from(name), String::from(value)));
+    self
+  }
+
+  pub fn timeout(self, timeout: Duration) -> Self {
+    OutboundRequest { timeout, ..self }
+  }
+
+  pub fn retries(self, retries: u8) -> Self {
+    OutboundRequest { retries, ..self }
+  }
+
+  fn idempotent(&self) -> bool {
+    self.method == Method::GET || self.method == Method::HEAD
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboundResponse {
+  pub status: u16,
+  pub body: Vec<u8>,
+}
+
+impl OutboundResponse {
+  pub fn is_success(&self) -> bool {
+    (200..300).contains(&self.status)
+  }
+
+  pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+    serde_json::from_slice(&self.body).map_err(errors::humanize_error)
+  }
+}
+
+#[async_trait]
+pub trait HttpClient: Send + Sync {
+  async fn send(&self, request: OutboundRequest) -> Result<OutboundResponse>;
+}
+
+pub struct IsahcClient {
+  client: isahc::HttpClient,
+}
+
+impl IsahcClient {
+  pub fn new() -> Result<Self> {
+    let client = isahc::HttpClient::new().map_err(errors::humanize_error)?;
+    Ok(IsahcClient { client })
+  }
+
+  async fn attempt(&self, request: &OutboundRequest) -> Result<OutboundResponse> {
+    let mut builder = Request::builder()
+      .method(request.method.clone())
+      .uri(request.url.as_str())
+      .timeout(request.timeout);
+
+    for (name, value) in &request.headers {
+      builder = builder.header(name.as_str(), value.as_str());
+    }
+
+    let outgoing = builder
+      .body(request.body.clone().unwrap_or_default())
+      .map_err(errors::humanize_error)?;
+
+    // Keeps the kind of connection failures and timeouts, e.g `ErrorKind::TimedOut`.
+    let mut response = self
+      .client
+      .send_async(outgoing)
+      .await
+      .map_err(Error::from)?;
+
+    let mut body = Vec::new();
+    response.body_mut().read_to_end(&mut body).await?;
+
+    Ok(OutboundResponse {
+      status: response.status().as_u16(),
+      body,
+    })
+  }
+}
+
+// Connection errors and server errors are considered transient and worth retrying. Anything else
+// will most likely fail the same way a second time.
+fn should_retry(result: &Result<OutboundResponse>) -> bool {
+  match result {
+    Ok(response) => response.status >= 500,
+    Err(_) => true,
+  }
+}
+
+#[async_trait]
+impl HttpClient for IsahcClient {
+  async fn send(&self, request: OutboundRequest) -> Result<OutboundResponse> {
+    let attempts = if request.idempotent() {
+      request.retries + 1
+    } else {
+      1
+    };
+
+    let mut attempt = 1;
+
+    loop {
+      debug!(
+        "{} '{}' (attempt {}/{})",
+        request.method, request.url, attempt, attempts
+      );
+      let result = self.attempt(&request).await;
+
+      if attempt >= attempts || !should_retry(&result) {
+        return result;
+      }
+
+      warn!(
+        "outbound request to '{}' failed (attempt {}/{}), retrying",
+        request.url, attempt, attempts
+      );
+      sleep(Duration::from_millis(
+        OUTBOUND_RETRY_BACKOFF * attempt as u64,
+      ))
+      .await;
+      attempt += 1;
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{HttpClient, IsahcClient, OutboundRequest};
+  use async_std::task::block_on;
+  use mockito::mock;
+  use std::io::ErrorKind;
+  use std::net::TcpListener;
+  use std::time::Duration;
+
+  #[test]
+  fn retries_idempotent_requests() {
+    let server = mock("GET", "/outbound/retries")
+      .with_status(503)
+      .expect(3)
+      .create();
+
+    let client = IsahcClient::new().unwrap();
+    let url = format!("{}/outbound/retries", mockito::server_url());
+    let response = block_on(client.send(OutboundRequest::get(url).retries(2))).unwrap();
+
+    assert_eq!(response.status, 503);
+    server.assert();
+  }
+
+  #[test]
+  fn does_not_retry_posts() {
+    let server = mock("POST", "/outbound/post")
+      .with_status(503)
+      .expect(1)
+      .create();
+
+    let client = IsahcClient::new().unwrap();
+    let url = format!("{}/outbound/post", mockito::server_url());
+    let response = block_on(client.send(OutboundRequest::post_form(url, String::new()))).unwrap();
+
+    assert!(!response.is_success());
+    server.assert();
+  }
+
+  // The listener's backlog accepts the connection, but nothing ever answers the request.
+  #[test]
+  fn times_out() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let client = IsahcClient::new().unwrap();
+    let request = OutboundRequest::get(url)
+      .timeout(Duration::from_millis(50))
+      .retries(0);
+
+    let error = block_on(client.send(request)).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::TimedOut);
+  }
+}
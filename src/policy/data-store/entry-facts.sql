@@ -0,0 +1,9 @@
+select
+  entries.id       as entry_id,
+  entries.round_id as round_id,
+  entries.user_id  as author_id
+from
+  krumnet.game_round_entries as entries
+where
+  entries.id = $1
+limit 1;
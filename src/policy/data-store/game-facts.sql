@@ -0,0 +1,15 @@
+select
+  games.id        as game_id,
+  games.lobby_id  as lobby_id,
+  members.id      as "member_id?"
+from
+  krumnet.games as games
+left join
+  krumnet.game_memberships as members
+on
+  members.game_id = games.id
+and
+  members.user_id = $2
+where
+  games.id = $1
+limit 1;
@@ -0,0 +1,18 @@
+select
+  lobbies.id         as lobby_id,
+  lobbies.name       as lobby_name,
+  lobbies.created_at as created_at,
+  lobbies.closed_at  as closed_at,
+  members.id         as "member_id?",
+  members.left_at    as "member_left_at?"
+from
+  krumnet.lobbies as lobbies
+left join
+  krumnet.lobby_memberships as members
+on
+  members.lobby_id = lobbies.id
+and
+  members.user_id = $2
+where
+  lobbies.id = $1
+limit 1;
@@ -0,0 +1,25 @@
+select
+  rounds.id           as round_id,
+  rounds.game_id      as game_id,
+  games.lobby_id      as lobby_id,
+  games.ended_at      as ended_at,
+  rounds.started_at   as started_at,
+  rounds.fulfilled_at as fulfilled_at,
+  rounds.completed_at as completed_at,
+  members.id          as "member_id?",
+  members.left_at     as "member_left_at?"
+from
+  krumnet.game_rounds as rounds
+inner join
+  krumnet.games as games
+on
+  games.id = rounds.game_id
+left join
+  krumnet.game_memberships as members
+on
+  members.game_id = rounds.game_id
+and
+  members.user_id = $2
+where
+  rounds.id = $1
+limit 1;
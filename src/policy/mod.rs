@@ -0,0 +1,492 @@
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use sqlx::query_file;
+use std::io::Result;
+
+use crate::authority::{Role, Scope};
+use crate::{errors, Authority, Context, Response};
+
+// The reason a user was not allowed to do something. Requests for things the user has no
+// relationship to are answered as though the thing does not exist, while members attempting
+// something their membership does not currently allow are told why.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Denial {
+  Unauthenticated,
+  NotFound,
+  NotMember,
+  InsufficientRole,
+  Left,
+  Closed,
+  RoundNotActive,
+  OwnEntry,
+}
+
+impl Denial {
+  pub fn reason(&self) -> &'static str {
+    match self {
+      Denial::Unauthenticated => "errors.policy.unauthenticated",
+      Denial::NotFound => "errors.policy.not_found",
+      Denial::NotMember => "errors.policy.not_member",
+      Denial::InsufficientRole => "errors.policy.insufficient_role",
+      Denial::Left => "errors.policy.left",
+      Denial::Closed => "errors.policy.closed",
+      Denial::RoundNotActive => "errors.policy.round_not_active",
+      Denial::OwnEntry => "errors.policy.own_entry",
+    }
+  }
+
+  pub fn response(&self, context: &Context) -> Response {
+    let response = match self {
+      Denial::Unauthenticated => Response::unauthorized(),
+      Denial::NotFound | Denial::NotMember => Response::not_found(),
+      _ => Response::forbidden_reason(self.reason()),
+    };
+
+    response.cors(context.cors())
+  }
+}
+
+pub type Decision<T> = std::result::Result<T, Denial>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LobbyAccess {
+  pub lobby_id: String,
+  pub name: String,
+  pub created: Option<DateTime<Utc>>,
+  pub member_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameAccess {
+  pub game_id: String,
+  pub lobby_id: String,
+  pub member_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundAccess {
+  pub lobby_id: String,
+  pub game_id: String,
+  pub round_id: String,
+  pub member_id: String,
+  pub user_id: String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct LobbyFacts {
+  lobby_id: String,
+  name: String,
+  created: Option<DateTime<Utc>>,
+  closed: Option<DateTime<Utc>>,
+  member_id: Option<String>,
+  left: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct GameFacts {
+  game_id: String,
+  lobby_id: String,
+  member_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct RoundFacts {
+  round_id: String,
+  game_id: String,
+  lobby_id: String,
+  ended: Option<DateTime<Utc>>,
+  started: Option<DateTime<Utc>>,
+  fulfilled: Option<DateTime<Utc>>,
+  completed: Option<DateTime<Utc>>,
+  member_id: Option<String>,
+  left: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct EntryFacts {
+  round_id: String,
+  author_id: String,
+}
+
+async fn lobby_facts(
+  context: &Context,
+  user_id: &str,
+  lobby_id: &str,
+) -> Result<Option<LobbyFacts>> {
+  let mut conn = context.records_connection().await?;
+
+  query_file!("src/policy/data-store/lobby-facts.sql", lobby_id, user_id)
+    .fetch_all(&mut conn)
+    .await
+    .map_err(errors::humanize_error)
+    .map(|rows| {
+      rows.into_iter().nth(0).map(|row| LobbyFacts {
+        lobby_id: row.lobby_id,
+        name: row.lobby_name,
+        created: row.created_at,
+        closed: row.closed_at,
+        member_id: row.member_id,
+        left: row.member_left_at,
+      })
+    })
+}
+
+async fn game_facts(context: &Context, user_id: &str, game_id: &str) -> Result<Option<GameFacts>> {
+  let mut conn = context.records_connection().await?;
+
+  query_file!("src/policy/data-store/game-facts.sql", game_id, user_id)
+    .fetch_all(&mut conn)
+    .await
+    .map_err(errors::humanize_error)
+    .map(|rows| {
+      rows.into_iter().nth(0).map(|row| GameFacts {
+        game_id: row.game_id,
+        lobby_id: row.lobby_id,
+        member_id: row.member_id,
+      })
+    })
+}
+
+async fn round_facts(
+  context: &Context,
+  user_id: &str,
+  round_id: &str,
+) -> Result<Option<RoundFacts>> {
+  let mut conn = context.records_connection().await?;
+
+  query_file!("src/policy/data-store/round-facts.sql", round_id, user_id)
+    .fetch_all(&mut conn)
+    .await
+    .map_err(errors::humanize_error)
+    .map(|rows| {
+      rows.into_iter().nth(0).map(|row| RoundFacts {
+        round_id: row.round_id,
+        game_id: row.game_id,
+        lobby_id: row.lobby_id,
+        ended: row.ended_at,
+        started: row.started_at,
+        fulfilled: row.fulfilled_at,
+        completed: row.completed_at,
+        member_id: row.member_id,
+        left: row.member_left_at,
+      })
+    })
+}
+
+async fn entry_facts(context: &Context, entry_id: &str) -> Result<Option<EntryFacts>> {
+  let mut conn = context.records_connection().await?;
+
+  query_file!("src/policy/data-store/entry-facts.sql", entry_id)
+    .fetch_all(&mut conn)
+    .await
+    .map_err(errors::humanize_error)
+    .map(|rows| {
+      rows.into_iter().nth(0).map(|row| EntryFacts {
+        round_id: row.round_id,
+        author_id: row.author_id,
+      })
+    })
+}
+
+fn lobby_view(facts: Option<LobbyFacts>) -> Decision<LobbyAccess> {
+  let facts = facts.ok_or(Denial::NotFound)?;
+  let member_id = facts.member_id.ok_or(Denial::NotMember)?;
+
+  Ok(LobbyAccess {
+    lobby_id: facts.lobby_id,
+    name: facts.name,
+    created: facts.created,
+    member_id,
+  })
+}
+
+fn game_start(facts: Option<LobbyFacts>) -> Decision<LobbyAccess> {
+  let (left, closed) = facts
+    .as_ref()
+    .map(|f| (f.left.is_some(), f.closed.is_some()))
+    .unwrap_or_default();
+  let access = lobby_view(facts)?;
+
+  match (left, closed) {
+    (true, _) => Err(Denial::Left),
+    (_, true) => Err(Denial::Closed),
+    _ => Ok(access),
+  }
+}
+
+fn lobby_leave(facts: Option<LobbyFacts>) -> Decision<LobbyAccess> {
+  let left = facts.as_ref().map(|f| f.left.is_some()).unwrap_or_default();
+  let access = lobby_view(facts)?;
+
+  match left {
+    true => Err(Denial::Left),
+    false => Ok(access),
+  }
+}
+
+fn game_view(facts: Option<GameFacts>) -> Decision<GameAccess> {
+  let facts = facts.ok_or(Denial::NotFound)?;
+  let member_id = facts.member_id.ok_or(Denial::NotMember)?;
+
+  Ok(GameAccess {
+    game_id: facts.game_id,
+    lobby_id: facts.lobby_id,
+    member_id,
+  })
+}
+
+fn round_membership(user_id: &str, facts: &RoundFacts) -> Decision<RoundAccess> {
+  let member_id = facts.member_id.clone().ok_or(Denial::NotMember)?;
+
+  Ok(RoundAccess {
+    lobby_id: facts.lobby_id.clone(),
+    game_id: facts.game_id.clone(),
+    round_id: facts.round_id.clone(),
+    member_id,
+    user_id: String::from(user_id),
+  })
+}
+
+// Members that have left, or whose game has ended, may still look at a round but not play in it.
+fn round_play(user_id: &str, facts: &RoundFacts) -> Decision<RoundAccess> {
+  let access = round_membership(user_id, facts)?;
+
+  if facts.left.is_some() {
+    return Err(Denial::Left);
+  }
+
+  if facts.ended.is_some() {
+    return Err(Denial::Closed);
+  }
+
+  Ok(access)
+}
+
+fn round_submission(user_id: &str, facts: Option<RoundFacts>) -> Decision<RoundAccess> {
+  let facts = facts.ok_or(Denial::NotFound)?;
+  let access = round_play(user_id, &facts)?;
+
+  match (facts.started, facts.fulfilled) {
+    (Some(_), None) => Ok(access),
+    _ => Err(Denial::RoundNotActive),
+  }
+}
+
+fn entry_vote(
+  user_id: &str,
+  round: Option<RoundFacts>,
+  entry: Option<EntryFacts>,
+) -> Decision<RoundAccess> {
+  let round = round.ok_or(Denial::NotFound)?;
+  let access = round_play(user_id, &round)?;
+
+  let entry = entry
+    .filter(|e| e.round_id == round.round_id)
+    .ok_or(Denial::NotFound)?;
+
+  if entry.author_id == user_id {
+    return Err(Denial::OwnEntry);
+  }
+
+  match (round.fulfilled, round.completed) {
+    (Some(_), None) => Ok(access),
+    _ => Err(Denial::RoundNotActive),
+  }
+}
+
+// Can the user view the lobby and its members?
+pub async fn view_lobby(
+  context: &Context,
+  user_id: &str,
+  lobby_id: &str,
+) -> Result<Decision<LobbyAccess>> {
+  let facts = lobby_facts(context, user_id, lobby_id).await?;
+  debug!("lobby facts for user '{}' - {:?}", user_id, facts);
+  Ok(lobby_view(facts))
+}
+
+// Can the user start a new game in the lobby?
+pub async fn start_game(
+  context: &Context,
+  user_id: &str,
+  lobby_id: &str,
+) -> Result<Decision<LobbyAccess>> {
+  let facts = lobby_facts(context, user_id, lobby_id).await?;
+  Ok(game_start(facts))
+}
+
+// Can the user leave the lobby?
+pub async fn leave_lobby(
+  context: &Context,
+  user_id: &str,
+  lobby_id: &str,
+) -> Result<Decision<LobbyAccess>> {
+  let facts = lobby_facts(context, user_id, lobby_id).await?;
+  Ok(lobby_leave(facts))
+}
+
+// Can the user view the game, its rounds and its placements?
+pub async fn view_game(
+  context: &Context,
+  user_id: &str,
+  game_id: &str,
+) -> Result<Decision<GameAccess>> {
+  let facts = game_facts(context, user_id, game_id).await?;
+  Ok(game_view(facts))
+}
+
+// Can the user view the round, its entries and its votes?
+pub async fn view_round(
+  context: &Context,
+  user_id: &str,
+  round_id: &str,
+) -> Result<Decision<RoundAccess>> {
+  let facts = round_facts(context, user_id, round_id).await?;
+  Ok(
+    facts
+      .ok_or(Denial::NotFound)
+      .and_then(|f| round_membership(user_id, &f)),
+  )
+}
+
+// Can the user submit an entry to the round?
+pub async fn submit_to_round(
+  context: &Context,
+  user_id: &str,
+  round_id: &str,
+) -> Result<Decision<RoundAccess>> {
+  let facts = round_facts(context, user_id, round_id).await?;
+  Ok(round_submission(user_id, facts))
+}
+
+// Can the user vote for the entry in the round?
+pub async fn vote_on_entry(
+  context: &Context,
+  user_id: &str,
+  round_id: &str,
+  entry_id: &str,
+) -> Result<Decision<RoundAccess>> {
+  let round = round_facts(context, user_id, round_id).await?;
+  let entry = entry_facts(context, entry_id).await?;
+  Ok(entry_vote(user_id, round, entry))
+}
+
+// Ensures the request was made through a login session belonging to a user holding at least the
+// required role, returning the user's id. Api tokens never carry account access and so can never
+// be used for privileged routes.
+pub fn require_role(context: &Context, required: Role) -> Decision<String> {
+  match context.authority() {
+    Authority::User {
+      id, scopes, role, ..
+    } if scopes.allows(Scope::Account) && role.satisfies(required) => Ok(id.clone()),
+    Authority::User { id, role, .. } => {
+      warn!(
+        "user '{}' ({}) denied access requiring '{}'",
+        id,
+        role.as_str(),
+        required.as_str()
+      );
+      Err(Denial::InsufficientRole)
+    }
+    Authority::None => Err(Denial::Unauthenticated),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{
+    entry_vote, game_start, round_submission, Denial, EntryFacts, LobbyFacts, RoundFacts,
+  };
+  use chrono::Utc;
+
+  fn member_round() -> RoundFacts {
+    RoundFacts {
+      round_id: String::from("round"),
+      member_id: Some(String::from("member")),
+      started: Some(Utc::now()),
+      ..RoundFacts::default()
+    }
+  }
+
+  #[test]
+  fn submission_requires_round() {
+    assert_eq!(round_submission("user", None), Err(Denial::NotFound));
+  }
+
+  #[test]
+  fn submission_requires_membership() {
+    let facts = RoundFacts {
+      member_id: None,
+      ..member_round()
+    };
+    assert_eq!(
+      round_submission("user", Some(facts)),
+      Err(Denial::NotMember)
+    );
+  }
+
+  #[test]
+  fn submission_requires_active_round() {
+    assert!(round_submission("user", Some(member_round())).is_ok());
+
+    let fulfilled = RoundFacts {
+      fulfilled: Some(Utc::now()),
+      ..member_round()
+    };
+    assert_eq!(
+      round_submission("user", Some(fulfilled)),
+      Err(Denial::RoundNotActive)
+    );
+
+    let left = RoundFacts {
+      left: Some(Utc::now()),
+      ..member_round()
+    };
+    assert_eq!(round_submission("user", Some(left)), Err(Denial::Left));
+  }
+
+  #[test]
+  fn vote_rules() {
+    let fulfilled = RoundFacts {
+      fulfilled: Some(Utc::now()),
+      ..member_round()
+    };
+    let entry = |author: &str| {
+      Some(EntryFacts {
+        round_id: String::from("round"),
+        author_id: String::from(author),
+      })
+    };
+
+    assert!(entry_vote("user", Some(fulfilled.clone()), entry("other")).is_ok());
+    assert_eq!(
+      entry_vote("user", Some(fulfilled.clone()), entry("user")),
+      Err(Denial::OwnEntry)
+    );
+    assert_eq!(
+      entry_vote("user", Some(member_round()), entry("other")),
+      Err(Denial::RoundNotActive)
+    );
+    assert_eq!(
+      entry_vote("user", Some(fulfilled), None),
+      Err(Denial::NotFound)
+    );
+  }
+
+  #[test]
+  fn start_game_rules() {
+    let member = LobbyFacts {
+      member_id: Some(String::from("member")),
+      ..LobbyFacts::default()
+    };
+    assert!(game_start(Some(member.clone())).is_ok());
+    assert_eq!(game_start(None), Err(Denial::NotFound));
+    assert_eq!(
+      game_start(Some(LobbyFacts {
+        closed: Some(Utc::now()),
+        ..member
+      })),
+      Err(Denial::Closed)
+    );
+  }
+}
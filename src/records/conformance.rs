@@ -0,0 +1,98 @@
+// Behavior expected of every implementation of the user records, run against each implementation.
+// Each implementation is seeded with an admin user holding a read-only api token.
+use async_std::task::block_on;
+use sqlx::query;
+use uuid::Uuid;
+
+use super::{MemoryRecords, RecordStore, UserGrant, UserRecords};
+use crate::api_tokens;
+use crate::authority::{Role, Scope, Scopes};
+use crate::context::test_helpers::{cleanup_user, load_config, make_user};
+
+async fn conforms(records: &dyn UserRecords, user_id: &str, token: &str) {
+  assert_eq!(
+    records.session_user(user_id).await.unwrap(),
+    Some(UserGrant {
+      user_id: String::from(user_id),
+      role: Role::Admin,
+      scopes: Scopes::all(),
+    })
+  );
+
+  let grant = records
+    .api_token_user(&api_tokens::hash(token))
+    .await
+    .unwrap()
+    .unwrap();
+  assert_eq!(grant.user_id, user_id);
+  assert_eq!(grant.role, Role::Admin);
+  assert!(grant.scopes.allows(Scope::Read));
+  assert!(!grant.scopes.allows(Scope::LobbyPlay));
+
+  let unknown = Uuid::new_v4().to_string();
+  assert!(records.session_user(&unknown).await.unwrap().is_none());
+  assert!(records
+    .api_token_user(&api_tokens::hash(&api_tokens::generate()))
+    .await
+    .unwrap()
+    .is_none());
+}
+
+fn read_only() -> Vec<String> {
+  vec![String::from("read")]
+}
+
+#[test]
+fn memory() {
+  block_on(async {
+    let records = MemoryRecords::new();
+    let token = api_tokens::generate();
+    records.add_user("conformance", Role::Admin).await;
+    records
+      .add_api_token(
+        &api_tokens::hash(&token),
+        "conformance",
+        Scopes::from_strings(&read_only()),
+      )
+      .await;
+
+    conforms(&records, "conformance", &token).await;
+  });
+}
+
+#[test]
+fn postgres() {
+  block_on(async {
+    let config = load_config().unwrap();
+    let records = RecordStore::open(&config).await.unwrap();
+    let user_id = make_user("records.conformance").await;
+    let token = api_tokens::generate();
+    let mut conn = records.acquire().await.unwrap();
+
+    query!(
+      "update krumnet.users set role = 'admin' where id = $1",
+      user_id
+    )
+    .execute(&mut conn)
+    .await
+    .unwrap();
+
+    query!(
+      "insert into krumnet.api_tokens (user_id, name, token_hash, scopes) values ($1, 'test', $2, $3)",
+      user_id,
+      api_tokens::hash(&token),
+      &read_only()
+    )
+    .execute(&mut conn)
+    .await
+    .unwrap();
+
+    conforms(&records, &user_id, &token).await;
+
+    query!("delete from krumnet.api_tokens where user_id = $1", user_id)
+      .execute(&mut conn)
+      .await
+      .unwrap();
+    cleanup_user(&user_id).await;
+  });
+}
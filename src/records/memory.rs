@@ -0,0 +1,67 @@
+use async_std::sync::Mutex;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io::Result;
+
+use super::{UserGrant, UserRecords};
+use crate::authority::{Role, Scopes};
+
+#[derive(Default)]
+struct State {
+  users: HashMap<String, Role>,
+  tokens: HashMap<String, (String, Scopes)>,
+}
+
+// An in-memory stand-in for the users and api tokens tables, seeded directly by tests.
+#[derive(Default)]
+pub struct MemoryRecords {
+  _state: Mutex<State>,
+}
+
+impl MemoryRecords {
+  pub fn new() -> Self {
+    MemoryRecords::default()
+  }
+
+  pub async fn add_user(&self, user_id: &str, role: Role) {
+    let mut state = self._state.lock().await;
+    state.users.insert(String::from(user_id), role);
+  }
+
+  pub async fn add_api_token(&self, digest: &str, user_id: &str, scopes: Scopes) {
+    let mut state = self._state.lock().await;
+    let grant = (String::from(user_id), scopes);
+    state.tokens.insert(String::from(digest), grant);
+  }
+
+  pub async fn remove_api_token(&self, digest: &str) {
+    self._state.lock().await.tokens.remove(digest);
+  }
+}
+
+#[async_trait]
+impl UserRecords for MemoryRecords {
+  async fn session_user(&self, user_id: &str) -> Result<Option<UserGrant>> {
+    let state = self._state.lock().await;
+
+    Ok(state.users.get(user_id).map(|role| UserGrant {
+      user_id: String::from(user_id),
+      role: *role,
+      scopes: Scopes::all(),
+    }))
+  }
+
+  async fn api_token_user(&self, digest: &str) -> Result<Option<UserGrant>> {
+    let state = self._state.lock().await;
+
+    let grant = state.tokens.get(digest).and_then(|(user_id, scopes)| {
+      state.users.get(user_id).map(|role| UserGrant {
+        user_id: user_id.clone(),
+        role: *role,
+        scopes: scopes.clone(),
+      })
+    });
+
+    Ok(grant)
+  }
+}
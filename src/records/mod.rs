@@ -0,0 +1,113 @@
+use std::io::{Error, Result};
+
+use async_trait::async_trait;
+use log::{debug, info, warn};
+
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::PgPool;
+use sqlx::{query_file, Postgres};
+
+use crate::authority::{Role, Scopes};
+use crate::{errors, Configuration};
+
+pub mod memory;
+
+#[cfg(test)]
+mod conformance;
+
+pub use memory::MemoryRecords;
+
+fn warn_and_return<E: std::error::Error>(error: E) -> Error {
+  warn!("record store failure - {}", error);
+  errors::humanize_error(error)
+}
+
+// What a credential allows its bearer to do on behalf of a user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserGrant {
+  pub user_id: String,
+  pub role: Role,
+  pub scopes: Scopes,
+}
+
+// The record lookups made while authorizing every request. Users that have been deleted, and api
+// tokens that have been revoked or have expired, are never found.
+#[async_trait]
+pub trait UserRecords: Send + Sync {
+  async fn session_user(&self, user_id: &str) -> Result<Option<UserGrant>>;
+
+  // Finds the user for an api token by the token's digest, marking the token as used.
+  async fn api_token_user(&self, digest: &str) -> Result<Option<UserGrant>>;
+}
+
+pub struct RecordStore {
+  _pg: PgPool,
+}
+
+pub type Connection = PoolConnection<Postgres>;
+
+impl RecordStore {
+  pub async fn open(configuration: &Configuration) -> Result<Self> {
+    let uri = &configuration.record_store.postgres_uri;
+
+    let pg = PgPool::connect(uri).await.map_err(errors::humanize_error)?;
+
+    info!("successfully connected to '{}'", uri);
+
+    Ok(RecordStore { _pg: pg })
+  }
+
+  // The pool behind the store, for sharing with the postgres job store.
+  pub fn pool(&self) -> PgPool {
+    self._pg.clone()
+  }
+
+  pub async fn acquire(&self) -> Result<Connection> {
+    self._pg.acquire().await.map_err(warn_and_return)
+  }
+}
+
+#[async_trait]
+impl UserRecords for RecordStore {
+  async fn session_user(&self, user_id: &str) -> Result<Option<UserGrant>> {
+    let mut conn = self.acquire().await?;
+    let grant = query_file!("src/data-store/user-for-session.sql", user_id)
+      .fetch_all(&mut conn)
+      .await
+      .map_err(errors::humanize_error)?
+      .into_iter()
+      .nth(0)
+      .map(|row| {
+        debug!("found user '{:?}'", row.user_id);
+
+        UserGrant {
+          user_id: row.user_id,
+          role: Role::from_stored(&row.user_role),
+          scopes: Scopes::all(),
+        }
+      });
+
+    Ok(grant)
+  }
+
+  async fn api_token_user(&self, digest: &str) -> Result<Option<UserGrant>> {
+    let mut conn = self.acquire().await?;
+    let grant = query_file!("src/data-store/use-api-token.sql", digest)
+      .fetch_all(&mut conn)
+      .await
+      .map_err(errors::humanize_error)?
+      .into_iter()
+      .nth(0)
+      .map(|row| {
+        debug!("found api token for user '{:?}'", row.user_id);
+
+        UserGrant {
+          user_id: row.user_id,
+          role: Role::from_stored(&row.user_role),
+          scopes: Scopes::from_strings(&row.scopes),
+        }
+      });
+
+    Ok(grant)
+  }
+}
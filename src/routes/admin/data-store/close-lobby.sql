@@ -0,0 +1,22 @@
+with games as (
+  update
+    krumnet.games as games
+  set
+    ended_at = now()
+  where
+    games.lobby_id = $1
+  and
+    games.ended_at is null
+  returning
+    games.id
+) update
+  krumnet.lobbies as lobbies
+set
+  closed_at = now()
+where
+  lobbies.id = $1
+and
+  lobbies.closed_at is null
+returning
+  lobbies.id as id,
+  (select count(*) from games) as "games!";
@@ -0,0 +1,10 @@
+update
+  krumnet.games as games
+set
+  ended_at = now()
+where
+  games.id = $1
+and
+  games.ended_at is null
+returning
+  games.id as id;
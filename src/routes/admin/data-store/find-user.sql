@@ -0,0 +1,21 @@
+select
+  users.id            as id,
+  users.name          as name,
+  users.default_email as email,
+  users.role          as role,
+  users.created_at    as created_at,
+  users.deleted_at    as deleted_at,
+  array_remove(array_agg(identities.provider), null) as "providers!"
+from
+  krumnet.users as users
+left join
+  krumnet.identities as identities
+on
+  identities.user_id = users.id
+where
+  users.id = $1
+or
+  lower(users.default_email) = lower($1)
+group by
+  users.id
+limit 1;
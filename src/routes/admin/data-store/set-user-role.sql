@@ -0,0 +1,8 @@
+update
+  krumnet.users as users
+set
+  role = $2
+where
+  users.id = $1
+returning
+  users.id as id;
@@ -0,0 +1,334 @@
+use async_std::io::Read as AsyncRead;
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::from_slice as deserialize;
+use sqlx::query_file;
+use std::io::Result;
+use std::marker::Unpin;
+
+use crate::authority::Role;
+use crate::http::{query as qs, Uri};
+use crate::{errors, interchange, policy, read_size_async, Context, Response};
+
+const INVALID_MERGE: &'static str = "errors.admin.invalid_merge";
+
+#[derive(Debug, Deserialize)]
+struct LobbyPayload {
+  lobby_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GamePayload {
+  game_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JobPayload {
+  id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RolePayload {
+  user_id: String,
+  role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+struct MergePayload {
+  source: String,
+  target: String,
+}
+
+// Route
+// GET /admin/users?q=<id or email>
+pub async fn find_user(context: &Context, uri: &Uri) -> Result<Response> {
+  let uid = match policy::require_role(context, Role::Moderator) {
+    Ok(id) => id,
+    Err(denial) => return Ok(denial.response(context)),
+  };
+
+  let search = uri
+    .query()
+    .and_then(|q| qs::parse(q.as_bytes()).find(|(k, _v)| k == "q"))
+    .map(|(_k, v)| String::from(v.as_ref()))
+    .unwrap_or_default();
+
+  info!("user '{}' looking up user '{}'", uid, search);
+  let mut conn = context.records_connection().await?;
+
+  let user = query_file!("src/routes/admin/data-store/find-user.sql", search)
+    .fetch_all(&mut conn)
+    .await
+    .map_err(errors::humanize_error)?
+    .into_iter()
+    .nth(0)
+    .map(|row| interchange::http::AdminUser {
+      id: row.id,
+      name: row.name,
+      email: row.email,
+      role: row.role,
+      providers: row.providers,
+      created: row.created_at,
+      deleted: row.deleted_at,
+    });
+
+  match user {
+    Some(user) => Response::ok_json(user).map(|r| r.cors(context.cors())),
+    None => Ok(Response::not_found().cors(context.cors())),
+  }
+}
+
+// Route
+// POST /admin/users/role
+pub async fn set_role<R>(context: &Context, reader: &mut R) -> Result<Response>
+where
+  R: AsyncRead + Unpin,
+{
+  let uid = match policy::require_role(context, Role::Admin) {
+    Ok(id) => id,
+    Err(denial) => return Ok(denial.response(context)),
+  };
+
+  let contents = read_size_async(reader, context.pending()).await?;
+  let payload = deserialize::<RolePayload>(&contents)?;
+  let mut conn = context.records_connection().await?;
+
+  let updated = query_file!(
+    "src/routes/admin/data-store/set-user-role.sql",
+    payload.user_id,
+    payload.role.as_str()
+  )
+  .fetch_all(&mut conn)
+  .await
+  .map_err(errors::humanize_error)?;
+
+  if updated.is_empty() {
+    return Ok(Response::not_found().cors(context.cors()));
+  }
+
+  info!(
+    "user '{}' set role of '{}' to '{}'",
+    uid,
+    payload.user_id,
+    payload.role.as_str()
+  );
+  Ok(Response::default().cors(context.cors()))
+}
+
+// Route
+// POST /admin/users/merge
+pub async fn merge_users<R>(context: &Context, reader: &mut R) -> Result<Response>
+where
+  R: AsyncRead + Unpin,
+{
+  let uid = match policy::require_role(context, Role::Admin) {
+    Ok(id) => id,
+    Err(denial) => return Ok(denial.response(context)),
+  };
+
+  let contents = read_size_async(reader, context.pending()).await?;
+  let payload = deserialize::<MergePayload>(&contents)?;
+
+  if payload.source == payload.target {
+    return Ok(Response::bad_request(INVALID_MERGE).cors(context.cors()));
+  }
+
+  let job = interchange::jobs::Job::MergeUsers(interchange::jobs::MergeUsers {
+    source: payload.source.clone(),
+    target: payload.target.clone(),
+    result: None,
+  });
+  let id = context.jobs().queue(&job).await?;
+
+  info!(
+    "user '{}' queued merge of '{}' into '{}' (job '{}')",
+    uid, payload.source, payload.target, id
+  );
+  Response::ok_json(interchange::http::JobHandle { id, result: None })
+    .map(|r| r.cors(context.cors()))
+}
+
+// Route
+// POST /admin/lobbies/close
+pub async fn close_lobby<R>(context: &Context, reader: &mut R) -> Result<Response>
+where
+  R: AsyncRead + Unpin,
+{
+  let uid = match policy::require_role(context, Role::Moderator) {
+    Ok(id) => id,
+    Err(denial) => return Ok(denial.response(context)),
+  };
+
+  let contents = read_size_async(reader, context.pending()).await?;
+  let payload = deserialize::<LobbyPayload>(&contents)?;
+  let mut conn = context.records_connection().await?;
+
+  let closed = query_file!(
+    "src/routes/admin/data-store/close-lobby.sql",
+    payload.lobby_id
+  )
+  .fetch_all(&mut conn)
+  .await
+  .map_err(errors::humanize_error)?
+  .into_iter()
+  .nth(0);
+
+  match closed {
+    Some(row) => {
+      info!(
+        "user '{}' closed lobby '{}' ({} games ended)",
+        uid, row.id, row.games
+      );
+      Ok(Response::default().cors(context.cors()))
+    }
+    None => {
+      warn!("no open lobby '{}' to close", payload.lobby_id);
+      Ok(Response::not_found().cors(context.cors()))
+    }
+  }
+}
+
+// Route
+// POST /admin/games/end
+pub async fn end_game<R>(context: &Context, reader: &mut R) -> Result<Response>
+where
+  R: AsyncRead + Unpin,
+{
+  let uid = match policy::require_role(context, Role::Moderator) {
+    Ok(id) => id,
+    Err(denial) => return Ok(denial.response(context)),
+  };
+
+  let contents = read_size_async(reader, context.pending()).await?;
+  let payload = deserialize::<GamePayload>(&contents)?;
+  let mut conn = context.records_connection().await?;
+
+  let ended = query_file!("src/routes/admin/data-store/end-game.sql", payload.game_id)
+    .fetch_all(&mut conn)
+    .await
+    .map_err(errors::humanize_error)?;
+
+  if ended.is_empty() {
+    warn!("no active game '{}' to end", payload.game_id);
+    return Ok(Response::not_found().cors(context.cors()));
+  }
+
+  info!("user '{}' ended game '{}'", uid, payload.game_id);
+  Ok(Response::default().cors(context.cors()))
+}
+
+// Route
+// POST /admin/jobs/requeue
+pub async fn requeue_job<R>(context: &Context, reader: &mut R) -> Result<Response>
+where
+  R: AsyncRead + Unpin,
+{
+  let uid = match policy::require_role(context, Role::Admin) {
+    Ok(id) => id,
+    Err(denial) => return Ok(denial.response(context)),
+  };
+
+  let contents = read_size_async(reader, context.pending()).await?;
+  let payload = deserialize::<JobPayload>(&contents)?;
+
+  match context.jobs().requeue(&payload.id).await? {
+    Some(id) => {
+      info!("user '{}' requeued job '{}'", uid, id);
+      Response::ok_json(interchange::http::JobHandle { id, result: None })
+        .map(|r| r.cors(context.cors()))
+    }
+    None => Ok(Response::not_found().cors(context.cors())),
+  }
+}
+
+// Route
+// GET /admin/jobs/dead
+pub async fn dead_letters(context: &Context) -> Result<Response> {
+  let uid = match policy::require_role(context, Role::Admin) {
+    Ok(id) => id,
+    Err(denial) => return Ok(denial.response(context)),
+  };
+
+  let dead = context
+    .jobs()
+    .dead_letters()
+    .await?
+    .into_iter()
+    .map(interchange::http::AdminDeadLetter::from)
+    .collect::<Vec<interchange::http::AdminDeadLetter>>();
+
+  info!("user '{}' listed {} dead letter jobs", uid, dead.len());
+  Response::ok_json(dead).map(|r| r.cors(context.cors()))
+}
+
+// Route
+// POST /admin/jobs/dead/replay
+pub async fn replay_job<R>(context: &Context, reader: &mut R) -> Result<Response>
+where
+  R: AsyncRead + Unpin,
+{
+  let uid = match policy::require_role(context, Role::Admin) {
+    Ok(id) => id,
+    Err(denial) => return Ok(denial.response(context)),
+  };
+
+  let contents = read_size_async(reader, context.pending()).await?;
+  let payload = deserialize::<JobPayload>(&contents)?;
+
+  match context.jobs().replay(&payload.id).await? {
+    Some(id) => {
+      info!("user '{}' replayed dead letter job '{}'", uid, id);
+      Response::ok_json(interchange::http::JobHandle { id, result: None })
+        .map(|r| r.cors(context.cors()))
+    }
+    None => Ok(Response::not_found().cors(context.cors())),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{dead_letters, find_user};
+  use crate::authority::{Role, Scopes};
+  use crate::context::test_helpers as context_helpers;
+  use crate::http::{StatusCode, Uri};
+  use crate::Authority;
+  use async_std::task::block_on;
+
+  fn with_role(role: Role) -> crate::Context {
+    context_helpers::with_auth(Authority::User {
+      id: String::from("admin-test"),
+      token: String::from(""),
+      scopes: Scopes::all(),
+      role,
+    })
+  }
+
+  #[test]
+  fn players_forbidden() {
+    let ctx = with_role(Role::Player);
+    let uri = "/admin/users?q=nobody".parse::<Uri>().unwrap();
+    let response = block_on(find_user(&ctx, &uri)).unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+  }
+
+  #[test]
+  fn moderators_lookup_users() {
+    let ctx = with_role(Role::Moderator);
+    let uri = "/admin/users?q=nobody@krumnet.local"
+      .parse::<Uri>()
+      .unwrap();
+    let response = block_on(find_user(&ctx, &uri)).unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  }
+
+  #[test]
+  fn dead_letters_require_admin() {
+    let ctx = with_role(Role::Moderator);
+    let response = block_on(dead_letters(&ctx)).unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let ctx = with_role(Role::Admin);
+    let response = block_on(dead_letters(&ctx)).unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+  }
+}
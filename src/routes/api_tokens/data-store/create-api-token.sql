@@ -0,0 +1,12 @@
+insert into krumnet.api_tokens
+  (user_id, name, token_hash, scopes, expires_at)
+values
+  ($1, $2, $3, $4, $5)
+on conflict do nothing
+returning
+  id,
+  name,
+  scopes,
+  created_at,
+  expires_at,
+  last_used_at;
@@ -0,0 +1,15 @@
+select
+  tokens.id           as id,
+  tokens.name         as name,
+  tokens.scopes       as scopes,
+  tokens.created_at   as created_at,
+  tokens.expires_at   as expires_at,
+  tokens.last_used_at as last_used_at
+from
+  krumnet.api_tokens as tokens
+where
+  tokens.user_id = $1
+and
+  tokens.revoked_at is null
+order by
+  tokens.created_at desc;
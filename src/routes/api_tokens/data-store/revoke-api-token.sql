@@ -0,0 +1,12 @@
+update
+  krumnet.api_tokens as tokens
+set
+  revoked_at = now()
+where
+  tokens.id = $1
+and
+  tokens.user_id = $2
+and
+  tokens.revoked_at is null
+returning
+  tokens.id as id;
@@ -0,0 +1,232 @@
+use async_std::io::Read as AsyncRead;
+use chrono::{Duration, Utc};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::from_slice as deserialize;
+use sqlx::query_file;
+use std::io::Result;
+use std::marker::Unpin;
+
+use crate::authority::Scope;
+use crate::constants::API_TOKEN_MAX_NAME_LENGTH;
+use crate::{api_tokens, errors, interchange, read_size_async, Authority, Context, Response};
+
+const INVALID_NAME: &'static str = "errors.api_tokens.invalid_name";
+const INVALID_SCOPES: &'static str = "errors.api_tokens.invalid_scopes";
+const INVALID_EXPIRATION: &'static str = "errors.api_tokens.invalid_expiration";
+const DUPLICATE_NAME: &'static str = "errors.api_tokens.duplicate_name";
+
+#[derive(Debug, Deserialize)]
+struct CreatePayload {
+  name: String,
+  scopes: Vec<Scope>,
+  expires_in: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DestroyPayload {
+  id: String,
+}
+
+// Validates the requested token details, returning the reason the request should be rejected if
+// any. Account access can never be delegated to a token; it remains exclusive to login sessions.
+fn validate(payload: &CreatePayload) -> Option<&'static str> {
+  let name = payload.name.trim();
+
+  if name.is_empty() || name.len() > API_TOKEN_MAX_NAME_LENGTH {
+    return Some(INVALID_NAME);
+  }
+
+  if payload.scopes.is_empty() || payload.scopes.contains(&Scope::Account) {
+    return Some(INVALID_SCOPES);
+  }
+
+  match payload.expires_in {
+    Some(seconds) if seconds <= 0 => Some(INVALID_EXPIRATION),
+    _ => None,
+  }
+}
+
+// Route
+// GET /api-tokens
+pub async fn find(context: &Context) -> Result<Response> {
+  let uid = match context.authority() {
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::Account) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
+    Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
+  };
+
+  debug!("loading api tokens for user '{}'", uid);
+  let mut conn = context.records_connection().await?;
+
+  let tokens = query_file!("src/routes/api_tokens/data-store/load-api-tokens.sql", uid)
+    .fetch_all(&mut conn)
+    .await
+    .map_err(errors::humanize_error)?
+    .into_iter()
+    .map(|row| interchange::http::ApiToken {
+      id: row.id,
+      name: row.name,
+      scopes: row.scopes,
+      created: row.created_at,
+      expires: row.expires_at,
+      last_used: row.last_used_at,
+    })
+    .collect();
+
+  Response::ok_json(interchange::http::ApiTokenList { tokens }).map(|r| r.cors(context.cors()))
+}
+
+// Route
+// POST /api-tokens
+pub async fn create<R>(context: &Context, reader: &mut R) -> Result<Response>
+where
+  R: AsyncRead + Unpin,
+{
+  let uid = match context.authority() {
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::Account) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
+    Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
+  };
+
+  let contents = read_size_async(reader, context.pending()).await?;
+  let payload = deserialize::<CreatePayload>(&contents)?;
+
+  if let Some(reason) = validate(&payload) {
+    warn!("rejecting api token for user '{}' - {}", uid, reason);
+    return Ok(Response::bad_request(reason).cors(context.cors()));
+  }
+
+  let token = api_tokens::generate();
+  let scopes = payload
+    .scopes
+    .iter()
+    .map(|scope| String::from(scope.as_str()))
+    .collect::<Vec<String>>();
+  let expires = payload
+    .expires_in
+    .map(|seconds| Utc::now() + Duration::seconds(seconds));
+
+  let mut conn = context.records_connection().await?;
+  let created = query_file!(
+    "src/routes/api_tokens/data-store/create-api-token.sql",
+    uid,
+    payload.name.trim(),
+    api_tokens::hash(&token),
+    &scopes,
+    expires
+  )
+  .fetch_all(&mut conn)
+  .await
+  .map_err(errors::humanize_error)?
+  .into_iter()
+  .nth(0)
+  .map(|row| interchange::http::ApiToken {
+    id: row.id,
+    name: row.name,
+    scopes: row.scopes,
+    created: row.created_at,
+    expires: row.expires_at,
+    last_used: row.last_used_at,
+  });
+
+  match created {
+    Some(details) => {
+      info!("user '{}' minted api token '{}'", uid, details.id);
+      Response::ok_json(interchange::http::NewApiToken { token, details })
+        .map(|r| r.cors(context.cors()))
+    }
+    None => {
+      warn!(
+        "user '{}' already has a token named '{}'",
+        uid, payload.name
+      );
+      Ok(Response::bad_request(DUPLICATE_NAME).cors(context.cors()))
+    }
+  }
+}
+
+// Route
+// DELETE /api-tokens
+pub async fn destroy<R>(context: &Context, reader: &mut R) -> Result<Response>
+where
+  R: AsyncRead + Unpin,
+{
+  let uid = match context.authority() {
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::Account) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
+    Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
+  };
+
+  let contents = read_size_async(reader, context.pending()).await?;
+  let payload = deserialize::<DestroyPayload>(&contents)?;
+  let mut conn = context.records_connection().await?;
+
+  let revoked = query_file!(
+    "src/routes/api_tokens/data-store/revoke-api-token.sql",
+    payload.id,
+    uid
+  )
+  .fetch_all(&mut conn)
+  .await
+  .map_err(errors::humanize_error)?
+  .into_iter()
+  .nth(0)
+  .map(|row| row.id);
+
+  match revoked {
+    Some(id) => {
+      info!("user '{}' revoked api token '{}'", uid, id);
+      Ok(Response::default().cors(context.cors()))
+    }
+    None => Ok(Response::not_found().cors(context.cors())),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{validate, CreatePayload, INVALID_EXPIRATION, INVALID_NAME, INVALID_SCOPES};
+  use crate::authority::Scope;
+
+  fn payload(name: &str, scopes: Vec<Scope>, expires_in: Option<i64>) -> CreatePayload {
+    CreatePayload {
+      name: String::from(name),
+      scopes,
+      expires_in,
+    }
+  }
+
+  #[test]
+  fn valid_payload() {
+    assert_eq!(validate(&payload("bot", vec![Scope::Read], Some(60))), None);
+  }
+
+  #[test]
+  fn invalid_name() {
+    assert_eq!(
+      validate(&payload(" ", vec![Scope::Read], None)),
+      Some(INVALID_NAME)
+    );
+  }
+
+  #[test]
+  fn account_scope_not_delegated() {
+    let scopes = vec![Scope::Read, Scope::Account];
+    assert_eq!(
+      validate(&payload("bot", scopes, None)),
+      Some(INVALID_SCOPES)
+    );
+    assert_eq!(
+      validate(&payload("bot", vec![], None)),
+      Some(INVALID_SCOPES)
+    );
+  }
+
+  #[test]
+  fn invalid_expiration() {
+    assert_eq!(
+      validate(&payload("bot", vec![Scope::LobbyPlay], Some(0))),
+      Some(INVALID_EXPIRATION)
+    );
+  }
+}
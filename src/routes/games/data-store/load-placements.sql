@@ -3,7 +3,8 @@ select
   placements.place      as placement,
   placements.vote_count as vote_count,
   users.name            as user_name,
-  users.id              as user_id
+  users.id              as user_id,
+  users.avatar_url      as user_avatar
 from
   krumnet.game_member_placement_results as placements
 left join
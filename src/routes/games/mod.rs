@@ -3,18 +3,19 @@ use chrono::{DateTime, Utc};
 use log::{debug, info, warn};
 use serde::Deserialize;
 use serde_json::from_slice as deserialize;
-use sqlx::query_file;
+use sqlx::postgres::PgConnection;
+use sqlx::{query_file, Connection};
 use std::io::Result;
 use std::marker::Unpin;
 
 use crate::{
+  authority::Scope,
   errors,
   http::{query_values, Uri},
-  interchange, read_size_async, Authority, Context, Response,
+  interchange, jobs, policy, read_size_async, Authority, Context, Response,
 };
 
 const NOT_ENOUGH_MEMBERS: &'static str = "errors.games.not_enough_members";
-const INVALID_LOBBY: &'static str = "errors.games.invalid_lobby";
 
 #[derive(Debug, Deserialize)]
 struct EntryVotePayload {
@@ -22,48 +23,18 @@ struct EntryVotePayload {
   pub entry_id: String,
 }
 
-#[derive(Debug)]
-struct RoundAuthority {
-  lobby_id: String,
-  game_id: String,
-  member_id: String,
-  user_id: String,
-  round_id: String,
-}
-
-async fn available_entry_for_vote(
-  context: &Context,
-  authority: &RoundAuthority,
-  entry_id: &String,
-) -> Result<Option<String>> {
-  let mut conn = context.records_connection().await?;
-
-  let query_result = query_file!(
-    "src/routes/games/data-store/available-entry-for-vote.sql",
-    entry_id,
-    &authority.user_id
-  )
-  .fetch_all(&mut conn)
-  .await
-  .map_err(errors::humanize_error)?;
-
-  Ok(query_result.into_iter().nth(0).map(|row| row.id))
-}
-
 async fn create_vote_for_entry(
-  context: &Context,
-  authority: &RoundAuthority,
+  conn: &mut PgConnection,
+  access: &policy::RoundAccess,
   entry_id: &String,
 ) -> Result<Option<String>> {
-  let mut conn = context.records_connection().await?;
-
   let query_result = query_file!(
     "src/routes/games/data-store/create-round-entry-vote.sql",
     entry_id,
-    authority.member_id,
-    authority.user_id,
+    access.member_id,
+    access.user_id,
   )
-  .fetch_all(&mut conn)
+  .fetch_all(conn)
   .await
   .map_err(errors::humanize_error)?;
 
@@ -78,30 +49,32 @@ pub async fn create_entry_vote<R: AsyncRead + Unpin>(
 ) -> Result<Response> {
   let uid = match context.authority() {
     Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
-    Authority::User { id, .. } => id,
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::LobbyPlay) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
   };
   let contents = read_size_async(reader, context.pending()).await?;
   let payload = deserialize::<EntryVotePayload>(&contents)?;
 
-  let authority = match authority_for_round(context, &payload.round_id, &uid).await? {
-    Some(auth) => auth,
-    None => {
-      warn!("unauthorized vote by user '{}'", uid);
-      return Ok(Response::unauthorized().cors(context.cors()));
-    }
-  };
+  let access =
+    match policy::vote_on_entry(context, uid, &payload.round_id, &payload.entry_id).await? {
+      Ok(access) => access,
+      Err(denial) => {
+        warn!(
+          "user '{}' cant vote for '{}' - {}",
+          uid,
+          payload.entry_id,
+          denial.reason()
+        );
+        return Ok(denial.response(context));
+      }
+    };
+
+  info!("user {:?} voting for '{}'", access, payload.entry_id);
 
-  let entry_id = match available_entry_for_vote(context, &authority, &payload.entry_id).await? {
-    Some(id) => id,
-    None => {
-      warn!("user '{}' cant vote for '{}'", uid, payload.entry_id);
-      return Ok(Response::bad_request("errors.vote_for_self").cors(context.cors()));
-    }
-  };
-
-  info!("user {:?} voting for '{}'", authority, payload.entry_id);
+  let mut conn = context.records_connection().await?;
+  let mut tx = conn.begin().await.map_err(errors::humanize_error)?;
 
-  let vote_id = match create_vote_for_entry(context, &authority, &entry_id).await? {
+  let vote_id = match create_vote_for_entry(&mut tx, &access, &payload.entry_id).await? {
     Some(e) => e,
     None => {
       warn!("user '{}' unable to vote for '{}'", uid, payload.entry_id);
@@ -112,45 +85,17 @@ pub async fn create_entry_vote<R: AsyncRead + Unpin>(
   info!("vote creation attempt: {:?}, queing job", vote_id);
 
   let job_context = interchange::jobs::CheckRoundCompletion {
-    round_id: authority.round_id.clone(),
-    game_id: authority.game_id.clone(),
+    round_id: access.round_id.clone(),
+    game_id: access.game_id.clone(),
     result: None,
   };
 
-  context
-    .jobs()
-    .queue(&interchange::jobs::Job::CheckRoundCompletion(job_context))
-    .await?;
+  let job = interchange::jobs::Job::CheckRoundCompletion(job_context);
+  jobs::commit_queued(context.jobs(), tx, &job).await?;
 
   return Ok(Response::default().cors(context.cors()));
 }
 
-async fn authority_for_round(
-  context: &Context,
-  round_id: &String,
-  user_id: &String,
-) -> Result<Option<RoundAuthority>> {
-  let mut conn = context.records_connection().await?;
-  let possible = query_file!(
-    "src/routes/games/data-store/game-for-entry-creation.sql",
-    round_id,
-    user_id
-  )
-  .fetch_all(&mut conn)
-  .await
-  .map_err(errors::humanize_error)?
-  .into_iter()
-  .nth(0)
-  .map(|row| RoundAuthority {
-    lobby_id: row.lobby_id,
-    game_id: row.game_id,
-    member_id: row.member_id,
-    user_id: row.user_id,
-    round_id: row.round_id,
-  });
-  Ok(possible)
-}
-
 #[derive(Debug, Deserialize)]
 struct EntryPayload {
   pub round_id: String,
@@ -165,31 +110,38 @@ pub async fn create_entry<R: AsyncRead + Unpin>(
 ) -> Result<Response> {
   let uid = match context.authority() {
     Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
-    Authority::User { id, .. } => id,
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::LobbyPlay) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
   };
 
   let contents = read_size_async(reader, context.pending()).await?;
   let payload = deserialize::<EntryPayload>(&contents)?;
 
-  let authority = match authority_for_round(context, &payload.round_id, &uid).await? {
-    Some(auth) => auth,
-    None => {
-      warn!("unauthorized attempt to create entry by user '{}'", uid);
-      return Ok(Response::unauthorized().cors(context.cors()));
+  let access = match policy::submit_to_round(context, uid, &payload.round_id).await? {
+    Ok(access) => access,
+    Err(denial) => {
+      warn!(
+        "user '{}' cant submit to round '{}' - {}",
+        uid,
+        payload.round_id,
+        denial.reason()
+      );
+      return Ok(denial.response(context));
     }
   };
 
   let mut conn = context.records_connection().await?;
+  let mut tx = conn.begin().await.map_err(errors::humanize_error)?;
   let created = query_file!(
     "src/routes/games/data-store/create-round-entry.sql",
-    authority.round_id,
-    authority.member_id,
+    access.round_id,
+    access.member_id,
     payload.entry,
-    authority.game_id,
-    authority.lobby_id,
-    authority.user_id
+    access.game_id,
+    access.lobby_id,
+    access.user_id
   )
-  .fetch_all(&mut conn)
+  .fetch_all(&mut tx)
   .await
   .map_err(errors::humanize_error)?
   .into_iter()
@@ -202,20 +154,13 @@ pub async fn create_entry<R: AsyncRead + Unpin>(
     Some((_entry_id, entry, round_id)) => {
       debug!("successfully created entry - {:?}", entry);
 
-      context
-        .jobs()
-        .queue(&interchange::jobs::Job::CheckRoundFulfillment(
-          interchange::jobs::CheckRoundFulfillment {
-            round_id,
-            result: None,
-          },
-        ))
-        .await
-        .map(|_id| Response::default().cors(context.cors()))
-        .or_else(|e| {
-          log_err(e);
-          Ok(Response::default().cors(context.cors()))
-        })
+      let job =
+        interchange::jobs::Job::CheckRoundFulfillment(interchange::jobs::CheckRoundFulfillment {
+          round_id,
+          result: None,
+        });
+      jobs::commit_queued(context.jobs(), tx, &job).await?;
+      Ok(Response::default().cors(context.cors()))
     }
     None => {
       warn!("round entry creation did not return information from inserted entry");
@@ -306,6 +251,7 @@ async fn placements_for_game(
         id: row.id,
         user_name: row.user_name,
         user_id: row.user_id,
+        user_avatar: row.user_avatar,
         place: row.placement,
         vote_count: row.vote_count,
       })
@@ -314,31 +260,37 @@ async fn placements_for_game(
 }
 
 async fn find_game(context: &Context, uid: &String, gid: &String) -> Result<Response> {
+  if let Err(denial) = policy::view_game(context, uid, gid).await? {
+    debug!(
+      "user '{}' cant view game '{}' - {}",
+      uid,
+      gid,
+      denial.reason()
+    );
+    return Ok(denial.response(context));
+  }
+
   let mut conn = context.records_connection().await?;
-  let details = query_file!(
-    "src/routes/games/data-store/load-game-details.sql",
-    gid,
-    uid
-  )
-  .fetch_all(&mut conn)
-  .await
-  .map_err(errors::humanize_error)?
-  .into_iter()
-  .nth(0)
-  .map(|row| {
-    Ok(GameDetails {
-      game_id: row.game_id,
-      created_at: row.created_at.ok_or_else(|| {
-        errors::e(format!(
-          "Unable to parse created timestamp for game '{}'",
-          gid
-        ))
-      })?,
-      name: row.game_name,
-      ended_at: row.ended_at,
+  let details = query_file!("src/routes/games/data-store/load-game-details.sql", gid)
+    .fetch_all(&mut conn)
+    .await
+    .map_err(errors::humanize_error)?
+    .into_iter()
+    .nth(0)
+    .map(|row| {
+      Ok(GameDetails {
+        game_id: row.game_id,
+        created_at: row.created_at.ok_or_else(|| {
+          errors::e(format!(
+            "Unable to parse created timestamp for game '{}'",
+            gid
+          ))
+        })?,
+        name: row.game_name,
+        ended_at: row.ended_at,
+      })
     })
-  })
-  .unwrap_or_else(|| Err(errors::e(format!("Unable to find game '{}'", gid))))?;
+    .unwrap_or_else(|| Err(errors::e(format!("Unable to find game '{}'", gid))))?;
 
   debug!(
     "found game '{}', created '{:?}'",
@@ -372,7 +324,8 @@ async fn find_game(context: &Context, uid: &String, gid: &String) -> Result<Resp
 // GET /games
 pub async fn find(context: &Context, uri: &Uri) -> Result<Response> {
   let uid = match context.authority() {
-    Authority::User { id, .. } => id,
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::Read) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
     Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
   };
 
@@ -396,7 +349,8 @@ where
 {
   let uid = match context.authority() {
     Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
-    Authority::User { id, .. } => id,
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::LobbyPlay) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
   };
 
   debug!("creating new game for user - {}", uid);
@@ -404,26 +358,23 @@ where
   let contents = read_size_async(reader, context.pending()).await?;
   let CreatePayload { lobby_id } = deserialize::<CreatePayload>(&contents)?;
 
-  let mut conn = context.records_connection().await?;
-  let maybe_lobby = query_file!(
-    "src/routes/lobbies/data-store/load-lobby-detail.sql",
-    lobby_id,
-    uid
-  )
-  .fetch_all(&mut conn)
-  .await
-  .map_err(errors::humanize_error)?
-  .into_iter()
-  .nth(0);
-
-  if let None = maybe_lobby {
-    warn!("no lobby '{}' for user '{}'", lobby_id, uid);
-    return Ok(Response::bad_request(INVALID_LOBBY).cors(context.cors()));
-  }
+  let access = match policy::start_game(context, uid, &lobby_id).await? {
+    Ok(access) => access,
+    Err(denial) => {
+      warn!(
+        "user '{}' cant start game in '{}' - {}",
+        uid,
+        lobby_id,
+        denial.reason()
+      );
+      return Ok(denial.response(context));
+    }
+  };
 
+  let mut conn = context.records_connection().await?;
   let member_count = query_file!(
     "src/routes/games/data-store/count-lobby-members.sql",
-    lobby_id
+    access.lobby_id
   )
   .fetch_all(&mut conn)
   .await
@@ -460,10 +411,10 @@ where
 
 #[cfg(test)]
 mod test {
-  use super::authority_for_round;
   use crate::{
     bg,
     context::{test_helpers as context_helpers, Context},
+    policy::{view_round, Denial},
     test_helpers::cleanup_lobby,
   };
   use async_std::task::block_on;
@@ -513,9 +464,9 @@ mod test {
     block_on(async {
       let (ctx, user_id) =
         context_helpers::with_user_by_name("routes.games.no_entry_for_same_user").await;
-      let authority = authority_for_round(&ctx, &String::from("bogus"), &user_id).await;
+      let authority = view_round(&ctx, &user_id, "bogus").await;
       assert_eq!(authority.is_ok(), true);
-      assert_eq!(authority.unwrap().is_none(), true);
+      assert_eq!(authority.unwrap(), Err(Denial::NotFound));
       context_helpers::cleanup(&ctx).await;
     });
   }
@@ -532,10 +483,10 @@ mod test {
       let game_context = game_for_user(&ctx, &other).await;
       let round_id = get_round_id(&ctx, &game_context.game_id, 0).await;
 
-      let authority = authority_for_round(&ctx, &round_id, &user_id).await;
+      let authority = view_round(&ctx, &user_id, &round_id).await;
 
       assert_eq!(authority.is_ok(), true);
-      assert_eq!(authority.unwrap().is_none(), true);
+      assert_eq!(authority.unwrap(), Err(Denial::NotMember));
 
       cleanup_lobby(&ctx, &game_context.lobby_id).await;
       context_helpers::cleanup_user(&other).await;
@@ -550,9 +501,9 @@ mod test {
         context_helpers::with_user_by_name("routes.games.authority_for_member").await;
       let game_context = game_for_user(&ctx, &user_id).await;
       let round_id = get_round_id(&ctx, &game_context.game_id, 0).await;
-      let authority = authority_for_round(&ctx, &round_id, &user_id).await;
+      let authority = view_round(&ctx, &user_id, &round_id).await;
       assert_eq!(authority.is_ok(), true);
-      assert_eq!(authority.unwrap().is_some(), true);
+      assert_eq!(authority.unwrap().is_ok(), true);
       cleanup_lobby(&ctx, &game_context.lobby_id).await;
       context_helpers::cleanup(&ctx).await;
     });
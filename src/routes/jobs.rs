@@ -1,15 +1,19 @@
 use crate::{
-  http::{query as qs, Uri},
-  interchange::http::JobHandle,
+  authority::Scope,
+  bg::registry::Registry,
+  constants::JOB_MAX_WAIT,
+  http::{query_values, Uri},
+  interchange::http::{JobDetails, JobList},
   interchange::jobs::QueuedJob,
   Authority, Context, Response,
 };
 use log::debug;
 use std::io::Result;
+use std::time::Duration;
 
-fn with_access(auth: &Authority, job: QueuedJob) -> Option<QueuedJob> {
+fn with_access(registry: &Registry, auth: &Authority, job: QueuedJob) -> Option<QueuedJob> {
   match auth {
-    Authority::User { id, token: _ } => job.user().and_then(|job_user| {
+    Authority::User { id, .. } => registry.user(&job.job).and_then(|job_user| {
       if &job_user == id {
         debug!("job '{}' owned by '{}', we good", job.id, job_user);
         return Some(job);
@@ -22,18 +26,127 @@ fn with_access(auth: &Authority, job: QueuedJob) -> Option<QueuedJob> {
   }
 }
 
+// Jobs looked up by id are also shown to users related to them, e.g the players of a round that a
+// check ran for, which means asking the records.
+async fn readable(context: &Context, job: QueuedJob) -> Result<Option<QueuedJob>> {
+  let uid = match context.authority() {
+    Authority::User { id, .. } => id,
+    Authority::None => return Ok(None),
+  };
+
+  if context.registry().visible(&job.job, uid, context).await? {
+    return Ok(Some(job));
+  }
+
+  debug!("job '{}' not visible to '{}'", job.id, uid);
+  Ok(None)
+}
+
+// How long the client would like the request held until its jobs settle, if at all. Requests are
+// never held for longer than `JOB_MAX_WAIT` seconds.
+fn wait_for(uri: &Uri) -> Option<Duration> {
+  query_values(uri, "wait")
+    .into_iter()
+    .next()
+    .and_then(|seconds| seconds.parse::<u64>().ok())
+    .filter(|seconds| *seconds > 0)
+    .map(|seconds| Duration::from_secs(seconds.min(JOB_MAX_WAIT)))
+}
+
+fn details(context: &Context, job: QueuedJob) -> JobDetails {
+  let result = context.registry().handle(&job).result;
+  JobDetails::new(job, result)
+}
+
+// Jobs that do not exist, or that belong to someone else, are left out.
+async fn visible(context: &Context, ids: &[String]) -> Result<Vec<QueuedJob>> {
+  let mut jobs = Vec::with_capacity(ids.len());
+
+  for id in ids {
+    let job = match context.jobs().lookup(id).await? {
+      Some(job) => readable(context, job).await?,
+      None => None,
+    };
+
+    if let Some(job) = job {
+      jobs.push(job);
+    }
+  }
+
+  Ok(jobs)
+}
+
+// Holds the request until each of the jobs has settled, or the wait is over, then looks them up
+// again. Jobs are only waited on once the user is known to be allowed to see them.
+async fn settle(context: &Context, ids: &[String], wait: Duration) -> Result<Vec<QueuedJob>> {
+  let jobs = visible(context, ids).await?;
+  let pending = jobs
+    .iter()
+    .filter(|job| !job.status.is_settled())
+    .map(|job| job.id.clone())
+    .collect::<Vec<String>>();
+
+  if pending.is_empty() {
+    return Ok(jobs);
+  }
+
+  debug!("waiting up to {:?} on {} jobs", wait, pending.len());
+  context.jobs().wait(&pending, wait).await?;
+  visible(context, ids).await
+}
+
+// Only the jobs the store has indexed under the user are read; finished jobs are only kept around
+// for the retention period. Jobs that merely relate to the user are not listed, since checking
+// that would mean looking at everyone else's.
+async fn mine(context: &Context, uid: &str) -> Result<Response> {
+  let jobs = context
+    .jobs()
+    .owned(uid)
+    .await?
+    .into_iter()
+    .filter_map(|job| with_access(context.registry(), context.authority(), job))
+    .map(|job| details(context, job))
+    .collect();
+
+  Response::ok_json(JobList { jobs }).map(|r| r.cors(context.cors()))
+}
+
+async fn many(context: &Context, ids: &[String], wait: Option<Duration>) -> Result<Response> {
+  let jobs = match wait {
+    Some(wait) => settle(context, ids, wait).await?,
+    None => visible(context, ids).await?,
+  };
+
+  let jobs = jobs.into_iter().map(|job| details(context, job)).collect();
+  Response::ok_json(JobList { jobs }).map(|r| r.cors(context.cors()))
+}
+
+// Jobs are looked up one at a time with `id`, or several at once with `ids[]`, in which case the
+// jobs are returned as a list. Either may be held until the jobs settle with `wait=<seconds>`.
 pub async fn find(context: &Context, uri: &Uri) -> Result<Response> {
   let uid = match context.authority() {
-    Authority::User { id, token: _ } => id,
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::Read) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
     Authority::None => return Ok(Response::not_found().cors(context.cors())),
   };
 
   debug!("user '{}' is requesting access to job", uid);
 
-  let id = uri
-    .query()
-    .and_then(|q| qs::parse(q.as_bytes()).find(|(k, _k)| k == "id"))
-    .map(|(_k, v)| String::from(v.as_ref()));
+  if query_values(uri, "mine")
+    .iter()
+    .any(|value| value == "true")
+  {
+    return mine(context, uid).await;
+  }
+
+  let wait = wait_for(uri);
+  let ids = query_values(uri, "ids[]");
+
+  if !ids.is_empty() {
+    return many(context, &ids, wait).await;
+  }
+
+  let id = query_values(uri, "id").into_iter().next();
 
   match id {
     Some(id) => {
@@ -44,12 +157,21 @@ pub async fn find(context: &Context, uri: &Uri) -> Result<Response> {
         Some(job) => {
           debug!("job '{}' found, validing creator", job.id);
 
-          with_access(context.authority(), job)
-            .map(|job| {
-              debug!("user has access to job");
-              Response::ok_json(JobHandle::from(job)).map(|r| r.cors(context.cors()))
-            })
-            .unwrap_or(Ok(Response::not_found().cors(context.cors())))
+          let job = match readable(context, job).await? {
+            Some(job) => job,
+            None => return Ok(Response::not_found().cors(context.cors())),
+          };
+
+          debug!("user has access to job");
+          let job = match wait {
+            Some(wait) => settle(context, &[job.id], wait).await?.into_iter().next(),
+            None => Some(job),
+          };
+
+          match job {
+            Some(job) => Response::ok_json(details(context, job)).map(|r| r.cors(context.cors())),
+            None => Ok(Response::default().cors(context.cors())),
+          }
         }
         None => {
           debug!("job '{}' not found", id);
@@ -66,57 +188,80 @@ pub async fn find(context: &Context, uri: &Uri) -> Result<Response> {
 
 #[cfg(test)]
 mod test {
-  use super::with_access;
+  use super::{wait_for, with_access};
   use crate::{
+    authority::{Role, Scopes},
+    bg::registry::Registry,
+    constants::JOB_MAX_WAIT,
+    http::Uri,
     interchange::jobs::{CreateLobby, Job, QueuedJob},
     Authority,
   };
+  use std::time::Duration;
 
   #[test]
   fn auth_none() {
     let uid = String::from("s-123");
-    let job = QueuedJob {
-      id: String::from("s-job"),
-      job: Job::CreateLobby(CreateLobby {
+    let job = QueuedJob::new(
+      "s-job",
+      Job::CreateLobby(CreateLobby {
         creator: uid.clone(),
         result: None,
       }),
-    };
+    );
     let auth = Authority::None;
-    assert!(with_access(&auth, job).is_none());
+    assert!(with_access(&Registry::default(), &auth, job).is_none());
   }
 
   #[test]
   fn auth_user_without_access() {
     let uid = String::from("s-123");
-    let job = QueuedJob {
-      id: String::from("s-job"),
-      job: Job::CreateLobby(CreateLobby {
+    let job = QueuedJob::new(
+      "s-job",
+      Job::CreateLobby(CreateLobby {
         creator: format!("{}-456", uid.clone()),
         result: None,
       }),
-    };
+    );
     let auth = Authority::User {
       id: uid.clone(),
       token: String::from(""),
+      scopes: Scopes::all(),
+      role: Role::Player,
     };
-    assert!(with_access(&auth, job).is_none());
+    assert!(with_access(&Registry::default(), &auth, job).is_none());
   }
 
   #[test]
   fn auth_user_with_access() {
     let uid = String::from("s-123");
-    let job = QueuedJob {
-      id: String::from("s-job"),
-      job: Job::CreateLobby(CreateLobby {
+    let job = QueuedJob::new(
+      "s-job",
+      Job::CreateLobby(CreateLobby {
         creator: uid.clone(),
         result: None,
       }),
-    };
+    );
     let auth = Authority::User {
       id: uid.clone(),
       token: String::from(""),
+      scopes: Scopes::all(),
+      role: Role::Player,
     };
-    assert!(with_access(&auth, job).is_some());
+    assert!(with_access(&Registry::default(), &auth, job).is_some());
+  }
+
+  #[test]
+  fn wait_is_capped() {
+    let parse = |uri: &str| wait_for(&uri.parse::<Uri>().unwrap());
+
+    assert_eq!(parse("/jobs?id=s-job"), None);
+    assert_eq!(parse("/jobs?id=s-job&wait=0"), None);
+    assert_eq!(parse("/jobs?id=s-job&wait=soon"), None);
+    assert_eq!(parse("/jobs?id=s-job&wait=5"), Some(Duration::from_secs(5)));
+    assert_eq!(
+      parse("/jobs?ids[]=s-job&wait=3600"),
+      Some(Duration::from_secs(JOB_MAX_WAIT))
+    );
   }
 }
@@ -1,4 +1,3 @@
-use chrono::{DateTime, Utc};
 use std::io::Result;
 use std::marker::Unpin;
 
@@ -9,9 +8,10 @@ use serde_json::from_slice as deserialize;
 use sqlx::query_file;
 
 use crate::{
+  authority::Scope,
   errors,
   http::{query_values, Uri},
-  interchange, read_size_async, Authority, Context, Response,
+  interchange, policy, read_size_async, Authority, Context, Response,
 };
 
 #[derive(Deserialize, Debug)]
@@ -43,39 +43,6 @@ async fn load_games(context: &Context, id: &String) -> Result<Vec<interchange::h
     .collect()
 }
 
-struct LobbyDetailRow {
-  pub id: String,
-  pub name: String,
-  pub created: DateTime<Utc>,
-}
-
-async fn lobby_details_for_user(
-  context: &Context,
-  lobby_id: &String,
-  user_id: &String,
-) -> Result<Option<LobbyDetailRow>> {
-  let mut conn = context.records_connection().await?;
-  let details = query_file!(
-    "src/routes/lobbies/data-store/load-lobby-detail.sql",
-    lobby_id,
-    user_id
-  )
-  .fetch_all(&mut conn)
-  .await
-  .map_err(errors::humanize_error)?
-  .into_iter()
-  .nth(0)
-  .and_then(|row| {
-    Some(LobbyDetailRow {
-      id: row.lobby_id,
-      name: row.lobby_name,
-      created: row.created_at?,
-    })
-  });
-
-  Ok(details)
-}
-
 async fn load_members(
   context: &Context,
   id: &String,
@@ -101,21 +68,30 @@ async fn load_members(
 
 pub async fn details(context: &Context, id: &String) -> Result<Response> {
   let uid = match context.authority() {
-    Authority::User { id: s, token: _ } => s,
-    _ => return Ok(Response::unauthorized().cors(context.cors())),
+    Authority::User { id: s, scopes, .. } if scopes.allows(Scope::Read) => s,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
+    Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
   };
 
   debug!("looking for loby via '{}' for user '{}'", id, uid);
-  let deets = match lobby_details_for_user(context, id, uid).await? {
-    Some(details) => details,
-    None => return Ok(Response::not_found().cors(context.cors())),
+  let deets = match policy::view_lobby(context, uid, id).await? {
+    Ok(access) => access,
+    Err(denial) => {
+      debug!(
+        "user '{}' cant view lobby '{}' - {}",
+        uid,
+        id,
+        denial.reason()
+      );
+      return Ok(denial.response(context));
+    }
   };
 
   debug!("found lobby '{}' details, loading members", deets.name);
   let members = load_members(&context, &id).await?;
   let games = load_games(&context, &id).await?;
   let details = interchange::http::LobbyDetails {
-    id: deets.id,
+    id: deets.lobby_id,
     name: deets.name,
     members,
     games,
@@ -127,7 +103,8 @@ pub async fn details(context: &Context, id: &String) -> Result<Response> {
 // GET /lobbies
 pub async fn find(context: &Context, uri: &Uri) -> Result<Response> {
   let uid = match context.authority() {
-    Authority::User { id, .. } => id,
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::Read) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
     Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
   };
 
@@ -174,8 +151,9 @@ where
   R: Read + Unpin,
 {
   let uid = match context.authority() {
-    Authority::User { id: s, token: _ } => s,
-    _ => return Ok(Response::unauthorized().cors(context.cors())),
+    Authority::User { id: s, scopes, .. } if scopes.allows(Scope::LobbyPlay) => s,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
+    Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
   };
 
   // TODO - does this need to be something?
@@ -2,11 +2,15 @@ use async_std::io::Read as AsyncRead;
 use log::{debug, info, warn};
 use serde::Deserialize;
 use serde_json::from_slice as deserialize;
-use sqlx::query_file;
+use sqlx::postgres::PgConnection;
+use sqlx::{query_file, Connection};
 use std::io::Result;
 use std::marker::Unpin;
 
-use crate::{constants, errors, interchange, read_size_async, Authority, Context, Response};
+use crate::authority::Scope;
+use crate::{
+  constants, errors, interchange, jobs, policy, read_size_async, Authority, Context, Response,
+};
 
 const TOO_MANY_MEMBERS: &'static str = "errors.lobbies.too_many_members";
 
@@ -83,7 +87,8 @@ where
 {
   let uid = match context.authority() {
     Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
-    Authority::User { id, .. } => id,
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::LobbyPlay) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
   };
 
   let contents = read_size_async(reader, context.pending()).await?;
@@ -118,17 +123,16 @@ where
 }
 
 async fn leave_lobby(
-  context: &Context,
+  conn: &mut PgConnection,
   lobby_id: &String,
   user_id: &String,
 ) -> Result<(String, String)> {
-  let mut conn = context.records_connection().await?;
   query_file!(
     "src/routes/lobby_memberships/data-store/leave-lobby-for-user.sql",
     lobby_id,
     user_id
   )
-  .fetch_all(&mut conn)
+  .fetch_all(conn)
   .await
   .map_err(errors::humanize_error)?
   .into_iter()
@@ -145,7 +149,8 @@ where
 {
   let uid = match context.authority() {
     Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
-    Authority::User { id, .. } => id,
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::LobbyPlay) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
   };
 
   let contents = read_size_async(reader, context.pending()).await?;
@@ -156,7 +161,19 @@ where
     uid, payload.lobby_id
   );
 
-  let (member_id, lobby_id) = leave_lobby(context, &payload.lobby_id, &uid).await?;
+  if let Err(denial) = policy::leave_lobby(context, uid, &payload.lobby_id).await? {
+    warn!(
+      "user '{}' cant leave lobby '{}' - {}",
+      uid,
+      payload.lobby_id,
+      denial.reason()
+    );
+    return Ok(denial.response(context));
+  }
+
+  let mut conn = context.records_connection().await?;
+  let mut tx = conn.begin().await.map_err(errors::humanize_error)?;
+  let (member_id, lobby_id) = leave_lobby(&mut tx, &payload.lobby_id, &uid).await?;
 
   if member_id.len() == 0 {
     warn!(
@@ -173,10 +190,8 @@ where
     result: None,
   };
 
-  context
-    .jobs()
-    .queue(&interchange::jobs::Job::CleanupLobbyMembership(details))
-    .await?;
+  let job = interchange::jobs::Job::CleanupLobbyMembership(details);
+  jobs::commit_queued(context.jobs(), tx, &job).await?;
 
   Ok(Response::default().cors(context.cors()))
 }
@@ -2,36 +2,60 @@ use log::info;
 use sqlx::query_file;
 use std::io::Result;
 
+pub mod admin;
+pub mod api_tokens;
 pub mod games;
 pub mod jobs;
 pub mod lobbies;
 pub mod lobby_memberships;
 pub mod rounds;
+pub mod users;
 
+use crate::authority::Scope;
 use crate::http::{query as qs, Uri};
 use crate::interchange::http::{SessionData, SessionUserData};
-use crate::{errors, Authority, Context, Response};
+use crate::{cookies, errors, Authority, Context, Response};
 
+// Route
+// POST /auth/destroy
+//
+// Ending a session is a mutation like any other, so session cookies are only honoured alongside the
+// csrf header. Requests without any credential are turned away before any cookie is cleared.
 pub async fn destroy(context: &Context, uri: &Uri) -> Result<Response> {
   let token = match context.authority() {
-    Authority::User { id: _, token } => Some(token.clone()),
+    Authority::User { token, .. } => Some(token.clone()),
 
     Authority::None => uri
       .query()
       .and_then(|q| qs::parse(q.as_bytes()).find(|(k, _k)| k == "token"))
       .map(|(_k, v)| String::from(v.as_ref())),
   }
-  .unwrap_or_default();
+  .filter(|token| !token.is_empty());
+
+  let token = match token {
+    Some(token) => token,
+    None => return Ok(Response::unauthorized().cors(context.cors())),
+  };
 
   info!("destroying session from token: {}", token);
   context.session().destroy(&token).await?;
 
-  Ok(Response::redirect(&context.config().krumi.auth_uri))
+  let cookie = &context.config().session_store.cookie;
+  let cleared = match cookie.enabled {
+    true => cookies::clear(cookie),
+    false => Vec::new(),
+  };
+
+  Ok(cleared.into_iter().fold(
+    Response::redirect(&context.config().krumi.auth_uri),
+    |response, value| response.cookie(value),
+  ))
 }
 
 pub async fn identify(context: &Context) -> Result<Response> {
   let uid = match context.authority() {
-    Authority::User { id, token: _ } => id,
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::Read) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
     Authority::None => return Ok(Response::not_found().cors(context.cors())),
   };
 
@@ -54,3 +78,20 @@ pub async fn identify(context: &Context) -> Result<Response> {
     .ok_or_else(|| errors::e("Not found"))
     .and_then(|tenant| Response::ok_json(&tenant).map(|r| r.cors(context.cors())))
 }
+
+#[cfg(test)]
+mod test {
+  use super::destroy;
+  use crate::context::test_helpers::with_auth;
+  use crate::http::{StatusCode, Uri};
+  use crate::Authority;
+  use async_std::task::block_on;
+
+  #[test]
+  fn destroy_requires_credentials() {
+    let context = with_auth(Authority::None);
+    let uri = "/auth/destroy".parse::<Uri>().unwrap();
+    let response = block_on(destroy(&context, &uri)).unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+  }
+}
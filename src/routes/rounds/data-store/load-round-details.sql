@@ -8,11 +8,5 @@ select
   rounds.started_at   as started_at
 from
   krumnet.game_rounds as rounds
-right join
-  krumnet.game_memberships as members
-on
-  members.game_id = rounds.game_id
 where
-  rounds.id = $2
-and
-  members.user_id = $1;
+  rounds.id = $1;
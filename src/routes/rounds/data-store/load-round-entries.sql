@@ -5,6 +5,7 @@ select
   entries.created_at  as created_at,
   entries.user_id     as user_id,
   users.name          as user_name,
+  users.avatar_url    as user_avatar,
   entries.entry       as entry,
   rounds.fulfilled_at as fulfilled
 from
@@ -2,6 +2,7 @@ select
   results.id      as result_id,
   results.user_id as user_id,
   users.name      as user_name,
+  users.avatar_url as user_avatar,
   results.place   as round_place
 from
   krumnet.game_member_round_placement_results as results
@@ -3,8 +3,13 @@ select
   votes.entry_id   as entry_id,
   votes.member_id  as member_id,
   votes.user_id    as user_id,
+  users.avatar_url as user_avatar,
   votes.created_at as created
 from
   krumnet.game_round_entry_votes as votes
+left join
+  krumnet.users as users
+on
+  users.id = votes.user_id
 where
   votes.round_id = $1;
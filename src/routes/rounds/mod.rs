@@ -4,9 +4,10 @@ use sqlx::{query_file, query_file_as, FromRow};
 use std::io::{Error, Result};
 
 use crate::{
+  authority::Scope,
   errors,
   http::{query_values, Uri},
-  interchange, Authority, Context, Response,
+  interchange, policy, Authority, Context, Response,
 };
 
 fn log_err<E: std::error::Error>(error: E) -> Error {
@@ -25,16 +26,11 @@ struct RoundDetailRow {
   fulfilled_at: Option<DateTime<Utc>>,
 }
 
-async fn round_details(
-  context: &Context,
-  user_id: &String,
-  round_id: &String,
-) -> Result<RoundDetailRow> {
+async fn round_details(context: &Context, round_id: &String) -> Result<RoundDetailRow> {
   let mut conn = context.records_connection().await?;
   query_file_as!(
     RoundDetailRow,
     "src/routes/rounds/data-store/load-round-details.sql",
-    user_id,
     round_id
   )
   .fetch_all(&mut conn)
@@ -47,8 +43,9 @@ async fn round_details(
 
 pub async fn find(context: &Context, uri: &Uri) -> Result<Response> {
   let uid = match context.authority() {
-    Authority::User { id, .. } => id,
-    Authority::None => return Ok(Response::not_found().cors(context.cors())),
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::Read) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
+    Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
   };
 
   let ids = query_values(uri, "ids[]");
@@ -60,6 +57,17 @@ pub async fn find(context: &Context, uri: &Uri) -> Result<Response> {
 
   let rid = ids.iter().nth(0).ok_or(errors::e("invalid id"))?;
   debug!("attempting to find round from single id - {:?}", rid);
+
+  if let Err(denial) = policy::view_round(context, uid, rid).await? {
+    debug!(
+      "user '{}' cant view round '{}' - {}",
+      uid,
+      rid,
+      denial.reason()
+    );
+    return Ok(denial.response(context));
+  }
+
   let RoundDetailRow {
     round_id: id,
     prompt,
@@ -68,7 +76,7 @@ pub async fn find(context: &Context, uri: &Uri) -> Result<Response> {
     fulfilled_at: fulfilled,
     completed_at: completed,
     started_at: started,
-  } = round_details(context, &uid, &rid).await?;
+  } = round_details(context, &rid).await?;
 
   debug!("found round row '{}', parsing into response", id);
   let entries = entries_for_round(context, &uid, &id).await?;
@@ -126,6 +134,7 @@ async fn results_for_round(
       id: row.result_id,
       user_name: row.user_name,
       user_id: row.user_id,
+      user_avatar: row.user_avatar,
       place: row.round_place,
     })
   })
@@ -162,6 +171,7 @@ async fn entries_for_round(
         .ok_or_else(|| errors::e("Unable to load round entry created timestamp"))?,
       user_id: row.user_id,
       user_name: row.user_name,
+      user_avatar: row.user_avatar,
       entry,
     })
   })
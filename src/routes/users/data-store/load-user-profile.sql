@@ -0,0 +1,12 @@
+select
+  users.id           as id,
+  users.name         as name,
+  users.display_name as display_name,
+  users.avatar_url   as avatar_url,
+  users.pronouns     as pronouns,
+  users.timezone     as timezone
+from
+  krumnet.users as users
+where
+  users.id = $1
+limit 1;
@@ -0,0 +1,16 @@
+update
+  krumnet.users as users
+set
+  display_name = case when $2::varchar is null then users.display_name else nullif($2, '') end,
+  avatar_url = case when $3::varchar is null then users.avatar_url else nullif($3, '') end,
+  pronouns = case when $4::varchar is null then users.pronouns else nullif($4, '') end,
+  timezone = case when $5::varchar is null then users.timezone else nullif($5, '') end
+where
+  users.id = $1
+returning
+  users.id           as id,
+  users.name         as name,
+  users.display_name as display_name,
+  users.avatar_url   as avatar_url,
+  users.pronouns     as pronouns,
+  users.timezone     as timezone;
@@ -0,0 +1,293 @@
+use async_std::io::Read as AsyncRead;
+use chrono_tz::Tz;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::from_slice as deserialize;
+use sqlx::query_file;
+use std::io::Result;
+use std::marker::Unpin;
+
+use crate::authority::Scope;
+use crate::constants::{
+  PROFILE_AVATAR_URL_MAX_LENGTH, PROFILE_DISPLAY_NAME_MAX_LENGTH, PROFILE_DISPLAY_NAME_MIN_LENGTH,
+  PROFILE_PRONOUNS_MAX_LENGTH,
+};
+use crate::http::Url;
+use crate::{errors, interchange, read_size_async, Authority, Context, Response};
+
+const INVALID_DISPLAY_NAME: &'static str = "errors.users.invalid_display_name";
+const DISPLAY_NAME_TAKEN: &'static str = "errors.users.display_name_taken";
+const INVALID_AVATAR_URL: &'static str = "errors.users.invalid_avatar_url";
+const INVALID_PRONOUNS: &'static str = "errors.users.invalid_pronouns";
+const INVALID_TIMEZONE: &'static str = "errors.users.invalid_timezone";
+
+const UNIQUE_VIOLATION: &'static str = "23505";
+
+// Fields omitted from the payload are left untouched, while empty strings clear the field.
+#[derive(Debug, Default, Deserialize)]
+struct UpdatePayload {
+  display_name: Option<String>,
+  avatar_url: Option<String>,
+  pronouns: Option<String>,
+  timezone: Option<String>,
+}
+
+fn valid_display_name(name: &str) -> bool {
+  let length = name.chars().count();
+
+  if !(PROFILE_DISPLAY_NAME_MIN_LENGTH..=PROFILE_DISPLAY_NAME_MAX_LENGTH).contains(&length) {
+    return false;
+  }
+
+  name.trim() == name
+    && name
+      .chars()
+      .all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_')
+}
+
+fn valid_avatar_url(url: &str) -> bool {
+  url.len() <= PROFILE_AVATAR_URL_MAX_LENGTH
+    && Url::parse(url)
+      .map(|parsed| parsed.scheme() == "https")
+      .unwrap_or(false)
+}
+
+fn provided(value: &Option<String>) -> Option<&str> {
+  value.as_deref().filter(|v| !v.is_empty())
+}
+
+// Validates the requested profile changes, returning the reason the request should be rejected if
+// any. Empty values are always accepted since they clear the field.
+fn validate(payload: &UpdatePayload) -> Option<&'static str> {
+  if let Some(name) = provided(&payload.display_name) {
+    if !valid_display_name(name) {
+      return Some(INVALID_DISPLAY_NAME);
+    }
+  }
+
+  if let Some(url) = provided(&payload.avatar_url) {
+    if !valid_avatar_url(url) {
+      return Some(INVALID_AVATAR_URL);
+    }
+  }
+
+  if let Some(pronouns) = provided(&payload.pronouns) {
+    if pronouns.chars().count() > PROFILE_PRONOUNS_MAX_LENGTH {
+      return Some(INVALID_PRONOUNS);
+    }
+  }
+
+  if let Some(timezone) = provided(&payload.timezone) {
+    if timezone.parse::<Tz>().is_err() {
+      return Some(INVALID_TIMEZONE);
+    }
+  }
+
+  None
+}
+
+fn is_unique_violation(error: &sqlx::Error) -> bool {
+  match error {
+    sqlx::Error::Database(inner) => inner.code().map(|c| c == UNIQUE_VIOLATION).unwrap_or(false),
+    _ => false,
+  }
+}
+
+// Route
+// GET /users/:id
+pub async fn find(context: &Context, id: &str) -> Result<Response> {
+  let uid = match context.authority() {
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::Read) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
+    Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
+  };
+
+  let target = if id == "me" { uid.as_str() } else { id };
+  debug!("user '{}' loading profile for '{}'", uid, target);
+
+  let mut conn = context.records_connection().await?;
+  let profile = query_file!("src/routes/users/data-store/load-user-profile.sql", target)
+    .fetch_all(&mut conn)
+    .await
+    .map_err(errors::humanize_error)?
+    .into_iter()
+    .nth(0)
+    .map(|row| interchange::http::UserProfile {
+      id: row.id,
+      name: row.name,
+      display_name: row.display_name,
+      avatar_url: row.avatar_url,
+      pronouns: row.pronouns,
+      timezone: row.timezone,
+    });
+
+  match profile {
+    Some(profile) => Response::ok_json(profile).map(|r| r.cors(context.cors())),
+    None => Ok(Response::not_found().cors(context.cors())),
+  }
+}
+
+// Route
+// PATCH /users/me
+pub async fn update<R>(context: &Context, reader: &mut R) -> Result<Response>
+where
+  R: AsyncRead + Unpin,
+{
+  let uid = match context.authority() {
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::Account) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
+    Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
+  };
+
+  let contents = read_size_async(reader, context.pending()).await?;
+  let payload = deserialize::<UpdatePayload>(&contents)?;
+
+  if let Some(reason) = validate(&payload) {
+    warn!("rejecting profile update for user '{}' - {}", uid, reason);
+    return Ok(Response::bad_request(reason).cors(context.cors()));
+  }
+
+  let mut conn = context.records_connection().await?;
+  let updated = query_file!(
+    "src/routes/users/data-store/update-user-profile.sql",
+    uid,
+    payload.display_name,
+    payload.avatar_url,
+    payload.pronouns,
+    payload.timezone
+  )
+  .fetch_all(&mut conn)
+  .await;
+
+  let profile = match updated {
+    Ok(rows) => rows
+      .into_iter()
+      .nth(0)
+      .map(|row| interchange::http::UserProfile {
+        id: row.id,
+        name: row.name,
+        display_name: row.display_name,
+        avatar_url: row.avatar_url,
+        pronouns: row.pronouns,
+        timezone: row.timezone,
+      })
+      .ok_or_else(|| errors::e(format!("Unable to find user '{}'", uid)))?,
+    Err(e) if is_unique_violation(&e) => {
+      warn!("display name already taken for user '{}'", uid);
+      return Ok(Response::bad_request(DISPLAY_NAME_TAKEN).cors(context.cors()));
+    }
+    Err(e) => return Err(errors::humanize_error(e)),
+  };
+
+  info!("updated profile for user '{}'", uid);
+  Response::ok_json(profile).map(|r| r.cors(context.cors()))
+}
+
+async fn queue_for_user(context: &Context, job: interchange::jobs::Job) -> Result<Response> {
+  let id = context.jobs().queue(&job).await?;
+
+  Response::ok_json(interchange::http::JobHandle { id, result: None })
+    .map(|r| r.cors(context.cors()))
+}
+
+// Route
+// GET /users/me/export
+pub async fn export(context: &Context) -> Result<Response> {
+  let uid = match context.authority() {
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::Account) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
+    Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
+  };
+
+  info!("queuing data export for user '{}'", uid);
+  let job = interchange::jobs::Job::ExportUserData(interchange::jobs::ExportUserData {
+    user_id: uid.clone(),
+    result: None,
+  });
+  queue_for_user(context, job).await
+}
+
+// Route
+// DELETE /users/me
+pub async fn destroy(context: &Context) -> Result<Response> {
+  let uid = match context.authority() {
+    Authority::User { id, scopes, .. } if scopes.allows(Scope::Account) => id,
+    Authority::User { .. } => return Ok(Response::forbidden().cors(context.cors())),
+    Authority::None => return Ok(Response::unauthorized().cors(context.cors())),
+  };
+
+  info!("queuing account deletion for user '{}'", uid);
+  let job = interchange::jobs::Job::DeleteUser(interchange::jobs::DeleteUser {
+    user_id: uid.clone(),
+    result: None,
+  });
+  queue_for_user(context, job).await
+}
+
+#[cfg(test)]
+mod test {
+  use super::{
+    validate, UpdatePayload, INVALID_AVATAR_URL, INVALID_DISPLAY_NAME, INVALID_PRONOUNS,
+    INVALID_TIMEZONE,
+  };
+
+  #[test]
+  fn valid_payload() {
+    let payload = UpdatePayload {
+      display_name: Some(String::from("danny h")),
+      avatar_url: Some(String::from("https://example.com/me.png")),
+      pronouns: Some(String::from("they/them")),
+      timezone: Some(String::from("America/New_York")),
+    };
+    assert_eq!(validate(&payload), None);
+  }
+
+  #[test]
+  fn empty_values_clear() {
+    let payload = UpdatePayload {
+      display_name: Some(String::new()),
+      avatar_url: Some(String::new()),
+      pronouns: Some(String::new()),
+      timezone: Some(String::new()),
+    };
+    assert_eq!(validate(&payload), None);
+  }
+
+  #[test]
+  fn invalid_display_name() {
+    for name in &["a", " padded ", "<script>", &"a".repeat(33)] {
+      let payload = UpdatePayload {
+        display_name: Some(String::from(*name)),
+        ..UpdatePayload::default()
+      };
+      assert_eq!(validate(&payload), Some(INVALID_DISPLAY_NAME));
+    }
+  }
+
+  #[test]
+  fn invalid_avatar_url() {
+    let payload = UpdatePayload {
+      avatar_url: Some(String::from("http://example.com/me.png")),
+      ..UpdatePayload::default()
+    };
+    assert_eq!(validate(&payload), Some(INVALID_AVATAR_URL));
+  }
+
+  #[test]
+  fn invalid_pronouns() {
+    let payload = UpdatePayload {
+      pronouns: Some("x".repeat(33)),
+      ..UpdatePayload::default()
+    };
+    assert_eq!(validate(&payload), Some(INVALID_PRONOUNS));
+  }
+
+  #[test]
+  fn invalid_timezone() {
+    let payload = UpdatePayload {
+      timezone: Some(String::from("Mars/Olympus_Mons")),
+      ..UpdatePayload::default()
+    };
+    assert_eq!(validate(&payload), Some(INVALID_TIMEZONE));
+  }
+}
@@ -0,0 +1,56 @@
+// Behavior expected of every session store, run against each implementation.
+use async_std::task::block_on;
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::{MemorySessionStore, RedisSessionStore, SessionStore};
+use crate::context::test_helpers::load_config;
+
+async fn sessions(store: &dyn SessionStore) {
+  let user = Uuid::new_v4().to_string();
+  let token = store.create(&user).await.unwrap();
+  assert_eq!(store.get(&token).await.unwrap(), user);
+
+  let other = store.create(&user).await.unwrap();
+  assert_ne!(token, other);
+
+  store.destroy(&token).await.unwrap();
+  assert!(store.get(&token).await.is_err());
+  assert_eq!(store.get(&other).await.unwrap(), user);
+
+  store.destroy(&token).await.unwrap();
+  store.destroy(&other).await.unwrap();
+  assert!(store.get("not-a-session").await.is_err());
+}
+
+async fn stash(store: &dyn SessionStore) {
+  let key = Uuid::new_v4().to_string();
+  let ttl = Duration::from_secs(60);
+
+  assert_eq!(store.claim(&key).await.unwrap(), None);
+  store.stash(&key, "carried", ttl).await.unwrap();
+  assert_eq!(
+    store.claim(&key).await.unwrap(),
+    Some(String::from("carried"))
+  );
+  assert_eq!(store.claim(&key).await.unwrap(), None);
+}
+
+async fn conforms(store: &dyn SessionStore) {
+  sessions(store).await;
+  stash(store).await;
+  assert!(store.health().await.healthy);
+}
+
+#[test]
+fn memory() {
+  let config = load_config().unwrap();
+  block_on(conforms(&MemorySessionStore::new(&config)));
+}
+
+#[test]
+fn redis() {
+  let config = load_config().unwrap();
+  let store = block_on(RedisSessionStore::open(&config)).unwrap();
+  block_on(conforms(&store));
+}
@@ -0,0 +1,89 @@
+use async_std::sync::Mutex;
+use async_trait::async_trait;
+use jsonwebtoken::EncodingKey;
+use std::collections::HashMap;
+use std::io::Result;
+use std::time::{Duration, Instant};
+
+use super::{expiration, issue, SessionStore};
+use crate::configuration::Configuration;
+use crate::errors;
+use crate::redis::Health;
+
+struct Entry {
+  value: String,
+  expires: Option<Instant>,
+}
+
+impl Entry {
+  fn new(value: &str, ttl: Option<Duration>) -> Self {
+    Entry {
+      value: String::from(value),
+      expires: ttl.map(|ttl| Instant::now() + ttl),
+    }
+  }
+
+  fn live(&self) -> bool {
+    self.expires.map(|at| Instant::now() < at).unwrap_or(true)
+  }
+}
+
+// Keeps sessions in process memory. Nothing is shared between processes or survives a restart, so
+// this is only suitable for tests and single-process local development.
+pub struct MemorySessionStore {
+  _encoding_key: EncodingKey,
+  _expiration_timeout: Option<Duration>,
+  _sessions: Mutex<HashMap<String, Entry>>,
+  _stash: Mutex<HashMap<String, Entry>>,
+}
+
+impl MemorySessionStore {
+  pub fn new(configuration: &Configuration) -> Self {
+    MemorySessionStore {
+      _encoding_key: EncodingKey::from_secret(configuration.session_store.secret.as_bytes()),
+      _expiration_timeout: expiration(configuration),
+      _sessions: Mutex::new(HashMap::new()),
+      _stash: Mutex::new(HashMap::new()),
+    }
+  }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+  async fn create(&self, id: &str) -> Result<String> {
+    let token = issue(&self._encoding_key, id)?;
+    let entry = Entry::new(id, self._expiration_timeout);
+    self._sessions.lock().await.insert(token.clone(), entry);
+    Ok(token)
+  }
+
+  async fn get(&self, token: &str) -> Result<String> {
+    match self._sessions.lock().await.get(token) {
+      Some(entry) if entry.live() => Ok(entry.value.clone()),
+      _ => Err(errors::e(format!(
+        "Unable to find user for token '{}'",
+        token
+      ))),
+    }
+  }
+
+  async fn destroy(&self, token: &str) -> Result<()> {
+    self._sessions.lock().await.remove(token);
+    Ok(())
+  }
+
+  async fn stash(&self, key: &str, value: &str, ttl: Duration) -> Result<()> {
+    let entry = Entry::new(value, Some(ttl));
+    self._stash.lock().await.insert(String::from(key), entry);
+    Ok(())
+  }
+
+  async fn claim(&self, key: &str) -> Result<Option<String>> {
+    let entry = self._stash.lock().await.remove(key);
+    Ok(entry.filter(Entry::live).map(|entry| entry.value))
+  }
+
+  async fn health(&self) -> Health {
+    Health::default()
+  }
+}
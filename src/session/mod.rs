@@ -0,0 +1,87 @@
+use async_trait::async_trait;
+use hmac::{Hmac, Mac, NewMac};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::io::Result;
+use std::time::{Duration, SystemTime};
+use subtle::ConstantTimeEq;
+
+use crate::configuration::Configuration;
+use crate::errors;
+use crate::redis::Health;
+
+pub mod memory;
+pub mod redis;
+
+#[cfg(test)]
+mod conformance;
+
+pub use memory::MemorySessionStore;
+pub use redis::RedisSessionStore;
+
+// Sessions map an opaque token handed to the client back to the id of the user it was created for.
+// The store is also used to hold short-lived values that need to survive a redirect.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+  async fn create(&self, id: &str) -> Result<String>;
+
+  async fn get(&self, token: &str) -> Result<String>;
+
+  async fn destroy(&self, token: &str) -> Result<()>;
+
+  // Stores a short-lived value alongside sessions, used to carry state across an oauth redirect.
+  async fn stash(&self, key: &str, value: &str, ttl: Duration) -> Result<()>;
+
+  // Removes a value previously stored with `stash`, returning it if it had not yet expired.
+  async fn claim(&self, key: &str) -> Result<Option<String>>;
+
+  async fn health(&self) -> Health;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionClaims {
+  uid: String,
+  created: SystemTime,
+}
+
+// Session tokens are signed with the configured secret, regardless of where they are stored.
+fn issue(key: &EncodingKey, id: &str) -> Result<String> {
+  let claims = SessionClaims {
+    uid: String::from(id),
+    created: SystemTime::now(),
+  };
+
+  encode(&Header::default(), &claims, key).map_err(errors::humanize_error)
+}
+
+fn expiration(configuration: &Configuration) -> Option<Duration> {
+  configuration
+    .session_store
+    .expiration_timeout
+    .map(Duration::from_secs)
+}
+
+// The csrf token for a session is an hmac of the session token keyed by the session secret, so
+// nothing needs to be stored; only someone able to read the csrf cookie can produce it.
+pub fn csrf_token(configuration: &Configuration, token: &str) -> Result<String> {
+  let secret = configuration.session_store.secret.as_bytes();
+  let mut mac =
+    Hmac::<Sha256>::new_varkey(secret).map_err(|_| errors::e("invalid session secret"))?;
+  mac.update(token.as_bytes());
+
+  Ok(
+    mac
+      .finalize()
+      .into_bytes()
+      .iter()
+      .map(|byte| format!("{:02x}", byte))
+      .collect(),
+  )
+}
+
+// Compares the csrf token sent with a request against the session's in constant time.
+pub fn csrf_matches(configuration: &Configuration, token: &str, candidate: &str) -> Result<bool> {
+  let expected = csrf_token(configuration, token)?;
+  Ok(expected.as_bytes().ct_eq(candidate.as_bytes()).into())
+}
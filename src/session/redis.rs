@@ -0,0 +1,141 @@
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+
+use async_std::sync::Arc;
+use async_trait::async_trait;
+
+use jsonwebtoken::EncodingKey;
+use kramer::{Arity, Command, Insertion, StringCommand};
+use log::{info, trace, warn};
+
+use super::{expiration, issue, SessionStore};
+use crate::configuration::Configuration;
+use crate::redis::{Health, Redis};
+
+fn lookup_command<S: std::fmt::Display>(prefix: S, key: &str) -> StringCommand<String, String> {
+  StringCommand::Get::<_, String>(Arity::One(format!("{}:{}", prefix, key)))
+}
+
+fn destroy_command<S: std::fmt::Display>(prefix: S, key: &str) -> Command<String, String> {
+  Command::Del::<_, String>(Arity::One(format!("{}:{}", prefix, key)))
+}
+
+pub struct RedisSessionStore {
+  _redis: Arc<Redis>,
+  _encoding_key: EncodingKey,
+  _session_prefix: String,
+  _expiration_timeout: Option<Duration>,
+}
+
+impl RedisSessionStore {
+  pub async fn open<C>(configuration: C) -> Result<Self>
+  where
+    C: std::ops::Deref<Target = Configuration>,
+  {
+    let redis = Redis::open(
+      configuration.session_store.redis_uri.as_str(),
+      &configuration.redis,
+    )
+    .await?;
+
+    Ok(RedisSessionStore::with_redis(
+      &configuration,
+      Arc::new(redis),
+    ))
+  }
+
+  // Creates a session store on top of an existing redis pool, allowing it to be shared.
+  pub fn with_redis(configuration: &Configuration, redis: Arc<Redis>) -> Self {
+    info!(
+      "session store ready with secret: {}",
+      configuration.session_store.secret
+    );
+    let key = EncodingKey::from_secret(configuration.session_store.secret.as_bytes());
+
+    RedisSessionStore {
+      _redis: redis,
+      _session_prefix: configuration.session_store.session_prefix.clone(),
+      _expiration_timeout: expiration(configuration),
+      _encoding_key: key,
+    }
+  }
+}
+
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+  async fn health(&self) -> Health {
+    self._redis.health().await
+  }
+
+  async fn destroy(&self, key: &str) -> Result<()> {
+    info!("removing key {}", key);
+    let des = destroy_command(&self._session_prefix, key);
+    match self._redis.execute(des).await? {
+      kramer::Response::Item(kramer::ResponseValue::Integer(1)) => Ok(()),
+      kramer::Response::Item(kramer::ResponseValue::Integer(0)) => {
+        info!("unable to find session");
+        return Ok(());
+      }
+      _ => Err(Error::new(
+        ErrorKind::Other,
+        format!("Unable to find key '{}' for deletion", key),
+      )),
+    }
+  }
+
+  async fn get(&self, key: &str) -> Result<String> {
+    let lookup = lookup_command(&self._session_prefix, key);
+    trace!("writing command {} to redis connection", lookup);
+
+    match self._redis.execute(lookup).await? {
+      kramer::Response::Item(kramer::ResponseValue::String(id)) => Ok(id),
+      r => {
+        warn!("strange response from session lookup - {:?}", r);
+        Err(Error::new(
+          ErrorKind::Other,
+          format!("Unable to find user for token '{}'", key),
+        ))
+      }
+    }
+  }
+
+  async fn stash(&self, key: &str, value: &str, ttl: Duration) -> Result<()> {
+    let key = format!("{}:state:{}", self._session_prefix, key);
+    let insert = StringCommand::Set(Arity::One((&key, value)), Some(ttl), Insertion::Always);
+    self._redis.execute(insert).await?;
+    Ok(())
+  }
+
+  async fn claim(&self, key: &str) -> Result<Option<String>> {
+    let key = format!("{}:state:{}", self._session_prefix, key);
+    let lookup = StringCommand::Get::<_, String>(Arity::One(key.clone()));
+
+    let value = match self._redis.execute(lookup).await? {
+      kramer::Response::Item(kramer::ResponseValue::String(value)) => Some(value),
+      _ => None,
+    };
+
+    self
+      ._redis
+      .execute(Command::Del::<_, String>(Arity::One(key)))
+      .await?;
+    Ok(value)
+  }
+
+  async fn create(&self, id: &str) -> Result<String> {
+    let token = issue(&self._encoding_key, id)?;
+
+    let key = format!("{}:{}", self._session_prefix, token);
+    let insert = StringCommand::Set(
+      Arity::One((&key, id)),
+      self._expiration_timeout,
+      Insertion::Always,
+    );
+    self._redis.execute(insert).await?;
+    info!(
+      "creating session for user id: {} (timeout: {:?})",
+      id, self._expiration_timeout
+    );
+    Ok(token)
+  }
+}